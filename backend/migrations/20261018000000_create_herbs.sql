@@ -0,0 +1,14 @@
+-- Herb documents for the SQL store. The full herb is kept as JSON in `doc`; the columns
+-- next to it are copies of the fields the store filters on.
+CREATE TABLE IF NOT EXISTS herbs (
+    id TEXT PRIMARY KEY,
+    -- CouchDB-style revision ("<generation>-<hash>"); updates and deletes must name it
+    rev TEXT NOT NULL,
+    farmer TEXT NOT NULL,
+    gtin TEXT,
+    lot TEXT,
+    doc TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS herbs_farmer ON herbs (farmer);
+CREATE INDEX IF NOT EXISTS herbs_gtin_lot ON herbs (gtin, lot);
@@ -0,0 +1,27 @@
+-- Companion documents for the SQL store (lab results, specs, scans, alerts), one table for all
+-- collections. As for herbs the document is kept as JSON in `doc`; herb_id, status and the
+-- collection's timestamp (fixed-width RFC 3339, so it sorts as text) are copied out for filtering.
+CREATE TABLE IF NOT EXISTS documents (
+    collection TEXT NOT NULL,
+    id TEXT NOT NULL,
+    rev TEXT NOT NULL,
+    herb_id TEXT,
+    status TEXT,
+    at TEXT,
+    doc TEXT NOT NULL,
+    PRIMARY KEY (collection, id)
+);
+
+CREATE INDEX IF NOT EXISTS documents_herb_at ON documents (collection, herb_id, at);
+CREATE INDEX IF NOT EXISTS documents_at ON documents (collection, at);
+CREATE INDEX IF NOT EXISTS documents_status ON documents (collection, status);
+
+-- Attachment files of each herb. BYTEA is PostgreSQL's binary type; SQLite stores the bytes as a
+-- blob whatever the declared type.
+CREATE TABLE IF NOT EXISTS files (
+    herb_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    data BYTEA NOT NULL,
+    PRIMARY KEY (herb_id, name)
+);
@@ -0,0 +1,48 @@
+-- Every row belongs to a tenant (see tenants.rs); '' is the default tenant. Primary keys gain the
+-- tenant column, which neither SQLite nor PostgreSQL can add in place, so each table is rebuilt.
+CREATE TABLE herbs_scoped (
+    tenant TEXT NOT NULL DEFAULT '',
+    id TEXT NOT NULL,
+    rev TEXT NOT NULL,
+    farmer TEXT NOT NULL,
+    gtin TEXT,
+    lot TEXT,
+    doc TEXT NOT NULL,
+    PRIMARY KEY (tenant, id)
+);
+INSERT INTO herbs_scoped (tenant, id, rev, farmer, gtin, lot, doc) SELECT '', id, rev, farmer, gtin, lot, doc FROM herbs;
+DROP TABLE herbs;
+ALTER TABLE herbs_scoped RENAME TO herbs;
+CREATE INDEX herbs_farmer ON herbs (tenant, farmer);
+CREATE INDEX herbs_gtin_lot ON herbs (tenant, gtin, lot);
+
+CREATE TABLE documents_scoped (
+    tenant TEXT NOT NULL DEFAULT '',
+    collection TEXT NOT NULL,
+    id TEXT NOT NULL,
+    rev TEXT NOT NULL,
+    herb_id TEXT,
+    status TEXT,
+    at TEXT,
+    doc TEXT NOT NULL,
+    PRIMARY KEY (tenant, collection, id)
+);
+INSERT INTO documents_scoped (tenant, collection, id, rev, herb_id, status, at, doc)
+    SELECT '', collection, id, rev, herb_id, status, at, doc FROM documents;
+DROP TABLE documents;
+ALTER TABLE documents_scoped RENAME TO documents;
+CREATE INDEX documents_herb_at ON documents (tenant, collection, herb_id, at);
+CREATE INDEX documents_at ON documents (tenant, collection, at);
+CREATE INDEX documents_status ON documents (tenant, collection, status);
+
+CREATE TABLE files_scoped (
+    tenant TEXT NOT NULL DEFAULT '',
+    herb_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    data BYTEA NOT NULL,
+    PRIMARY KEY (tenant, herb_id, name)
+);
+INSERT INTO files_scoped (tenant, herb_id, name, content_type, data) SELECT '', herb_id, name, content_type, data FROM files;
+DROP TABLE files;
+ALTER TABLE files_scoped RENAME TO files;
@@ -0,0 +1,36 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+
+// Token the /admin routes expect as "Authorization: Bearer <token>"; None disables them
+pub type AdminToken = Option<Arc<str>>;
+
+// Compares without stopping at the first differing byte, so timing does not leak the token
+fn same_token(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given.bytes().zip(expected.bytes()).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+// Whether the request carries the admin bearer token; never when the admin API is disabled
+pub fn is_admin(headers: &HeaderMap, token: Option<&str>) -> bool {
+    let given = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    matches!((given, token), (Some(given), Some(expected)) if same_token(given, expected))
+}
+
+pub async fn require_admin(State(token): State<AdminToken>, request: Request, next: Next) -> Response {
+    if token.is_none() {
+        return (StatusCode::SERVICE_UNAVAILABLE, "Admin API is disabled; set ADMIN_TOKEN to enable it").into_response();
+    }
+    if is_admin(request.headers(), token.as_deref()) {
+        next.run(request).await
+    } else {
+        (StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, "Bearer")], "Admin token required").into_response()
+    }
+}
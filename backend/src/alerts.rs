@@ -0,0 +1,407 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::{ToSchema, IntoParams};
+use crate::analytics::ScanEvent;
+use crate::docs::{Collection, DocQuery};
+use crate::handlers::{AppState, Herb};
+use crate::store::StoreError;
+
+// A unit may legitimately be scanned a few times (shop shelf, checkout, at home)
+const SCANS_PER_UNIT_ALLOWANCE: u32 = 3;
+// Faster than a commercial flight between two scans means two physical copies of the code
+const MAX_PLAUSIBLE_SPEED_KMH: f64 = 900.0;
+// Scan coordinates are rounded to 0.1 degree (see analytics.rs), so two scans from the same place
+// can appear up to ~16 km apart; shorter hops are never travel
+const MIN_TRAVEL_KM: f64 = 50.0;
+// Speeds are measured over at least this long, so clock skew and back-to-back scans do not
+// turn a short hop into an impossible one
+const MIN_TRAVEL_WINDOW_MINUTES: i64 = 15;
+// Attempts to save an alert that changed (e.g. was dismissed) while it was being bumped
+const RAISE_ATTEMPTS: usize = 3;
+// Window for the "scanned in two countries on one day" check
+const COUNTRY_HOP_WINDOW_HOURS: i64 = 24;
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertRule {
+    // More scans than the produced quantity can explain
+    ScansExceedUnits,
+    // Consecutive scans too far apart for the time between them
+    ImpossibleTravel,
+    // A well-formed herb id that was never issued
+    UnknownId,
+}
+
+impl AlertRule {
+    fn slug(self) -> &'static str {
+        match self {
+            AlertRule::ScansExceedUnits => "scans_exceed_units",
+            AlertRule::ImpossibleTravel => "impossible_travel",
+            AlertRule::UnknownId => "unknown_id",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertStatus {
+    Open,
+    Dismissed,
+}
+
+impl AlertStatus {
+    // As serialized, for DocQuery::status
+    fn slug(self) -> &'static str {
+        match self {
+            AlertStatus::Open => "open",
+            AlertStatus::Dismissed => "dismissed",
+        }
+    }
+}
+
+// One alert per herb and rule; repeated detections bump its counters
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub struct Alert {
+    pub id: String,
+    pub herb_id: String,
+    pub rule: AlertRule,
+    pub message: String,
+    pub status: AlertStatus,
+    pub first_detected_at: DateTime<Utc>,
+    pub last_detected_at: DateTime<Utc>,
+    pub occurrences: u64,
+}
+
+pub struct RuleHit {
+    pub rule: AlertRule,
+    pub message: String,
+}
+
+fn alert_id(herb_id: &str, rule: AlertRule) -> String {
+    format!("alert_{}_{}", herb_id, rule.slug())
+}
+
+// Shape of ids produced by handlers::generate_id: "herb_" followed by up to 16 hex digits
+pub fn looks_like_issued_id(id: &str) -> bool {
+    id.strip_prefix("herb_")
+        .is_some_and(|hex| !hex.is_empty() && hex.len() <= 16 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+fn distance_km(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (lat1, lon1) = (a.0.to_radians(), a.1.to_radians());
+    let (lat2, lon2) = (b.0.to_radians(), b.1.to_radians());
+    let h = ((lat2 - lat1) / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * ((lon2 - lon1) / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * h.sqrt().asin()
+}
+
+fn coords(scan: &ScanEvent) -> Option<(f64, f64)> {
+    scan.latitude.zip(scan.longitude)
+}
+
+// Check the latest scan against the herb's scans. `scan_count` is the herb's total number of
+// scans and `recent` its scans from the last COUNTRY_HOP_WINDOW_HOURS, both including `latest`.
+pub fn evaluate(herb: Option<&Herb>, scan_count: u64, recent: &[ScanEvent], latest: &ScanEvent) -> Vec<RuleHit> {
+    let mut hits = Vec::new();
+
+    let Some(herb) = herb else {
+        if looks_like_issued_id(&latest.herb_id) {
+            hits.push(RuleHit {
+                rule: AlertRule::UnknownId,
+                message: format!("{} has the format of an issued id but no such herb exists", latest.herb_id),
+            });
+        }
+        return hits;
+    };
+
+    if let Some(units) = herb.units {
+        let allowed = units as u64 * SCANS_PER_UNIT_ALLOWANCE as u64;
+        if scan_count > allowed {
+            hits.push(RuleHit {
+                rule: AlertRule::ScansExceedUnits,
+                message: format!("{} scans for {} units produced", scan_count, units),
+            });
+        }
+    }
+
+    for previous in recent.iter().filter(|s| s.id != latest.id && s.scanned_at <= latest.scanned_at) {
+        let elapsed = latest.scanned_at - previous.scanned_at;
+        if let (Some(a), Some(b)) = (coords(previous), coords(latest)) {
+            let km = distance_km(a, b);
+            let window = elapsed.max(Duration::minutes(MIN_TRAVEL_WINDOW_MINUTES));
+            let hours = window.num_seconds() as f64 / 3600.0;
+            if km >= MIN_TRAVEL_KM && km / hours > MAX_PLAUSIBLE_SPEED_KMH {
+                hits.push(RuleHit {
+                    rule: AlertRule::ImpossibleTravel,
+                    message: format!("scanned {:.0} km apart within {} minutes", km, elapsed.num_minutes()),
+                });
+                break;
+            }
+        }
+        if let (Some(a), Some(b)) = (&previous.country, &latest.country) {
+            if a != b && elapsed <= Duration::hours(COUNTRY_HOP_WINDOW_HOURS) {
+                hits.push(RuleHit {
+                    rule: AlertRule::ImpossibleTravel,
+                    message: format!("scanned in {} and {} within {} hours", a, b, elapsed.num_hours()),
+                });
+                break;
+            }
+        }
+    }
+    hits
+}
+
+// Create or bump the alert for each hit. Dismissed alerts stay dismissed: an alert that changes
+// while it is being bumped is re-read and bumped again.
+pub async fn raise(state: &AppState, herb_id: &str, hits: &[RuleHit]) {
+    for hit in hits {
+        let id = alert_id(herb_id, hit.rule);
+        match raise_one(state, &id, herb_id, hit).await {
+            Ok(()) => state.workers.succeeded("alert-raiser"),
+            Err(err) => {
+                eprintln!("raising alert {} failed: {}", id, err);
+                state.workers.failed("alert-raiser", err);
+            }
+        }
+    }
+}
+
+async fn raise_one(state: &AppState, id: &str, herb_id: &str, hit: &RuleHit) -> Result<(), StoreError> {
+    for _ in 0..RAISE_ATTEMPTS {
+        let now = Utc::now();
+        let saved = match state.docs.get_doc::<Alert>(Collection::Alerts, id).await {
+            Ok((mut existing, rev)) => {
+                existing.message = hit.message.clone();
+                existing.last_detected_at = now;
+                existing.occurrences += 1;
+                state.docs.update_doc(Collection::Alerts, id, &rev, &existing).await
+            }
+            Err(StoreError::NotFound) => {
+                let alert = Alert {
+                    id: id.to_string(),
+                    herb_id: herb_id.to_string(),
+                    rule: hit.rule,
+                    message: hit.message.clone(),
+                    status: AlertStatus::Open,
+                    first_detected_at: now,
+                    last_detected_at: now,
+                    occurrences: 1,
+                };
+                state.docs.put_doc(Collection::Alerts, id, &alert).await
+            }
+            Err(err) => return Err(err),
+        };
+        match saved {
+            Ok(_) => return Ok(()),
+            Err(StoreError::Conflict) => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Err(StoreError::Conflict)
+}
+
+// Run the rules for a scan and return warnings for the scanner. Alerts are stored in the background.
+pub async fn check_scan(state: &AppState, herb: Option<&Herb>, latest: &ScanEvent) -> Vec<String> {
+    let all = DocQuery::herb(&latest.herb_id);
+    let recent = DocQuery {
+        from: Some(latest.scanned_at - Duration::hours(COUNTRY_HOP_WINDOW_HOURS)),
+        ..all.clone()
+    };
+    let (count, recent) = tokio::join!(
+        state.docs.count(Collection::Scans, &all),
+        state.docs.find_docs::<ScanEvent>(Collection::Scans, &recent),
+    );
+    let mut recent = recent.unwrap_or_else(|err| {
+        eprintln!("check_scan could not load recent scans of {}: {}", latest.herb_id, err);
+        Vec::new()
+    });
+    let mut count = count.unwrap_or_else(|err| {
+        eprintln!("check_scan could not count scans of {}: {}", latest.herb_id, err);
+        0
+    });
+    // The latest scan is recorded asynchronously and may not be stored yet
+    if !recent.iter().any(|s| s.id == latest.id) {
+        recent.push(latest.clone());
+        count += 1;
+    }
+    recent.sort_by_key(|s| std::cmp::Reverse(s.scanned_at));
+
+    let hits = evaluate(herb, count, &recent, latest);
+    let warnings = hits.iter().map(|h| h.message.clone()).collect();
+    if !hits.is_empty() {
+        let state = state.clone();
+        let herb_id = latest.herb_id.clone();
+        tokio::spawn(async move { raise(&state, &herb_id, &hits).await });
+    }
+    warnings
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AlertQuery {
+    pub status: Option<AlertStatus>,
+    pub herb_id: Option<String>,
+}
+
+// Handlers
+
+// GET /alerts?status=open&herb_id= - Flagged herbs for review, most recent first
+#[utoipa::path(
+    get,
+    path = "/alerts",
+    tag = "alerts",
+    params(AlertQuery),
+    responses(
+        (status = 200, description = "Counterfeit alerts, most recent first", body = [Alert]),
+    ),
+)]
+pub async fn list_alerts(
+    State(state): State<AppState>,
+    Query(query): Query<AlertQuery>,
+) -> impl IntoResponse {
+    let filter = DocQuery {
+        herb_ids: query.herb_id.into_iter().collect(),
+        status: query.status.map(|status| status.slug().to_string()),
+        ..DocQuery::default()
+    };
+    match state.docs.find_docs::<Alert>(Collection::Alerts, &filter).await {
+        Ok(mut alerts) => {
+            alerts.sort_by_key(|a| std::cmp::Reverse(a.last_detected_at));
+            (StatusCode::OK, Json(alerts)).into_response()
+        }
+        Err(err) => {
+            eprintln!("list_alerts failed: {}", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch alerts").into_response()
+        }
+    }
+}
+
+// POST /alerts/{id}/dismiss - Mark an alert as reviewed
+#[utoipa::path(
+    post,
+    path = "/alerts/{id}/dismiss",
+    tag = "alerts",
+    params(("id" = String, Path, description = "Alert id")),
+    responses(
+        (status = 200, description = "Dismissed alert", body = Alert),
+        (status = 404, description = "Alert not found"),
+        (status = 500, description = "Failed to update alert"),
+    ),
+)]
+pub async fn dismiss_alert(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let (mut alert, rev) = match state.docs.get_doc::<Alert>(Collection::Alerts, &id).await {
+        Ok(pair) => pair,
+        Err(StoreError::NotFound) => return (StatusCode::NOT_FOUND, "Alert not found").into_response(),
+        Err(err) => {
+            eprintln!("dismiss_alert get failed for id {}: {}", id, err);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch alert").into_response();
+        }
+    };
+    alert.status = AlertStatus::Dismissed;
+    if let Err(err) = state.docs.update_doc(Collection::Alerts, &id, &rev, &alert).await {
+        eprintln!("dismiss_alert save failed for id {}: {}", id, err);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update alert").into_response();
+    }
+    (StatusCode::OK, Json(alert)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analytics::{new_event, ScanClient, ScanSource};
+    use crate::handlers::test_herb;
+
+    fn herb(units: Option<u32>) -> Herb {
+        Herb { units, ..test_herb("Tulsi", "Asha") }
+    }
+
+    // A scan of `herb_id` `minutes` after the first one, from `coords` and/or `country`
+    fn scan(herb_id: &str, minutes: i64, coords: Option<(f64, f64)>, country: Option<&str>) -> ScanEvent {
+        let client = ScanClient { country: country.map(str::to_string), ..ScanClient::default() };
+        let mut event = new_event(herb_id, true, ScanSource::Scan, &client, coords);
+        event.scanned_at = DateTime::parse_from_rfc3339("2026-10-01T08:00:00Z").unwrap().to_utc() + Duration::minutes(minutes);
+        event
+    }
+
+    fn rules(hits: &[RuleHit]) -> Vec<AlertRule> {
+        hits.iter().map(|h| h.rule).collect()
+    }
+
+    #[test]
+    fn unknown_ids_are_flagged_only_when_they_look_issued() {
+        let latest = scan("herb_00ff", 0, None, None);
+        assert_eq!(rules(&evaluate(None, 1, std::slice::from_ref(&latest), &latest)), [AlertRule::UnknownId]);
+        let latest = scan("hello", 0, None, None);
+        assert!(evaluate(None, 1, std::slice::from_ref(&latest), &latest).is_empty());
+    }
+
+    #[test]
+    fn scans_beyond_the_allowance_per_unit_are_flagged() {
+        let herb = herb(Some(2));
+        let latest = scan(&herb.id, 0, None, None);
+        assert!(evaluate(Some(&herb), 6, std::slice::from_ref(&latest), &latest).is_empty());
+        assert_eq!(rules(&evaluate(Some(&herb), 7, std::slice::from_ref(&latest), &latest)), [AlertRule::ScansExceedUnits]);
+        assert!(evaluate(Some(&self::herb(None)), 1_000, std::slice::from_ref(&latest), &latest).is_empty());
+    }
+
+    #[test]
+    fn distant_scans_close_in_time_are_impossible_travel() {
+        let herb = herb(None);
+        // Bengaluru, then Delhi ten minutes later
+        let first = scan(&herb.id, 0, Some((12.97, 77.59)), None);
+        let latest = scan(&herb.id, 10, Some((28.61, 77.21)), None);
+        let recent = [latest.clone(), first.clone()];
+        assert_eq!(rules(&evaluate(Some(&herb), 2, &recent, &latest)), [AlertRule::ImpossibleTravel]);
+        // A day's flight apart is plausible
+        let latest = scan(&herb.id, 6 * 60, Some((28.61, 77.21)), None);
+        assert!(evaluate(Some(&herb), 2, &[latest.clone(), first], &latest).is_empty());
+    }
+
+    #[test]
+    fn rounding_and_back_to_back_scans_are_not_travel() {
+        let herb = herb(None);
+        // Metres apart, but rounded to opposite sides of a 0.1 degree boundary
+        let first = scan(&herb.id, 0, Some((12.949, 77.549)), None);
+        let latest = scan(&herb.id, 0, Some((12.951, 77.551)), None);
+        assert_eq!((first.latitude, latest.latitude), (Some(12.9), Some(13.0)));
+        assert!(evaluate(Some(&herb), 2, &[latest.clone(), first], &latest).is_empty());
+        // 60 km within the same minute is measured over the minimum window
+        let first = scan(&herb.id, 0, Some((12.97, 77.59)), None);
+        let latest = scan(&herb.id, 1, Some((12.97, 78.14)), None);
+        assert!(evaluate(Some(&herb), 2, &[latest.clone(), first], &latest).is_empty());
+    }
+
+    #[test]
+    fn two_countries_within_a_day_are_impossible_travel() {
+        let herb = herb(None);
+        let first = scan(&herb.id, 0, None, Some("IN"));
+        let latest = scan(&herb.id, 12 * 60, None, Some("DE"));
+        assert_eq!(rules(&evaluate(Some(&herb), 2, &[latest.clone(), first.clone()], &latest)), [AlertRule::ImpossibleTravel]);
+        let latest = scan(&herb.id, 12 * 60, None, Some("IN"));
+        assert!(evaluate(Some(&herb), 2, &[latest.clone(), first], &latest).is_empty());
+    }
+
+    #[tokio::test]
+    async fn raising_again_keeps_a_dismissed_alert_dismissed() {
+        let state = AppState::for_tests();
+        let hit = [RuleHit { rule: AlertRule::UnknownId, message: "unknown".to_string() }];
+        raise(&state, "herb_00ff", &hit).await;
+        let id = alert_id("herb_00ff", AlertRule::UnknownId);
+        let response = dismiss_alert(State(state.clone()), Path(id.clone())).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        raise(&state, "herb_00ff", &hit).await;
+        let (alert, _) = state.docs.get_doc::<Alert>(Collection::Alerts, &id).await.unwrap();
+        assert_eq!(alert.status, AlertStatus::Dismissed);
+        assert_eq!(alert.occurrences, 2);
+    }
+}
@@ -0,0 +1,438 @@
+use axum::{
+    extract::{ConnectInfo, FromRequestParts, Query, State},
+    http::{header, request::Parts, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::{ToSchema, IntoParams};
+use async_graphql::{Enum, SimpleObject};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::docs::{Collection, DocQuery};
+use crate::handlers::AppState;
+use crate::store::StoreError;
+
+const MAX_USER_AGENT_LEN: usize = 200;
+pub const DEFAULT_WINDOW_DAYS: i64 = 30;
+// Client-supplied coordinates are rounded to one decimal place (roughly 11 km)
+const COORDINATE_PRECISION: f64 = 10.0;
+
+// Headers set by common CDNs / reverse proxies with the viewer's country and region
+const COUNTRY_HEADERS: [&str; 3] = ["cf-ipcountry", "cloudfront-viewer-country", "x-country-code"];
+const REGION_HEADERS: [&str; 2] = ["cloudfront-viewer-country-region", "x-region-code"];
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Enum)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanSource {
+    // POST /scan from the app or scan page
+    Scan,
+    // QR landing pages: /p/{id}, /p/{id}/html and GS1 Digital Links
+    PublicPage,
+}
+
+#[derive(Serialize, Deserialize, Clone, SimpleObject)]
+pub struct ScanEvent {
+    pub id: String,
+    // The id that was scanned; it may not belong to any herb
+    pub herb_id: String,
+    pub found: bool,
+    pub scanned_at: DateTime<Utc>,
+    pub source: ScanSource,
+    // Anonymised client address (IPv4 /24, IPv6 /48); kept out of GraphQL like the REST analytics
+    #[graphql(skip)]
+    pub ip_prefix: Option<String>,
+    #[graphql(skip)]
+    pub user_agent: Option<String>,
+    pub country: Option<String>,
+    pub region: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+// Zero the host part of an address so individual clients cannot be identified
+pub fn anonymize_ip(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, c, _] = v4.octets();
+            format!("{}.{}.{}.0/24", a, b, c)
+        }
+        IpAddr::V6(v6) => {
+            let s = v6.segments();
+            format!("{:x}:{:x}:{:x}::/48", s[0], s[1], s[2])
+        }
+    }
+}
+
+fn round_coordinate(value: f64) -> f64 {
+    (value * COORDINATE_PRECISION).round() / COORDINATE_PRECISION
+}
+
+fn first_header(headers: &HeaderMap, names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .filter_map(|name| headers.get(*name))
+        .filter_map(|v| v.to_str().ok())
+        .map(|v| v.trim().to_uppercase())
+        .find(|v| !v.is_empty() && v != "XX")
+}
+
+// Who scanned: collected from the connection and request headers by every scan endpoint
+#[derive(Clone, Default)]
+pub struct ScanClient {
+    pub ip_prefix: Option<String>,
+    pub user_agent: Option<String>,
+    pub country: Option<String>,
+    pub region: Option<String>,
+}
+
+impl FromRequestParts<AppState> for ScanClient {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let headers = &parts.headers;
+        let forwarded = headers.get("x-forwarded-for").and_then(|v| v.to_str().ok());
+        let peer = parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|c| c.0.ip());
+        let user_agent = headers
+            .get(header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .map(|ua| ua.chars().take(MAX_USER_AGENT_LEN).collect());
+        Ok(Self {
+            ip_prefix: client_ip(peer, forwarded, &state.settings.trusted_proxies).map(anonymize_ip),
+            user_agent,
+            country: first_header(headers, &COUNTRY_HEADERS),
+            region: first_header(headers, &REGION_HEADERS),
+        })
+    }
+}
+
+// The connecting address, unless it is a trusted proxy: then the X-Forwarded-For entry added by
+// the outermost trusted proxy, read from the right because clients can prepend anything
+fn client_ip(peer: Option<IpAddr>, forwarded: Option<&str>, trusted: &[IpAddr]) -> Option<IpAddr> {
+    let peer = peer?;
+    if !trusted.contains(&peer) {
+        return Some(peer);
+    }
+    let mut client = peer;
+    for hop in forwarded.unwrap_or_default().rsplit(',') {
+        match hop.trim().parse::<IpAddr>() {
+            Ok(ip) => {
+                client = ip;
+                if !trusted.contains(&ip) {
+                    break;
+                }
+            }
+            Err(_) => break,
+        }
+    }
+    Some(client)
+}
+
+fn next_scan_id(now: DateTime<Utc>) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let seq = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("scan_{}_{:x}", now.timestamp_micros(), seq)
+}
+
+pub fn new_event(herb_id: &str, found: bool, source: ScanSource, client: &ScanClient, coords: Option<(f64, f64)>) -> ScanEvent {
+    let now = Utc::now();
+    let coords = coords.filter(|(lat, lon)| (-90.0..=90.0).contains(lat) && (-180.0..=180.0).contains(lon));
+    ScanEvent {
+        id: next_scan_id(now),
+        herb_id: herb_id.to_string(),
+        found,
+        scanned_at: now,
+        source,
+        ip_prefix: client.ip_prefix.clone(),
+        user_agent: client.user_agent.clone(),
+        country: client.country.clone(),
+        region: client.region.clone(),
+        latitude: coords.map(|(lat, _)| round_coordinate(lat)),
+        longitude: coords.map(|(_, lon)| round_coordinate(lon)),
+    }
+}
+
+// Persist a scan in the background so recording never slows down or fails the scan itself
+pub fn record(state: &AppState, event: ScanEvent) {
+    let state = state.clone();
+    tokio::spawn(async move {
+        match state.docs.put_doc(Collection::Scans, &event.id, &event).await {
+            Ok(_) => {
+                state.workers.succeeded("scan-recorder");
+                state.live.scan_recorded(&event);
+            }
+            Err(err) => {
+                eprintln!("recording scan {} failed: {}", event.id, err);
+                state.workers.failed("scan-recorder", err);
+            }
+        }
+    });
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ScanQuery {
+    pub herb_id: Option<String>,
+    // Defaults to the last 30 days
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+pub async fn fetch_scans(
+    state: &AppState,
+    herb_id: Option<&str>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<ScanEvent>, StoreError> {
+    let query = DocQuery {
+        herb_ids: herb_id.map(str::to_string).into_iter().collect(),
+        from: Some(from),
+        to: Some(to),
+        ..DocQuery::default()
+    };
+    state.docs.find_docs::<ScanEvent>(Collection::Scans, &query).await
+}
+
+async fn scans_for_query(state: &AppState, query: &ScanQuery) -> Result<Vec<ScanEvent>, axum::response::Response> {
+    let to = query.to.unwrap_or_else(Utc::now);
+    let from = query.from.unwrap_or(to - Duration::days(DEFAULT_WINDOW_DAYS));
+    if from >= to {
+        return Err((StatusCode::BAD_REQUEST, "from must be before to").into_response());
+    }
+    fetch_scans(state, query.herb_id.as_deref(), from, to).await.map_err(|err| {
+        eprintln!("scan analytics query failed: {}", err);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch scans").into_response()
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct HerbScanStats {
+    pub herb_id: String,
+    pub found: bool,
+    pub scans: usize,
+    pub unique_clients: usize,
+    pub countries: Vec<String>,
+    pub last_scanned_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DayScanStats {
+    pub day: String,
+    pub scans: usize,
+    pub unique_clients: usize,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct RegionScanStats {
+    pub country: Option<String>,
+    pub region: Option<String>,
+    pub scans: usize,
+    pub herbs: usize,
+}
+
+// Busiest herbs first
+fn herb_stats(scans: &[ScanEvent]) -> Vec<HerbScanStats> {
+    let mut grouped: HashMap<&str, Vec<&ScanEvent>> = HashMap::new();
+    for scan in scans {
+        grouped.entry(scan.herb_id.as_str()).or_default().push(scan);
+    }
+    let mut stats: Vec<HerbScanStats> = grouped
+        .into_iter()
+        .map(|(herb_id, events)| {
+            let clients: HashSet<_> = events.iter().filter_map(|e| e.ip_prefix.as_ref()).collect();
+            let countries: HashSet<_> = events.iter().filter_map(|e| e.country.clone()).collect();
+            let mut countries: Vec<String> = countries.into_iter().collect();
+            countries.sort();
+            HerbScanStats {
+                herb_id: herb_id.to_string(),
+                found: events.iter().any(|e| e.found),
+                scans: events.len(),
+                unique_clients: clients.len(),
+                countries,
+                last_scanned_at: events.iter().map(|e| e.scanned_at).max().unwrap_or_default(),
+            }
+        })
+        .collect();
+    stats.sort_by(|a, b| b.scans.cmp(&a.scans).then(a.herb_id.cmp(&b.herb_id)));
+    stats
+}
+
+// Oldest day first
+fn day_stats(scans: &[ScanEvent]) -> Vec<DayScanStats> {
+    let mut grouped: BTreeMap<String, Vec<&ScanEvent>> = BTreeMap::new();
+    for scan in scans {
+        grouped.entry(scan.scanned_at.format("%Y-%m-%d").to_string()).or_default().push(scan);
+    }
+    grouped
+        .into_iter()
+        .map(|(day, events)| {
+            let clients: HashSet<_> = events.iter().filter_map(|e| e.ip_prefix.as_ref()).collect();
+            DayScanStats { day, scans: events.len(), unique_clients: clients.len() }
+        })
+        .collect()
+}
+
+// Busiest regions first
+fn region_stats(scans: &[ScanEvent]) -> Vec<RegionScanStats> {
+    let mut grouped: HashMap<(Option<String>, Option<String>), Vec<&ScanEvent>> = HashMap::new();
+    for scan in scans {
+        grouped.entry((scan.country.clone(), scan.region.clone())).or_default().push(scan);
+    }
+    let mut stats: Vec<RegionScanStats> = grouped
+        .into_iter()
+        .map(|((country, region), events)| {
+            let herbs: HashSet<_> = events.iter().map(|e| e.herb_id.as_str()).collect();
+            RegionScanStats { country, region, scans: events.len(), herbs: herbs.len() }
+        })
+        .collect();
+    stats.sort_by(|a, b| b.scans.cmp(&a.scans).then_with(|| (&a.country, &a.region).cmp(&(&b.country, &b.region))));
+    stats
+}
+
+// Handlers
+
+// GET /analytics/scans/by-herb?from=&to=
+#[utoipa::path(
+    get,
+    path = "/analytics/scans/by-herb",
+    tag = "analytics",
+    params(ScanQuery),
+    responses((status = 200, description = "Scan counts per herb", body = [HerbScanStats])),
+)]
+pub async fn scans_by_herb(
+    State(state): State<AppState>,
+    Query(query): Query<ScanQuery>,
+) -> impl IntoResponse {
+    let scans = match scans_for_query(&state, &query).await {
+        Ok(scans) => scans,
+        Err(response) => return response,
+    };
+    (StatusCode::OK, Json(herb_stats(&scans))).into_response()
+}
+
+// GET /analytics/scans/by-day?herb_id=&from=&to=
+#[utoipa::path(
+    get,
+    path = "/analytics/scans/by-day",
+    tag = "analytics",
+    params(ScanQuery),
+    responses((status = 200, description = "Scan counts per day", body = [DayScanStats])),
+)]
+pub async fn scans_by_day(
+    State(state): State<AppState>,
+    Query(query): Query<ScanQuery>,
+) -> impl IntoResponse {
+    let scans = match scans_for_query(&state, &query).await {
+        Ok(scans) => scans,
+        Err(response) => return response,
+    };
+    (StatusCode::OK, Json(day_stats(&scans))).into_response()
+}
+
+// GET /analytics/scans/by-region?herb_id=&from=&to=
+#[utoipa::path(
+    get,
+    path = "/analytics/scans/by-region",
+    tag = "analytics",
+    params(ScanQuery),
+    responses(
+        (status = 200, description = "Scan counts per region", body = [RegionScanStats]),
+    ),
+)]
+pub async fn scans_by_region(
+    State(state): State<AppState>,
+    Query(query): Query<ScanQuery>,
+) -> impl IntoResponse {
+    let scans = match scans_for_query(&state, &query).await {
+        Ok(scans) => scans,
+        Err(response) => return response,
+    };
+    (StatusCode::OK, Json(region_stats(&scans))).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    fn scan(herb_id: &str, day: u32, ip_prefix: &str, country: Option<&str>) -> ScanEvent {
+        let client = ScanClient {
+            ip_prefix: Some(ip_prefix.to_string()),
+            country: country.map(str::to_string),
+            ..ScanClient::default()
+        };
+        let mut event = new_event(herb_id, true, ScanSource::Scan, &client, None);
+        event.scanned_at = DateTime::parse_from_rfc3339(&format!("2026-10-{:02}T08:00:00Z", day)).unwrap().to_utc();
+        event
+    }
+
+    #[test]
+    fn addresses_lose_their_host_part() {
+        assert_eq!(anonymize_ip(ip("203.0.113.77")), "203.0.113.0/24");
+        assert_eq!(anonymize_ip(ip("2001:db8:abcd:12::1")), "2001:db8:abcd::/48");
+    }
+
+    #[test]
+    fn coordinates_are_rounded_to_a_tenth_of_a_degree() {
+        assert_eq!(round_coordinate(12.9716), 13.0);
+        assert_eq!(round_coordinate(77.5946), 77.6);
+        assert_eq!(round_coordinate(-33.8688), -33.9);
+        let event = new_event("herb_1", true, ScanSource::Scan, &ScanClient::default(), Some((12.94, 77.56)));
+        assert_eq!((event.latitude, event.longitude), (Some(12.9), Some(77.6)));
+        // Off the globe is dropped rather than rounded
+        let event = new_event("herb_1", true, ScanSource::Scan, &ScanClient::default(), Some((91.0, 0.0)));
+        assert_eq!((event.latitude, event.longitude), (None, None));
+    }
+
+    #[test]
+    fn forwarded_addresses_are_believed_only_from_trusted_proxies() {
+        let proxy = ip("10.0.0.2");
+        let trusted = [proxy, ip("10.0.0.3")];
+        let client = ip("203.0.113.7");
+        // Anyone else's X-Forwarded-For is ignored
+        assert_eq!(client_ip(Some(client), Some("198.51.100.1"), &trusted), Some(client));
+        assert_eq!(client_ip(Some(client), Some("198.51.100.1"), &[]), Some(client));
+        // A spoofed entry prepended by the client does not win over the one the proxy added
+        assert_eq!(client_ip(Some(proxy), Some("198.51.100.1, 203.0.113.7"), &trusted), Some(client));
+        assert_eq!(client_ip(Some(proxy), Some("203.0.113.7, 10.0.0.3"), &trusted), Some(client));
+        assert_eq!(client_ip(Some(proxy), Some("garbage"), &trusted), Some(proxy));
+        assert_eq!(client_ip(Some(proxy), None, &trusted), Some(proxy));
+        assert_eq!(client_ip(None, Some("203.0.113.7"), &trusted), None);
+    }
+
+    #[test]
+    fn scans_are_aggregated_per_herb_day_and_region() {
+        let scans = [
+            scan("herb_a", 1, "203.0.113.0/24", Some("IN")),
+            scan("herb_a", 1, "203.0.113.0/24", Some("IN")),
+            scan("herb_a", 2, "198.51.100.0/24", Some("DE")),
+            scan("herb_b", 2, "203.0.113.0/24", Some("IN")),
+        ];
+
+        let herbs = herb_stats(&scans);
+        assert_eq!(herbs.iter().map(|h| (h.herb_id.as_str(), h.scans, h.unique_clients)).collect::<Vec<_>>(), [
+            ("herb_a", 3, 2),
+            ("herb_b", 1, 1),
+        ]);
+        assert_eq!(herbs[0].countries, ["DE", "IN"]);
+        assert_eq!(herbs[0].last_scanned_at, scans[2].scanned_at);
+
+        let days = day_stats(&scans);
+        assert_eq!(days.iter().map(|d| (d.day.as_str(), d.scans, d.unique_clients)).collect::<Vec<_>>(), [
+            ("2026-10-01", 2, 1),
+            ("2026-10-02", 2, 2),
+        ]);
+
+        let regions = region_stats(&scans);
+        assert_eq!(regions.iter().map(|r| (r.country.as_deref(), r.scans, r.herbs)).collect::<Vec<_>>(), [
+            (Some("IN"), 3, 2),
+            (Some("DE"), 1, 1),
+        ]);
+    }
+}
@@ -0,0 +1,447 @@
+use axum::{
+    extract::{Path, Json, Query, Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Deserializer};
+use utoipa::ToSchema;
+use crate::store::StoreError;
+use crate::tenants::BasePath;
+use crate::handlers::{self, new_herb, AddHerbRequest, AppState, Herb, HerbFilter, HerbStatus, HerbWithQr, UpdateHerbRequest};
+
+// Resource-oriented herb API. The RPC-style routes (/addHerb, /getHerb/{id}, ...) stay mounted as
+// deprecated aliases; mark_deprecated adds RFC 9745 Deprecation and RFC 8594 Sunset headers to them.
+pub const HERBS_PATH: &str = "/api/v1/herbs";
+// 2026-10-18T00:00:00Z, as an RFC 9745 structured-field date
+const LEGACY_DEPRECATED_AT: &str = "@1792281600";
+const LEGACY_SUNSET: &str = "Fri, 30 Apr 2027 00:00:00 GMT";
+
+// Legacy route -> its /api/v1 replacement, for the successor-version Link header
+fn successor(path: &str) -> String {
+    let id = path.trim_start_matches('/').split_once('/').map(|(_, id)| id);
+    match id {
+        Some(id) if !id.is_empty() => format!("{}/{}", HERBS_PATH, id),
+        _ => HERBS_PATH.to_string(),
+    }
+}
+
+pub async fn mark_deprecated(request: Request, next: Next) -> Response {
+    let link = format!("<{}>; rel=\"successor-version\"", successor(request.uri().path()));
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    headers.insert("deprecation", HeaderValue::from_static(LEGACY_DEPRECATED_AT));
+    headers.insert("sunset", HeaderValue::from_static(LEGACY_SUNSET));
+    if let Ok(value) = HeaderValue::from_str(&link) {
+        headers.insert(header::LINK, value);
+    }
+    response
+}
+
+// Distinguishes a field set to null (Some(None)) from an absent one (None)
+fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+// JSON Merge Patch (RFC 7396) body: absent fields are left alone, null removes an optional field
+#[derive(Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct HerbPatch {
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<String>)]
+    pub name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<String>)]
+    pub farmer: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<String>)]
+    pub location: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<HerbStatus>)]
+    pub status: Option<Option<HerbStatus>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<String>)]
+    pub gtin: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<String>)]
+    pub lot: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<u32>)]
+    pub units: Option<Option<u32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<f64>)]
+    pub latitude: Option<Option<f64>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<f64>)]
+    pub longitude: Option<Option<f64>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<String>)]
+    pub recall_reason: Option<Option<String>>,
+}
+
+// Optional fields a merge patch set to null
+#[derive(Default)]
+struct Removed {
+    gtin: bool,
+    lot: bool,
+    units: bool,
+    origin: bool,
+    recall_reason: bool,
+}
+
+impl Removed {
+    fn apply(&self, herb: &mut Herb) {
+        if self.gtin { herb.gtin = None; }
+        if self.lot { herb.lot = None; }
+        if self.units { herb.units = None; }
+        if self.origin { (herb.latitude, herb.longitude) = (None, None); }
+        if self.recall_reason { herb.recall_reason = None; }
+    }
+}
+
+impl HerbPatch {
+    // Splits the patch into the values to set (validated by handlers::apply_update) and the fields to remove
+    fn into_update(self, herb: &Herb) -> Result<(UpdateHerbRequest, Removed), String> {
+        fn required<T>(field: &str, value: Option<Option<T>>) -> Result<Option<T>, String> {
+            match value {
+                Some(None) => Err(format!("{} cannot be removed", field)),
+                other => Ok(other.flatten()),
+            }
+        }
+        let mut removed = Removed {
+            gtin: matches!(self.gtin, Some(None)),
+            lot: matches!(self.lot, Some(None)),
+            units: matches!(self.units, Some(None)),
+            recall_reason: matches!(self.recall_reason, Some(None)),
+            ..Removed::default()
+        };
+
+        // Coordinates are merged with the stored ones, then validated as a pair
+        let (mut latitude, mut longitude) = (None, None);
+        if self.latitude.is_some() || self.longitude.is_some() {
+            let lat = self.latitude.unwrap_or(herb.latitude);
+            let lon = self.longitude.unwrap_or(herb.longitude);
+            match (lat, lon) {
+                (None, None) => removed.origin = true,
+                (Some(_), Some(_)) => (latitude, longitude) = (lat, lon),
+                _ => return Err("latitude and longitude must be given together".to_string()),
+            }
+        }
+
+        let update = UpdateHerbRequest {
+            name: required("name", self.name)?,
+            farmer: required("farmer", self.farmer)?,
+            location: required("location", self.location)?,
+            status: required("status", self.status)?,
+            gtin: self.gtin.flatten(),
+            lot: self.lot.flatten(),
+            units: self.units.flatten(),
+            latitude,
+            longitude,
+            recall_reason: self.recall_reason.flatten(),
+        };
+        Ok((update, removed))
+    }
+}
+
+// Strong ETag for a CouchDB revision
+fn etag(rev: &str) -> Option<HeaderValue> {
+    HeaderValue::from_str(&format!("\"{}\"", rev)).ok()
+}
+
+// If-Match must name the current revision (or be "*") when the client sends it
+fn precondition_holds(headers: &HeaderMap, rev: &str) -> bool {
+    let Some(if_match) = headers.get(header::IF_MATCH).and_then(|v| v.to_str().ok()) else {
+        return true;
+    };
+    if_match
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/").trim_matches('"'))
+        .any(|tag| tag == "*" || tag == rev)
+}
+
+fn herb_location(id: &str) -> String {
+    format!("{}/{}", HERBS_PATH, id)
+}
+
+// Handlers
+
+// GET /api/v1/herbs
+#[utoipa::path(
+    get,
+    path = "/api/v1/herbs",
+    tag = "herbs",
+    params(HerbFilter),
+    responses((status = 200, description = "Matching herbs", body = [Herb])),
+)]
+pub async fn list_herbs(state: State<AppState>, filter: Query<HerbFilter>) -> impl IntoResponse {
+    handlers::list_herbs(state, filter).await
+}
+
+// POST /api/v1/herbs
+#[utoipa::path(
+    post,
+    path = "/api/v1/herbs",
+    tag = "herbs",
+    request_body = AddHerbRequest,
+    responses(
+        (status = 201, description = "Herb created; Location names the new resource", body = Herb),
+        (status = 400, description = "Validation failed"),
+        (status = 409, description = "A herb with the same name and farmer exists; Location names it"),
+    ),
+)]
+pub async fn create_herb(
+    State(state): State<AppState>,
+    Json(payload): Json<AddHerbRequest>,
+) -> impl IntoResponse {
+    if let Err(msg) = payload.validate() {
+        return (StatusCode::BAD_REQUEST, msg).into_response();
+    }
+    let herb = new_herb(payload);
+    let location = [(header::LOCATION, herb_location(&herb.id))];
+    match state.herbs.put(&herb).await {
+        Ok(_) => {
+            state.live.herb_added(&herb);
+            (StatusCode::CREATED, location, Json(herb)).into_response()
+        }
+        Err(StoreError::Conflict) => {
+            (StatusCode::CONFLICT, location, "Herb already exists").into_response()
+        }
+        Err(err) => {
+            eprintln!("create_herb failed for id {}: {}", herb.id, err);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to add herb").into_response()
+        }
+    }
+}
+
+// GET /api/v1/herbs/{id}
+#[utoipa::path(
+    get,
+    path = "/api/v1/herbs/{id}",
+    tag = "herbs",
+    params(("id" = String, Path, description = "Herb id")),
+    responses(
+        (status = 200, description = "Herb with QR code and attachments; ETag is the document revision", body = HerbWithQr),
+        (status = 404, description = "Herb not found"),
+    ),
+)]
+pub async fn get_herb(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    base: BasePath,
+) -> impl IntoResponse {
+    match state.herbs.get(&id).await {
+        Ok((herb, rev)) => {
+            let mut response = (StatusCode::OK, Json(handlers::with_qr(&state, herb, &rev, &base).await)).into_response();
+            if let Some(value) = etag(&rev) {
+                response.headers_mut().insert(header::ETAG, value);
+            }
+            response
+        }
+        Err(err) => {
+            eprintln!("v1 get_herb failed for id {}: {}", id, err);
+            (StatusCode::NOT_FOUND, "Herb not found").into_response()
+        }
+    }
+}
+
+// PATCH /api/v1/herbs/{id} - JSON Merge Patch; send If-Match with the ETag to avoid lost updates
+#[utoipa::path(
+    patch,
+    path = "/api/v1/herbs/{id}",
+    tag = "herbs",
+    params(
+        ("id" = String, Path, description = "Herb id"),
+        ("If-Match" = Option<String>, Header, description = "ETag from a previous GET"),
+    ),
+    request_body(content = HerbPatch, content_type = "application/merge-patch+json"),
+    responses(
+        (status = 200, description = "Patched herb; ETag is the new revision", body = Herb),
+        (status = 400, description = "Validation failed"),
+        (status = 404, description = "Herb not found"),
+        (status = 409, description = "Release needs a passing lab result, or the herb changed during the update"),
+        (status = 412, description = "If-Match does not match the current revision"),
+    ),
+)]
+pub async fn patch_herb(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(patch): Json<HerbPatch>,
+) -> impl IntoResponse {
+    let (mut herb, rev) = match state.herbs.get(&id).await {
+        Ok(pair) => pair,
+        Err(err) => {
+            eprintln!("patch_herb get failed for id {}: {}", id, err);
+            return (StatusCode::NOT_FOUND, "Herb not found").into_response();
+        }
+    };
+    if !precondition_holds(&headers, &rev) {
+        return (StatusCode::PRECONDITION_FAILED, "Herb has changed since it was read").into_response();
+    }
+
+    let (update, removed) = match patch.into_update(&herb) {
+        Ok(parts) => parts,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    if let Err(response) = handlers::apply_update(&state, &mut herb, update).await {
+        return response;
+    }
+    removed.apply(&mut herb);
+
+    match state.herbs.update(&herb, &rev).await {
+        Ok(rev) => {
+            let mut response = (StatusCode::OK, Json(herb)).into_response();
+            if let Some(value) = etag(&rev) {
+                response.headers_mut().insert(header::ETAG, value);
+            }
+            response
+        }
+        Err(StoreError::Conflict) => {
+            (StatusCode::CONFLICT, "Herb was changed by another request; retry").into_response()
+        }
+        Err(err) => {
+            eprintln!("patch_herb save failed for id {}: {}", id, err);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update herb").into_response()
+        }
+    }
+}
+
+// DELETE /api/v1/herbs/{id}
+#[utoipa::path(
+    delete,
+    path = "/api/v1/herbs/{id}",
+    tag = "herbs",
+    params(
+        ("id" = String, Path, description = "Herb id"),
+        ("If-Match" = Option<String>, Header, description = "ETag from a previous GET"),
+    ),
+    responses(
+        (status = 204, description = "Herb deleted"),
+        (status = 404, description = "Herb not found"),
+        (status = 412, description = "If-Match does not match the current revision"),
+    ),
+)]
+pub async fn delete_herb(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let rev = match state.herbs.rev(&id).await {
+        Ok(Some(rev)) => rev,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Herb not found").into_response(),
+        Err(err) => {
+            eprintln!("v1 delete_herb lookup failed for id {}: {}", id, err);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete herb").into_response();
+        }
+    };
+    if !precondition_holds(&headers, &rev) {
+        return (StatusCode::PRECONDITION_FAILED, "Herb has changed since it was read").into_response();
+    }
+    match handlers::remove_herb(&state, &id, &rev).await {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(StoreError::Conflict) => {
+            (StatusCode::PRECONDITION_FAILED, "Herb has changed since it was read").into_response()
+        }
+        Err(err) => {
+            eprintln!("v1 delete_herb failed for id {}: {}", id, err);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete herb").into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::to_bytes;
+
+    async fn body_json(response: Response) -> serde_json::Value {
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    fn add_request() -> AddHerbRequest {
+        serde_json::from_value(serde_json::json!({
+            "name": "Tulsi",
+            "farmer": "Asha",
+            "location": "Mysuru",
+            "gtin": "8901234567890",
+            "lot": "L1",
+        }))
+        .unwrap()
+    }
+
+    fn patch(body: serde_json::Value) -> Json<HerbPatch> {
+        Json(serde_json::from_value(body).unwrap())
+    }
+
+    fn if_match(rev: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_MATCH, etag(rev).unwrap());
+        headers
+    }
+
+    #[tokio::test]
+    async fn create_conflicts_on_a_second_post() {
+        let state = AppState::for_tests();
+        let created = create_herb(State(state.clone()), Json(add_request())).await.into_response();
+        assert_eq!(created.status(), StatusCode::CREATED);
+        let location = created.headers()[header::LOCATION].to_str().unwrap().to_string();
+        let id = body_json(created).await["id"].as_str().unwrap().to_string();
+        assert_eq!(location, herb_location(&id));
+
+        let again = create_herb(State(state), Json(add_request())).await.into_response();
+        assert_eq!(again.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn merge_patch_sets_removes_and_keeps_fields() {
+        let state = AppState::for_tests();
+        let herb = new_herb(add_request());
+        state.herbs.put(&herb).await.unwrap();
+
+        let body = patch(serde_json::json!({ "location": "Hassan", "lot": null }));
+        let response = patch_herb(State(state.clone()), Path(herb.id.clone()), HeaderMap::new(), body).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let patched = body_json(response).await;
+        assert_eq!(patched["location"], "Hassan");
+        assert!(patched.get("lot").is_none());
+        // Untouched fields keep their values
+        assert_eq!(patched["gtin"], "08901234567890");
+        assert_eq!(patched["name"], "Tulsi");
+
+        let body = patch(serde_json::json!({ "name": null }));
+        let response = patch_herb(State(state), Path(herb.id), HeaderMap::new(), body).await.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn stale_if_match_is_rejected() {
+        let state = AppState::for_tests();
+        let herb = new_herb(add_request());
+        state.herbs.put(&herb).await.unwrap();
+        let (_, rev) = state.herbs.get(&herb.id).await.unwrap();
+
+        let body = patch(serde_json::json!({ "units": 10 }));
+        let response = patch_herb(State(state.clone()), Path(herb.id.clone()), if_match(&rev), body).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        // The ETag names the new revision, ready for the next If-Match
+        let (_, current) = state.herbs.get(&herb.id).await.unwrap();
+        assert_eq!(response.headers().get(header::ETAG), etag(&current).as_ref());
+
+        // `rev` is now out of date
+        let body = patch(serde_json::json!({ "units": 20 }));
+        let response = patch_herb(State(state.clone()), Path(herb.id.clone()), if_match(&rev), body).await.into_response();
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        let response = delete_herb(State(state.clone()), Path(herb.id.clone()), if_match(&rev)).await.into_response();
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+
+        let response = delete_herb(State(state.clone()), Path(herb.id.clone()), HeaderMap::new()).await.into_response();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(state.herbs.rev(&herb.id).await, Ok(None));
+    }
+}
@@ -0,0 +1,399 @@
+use axum::{
+    extract::{Multipart, Path, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use image::ImageFormat;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use std::io::Cursor;
+use crate::docs::FileInfo;
+use crate::handlers::AppState;
+use crate::store::StoreError;
+use crate::tenants::BasePath;
+
+// Upload limits
+pub const MAX_ATTACHMENT_BYTES: usize = 10 * 1024 * 1024;
+const ALLOWED_CONTENT_TYPES: [&str; 4] = ["image/jpeg", "image/png", "image/webp", "application/pdf"];
+const THUMBNAIL_SIZE: u32 = 256;
+// Thumbnails are stored next to the original as "{name}.thumb.png"
+const THUMBNAIL_SUFFIX: &str = ".thumb.png";
+
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub struct AttachmentInfo {
+    pub name: String,
+    pub content_type: String,
+    pub length: u64,
+    pub url: String,
+    pub thumbnail_url: Option<String>,
+}
+
+// Keep attachment names URL-safe so they can be used directly as path segments in any store
+fn sanitize_name(raw: &str) -> Option<String> {
+    let base = raw.rsplit(['/', '\\']).next().unwrap_or(raw).trim();
+    let name: String = base
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' })
+        .collect();
+    let name = name.trim_start_matches('.').to_string();
+    if name.is_empty() || name.len() > 100 || name.ends_with(THUMBNAIL_SUFFIX) {
+        return None;
+    }
+    Some(name)
+}
+
+// The declared type must agree with the file's magic bytes, so a script sent as image/png is not
+// stored and later served under that type
+fn content_matches(content_type: &str, bytes: &[u8]) -> bool {
+    if content_type == "application/pdf" {
+        return bytes.starts_with(b"%PDF-");
+    }
+    match (ImageFormat::from_mime_type(content_type), image::guess_format(bytes)) {
+        (Some(declared), Ok(sniffed)) => declared == sniffed,
+        _ => false,
+    }
+}
+
+fn thumbnail_name(name: &str) -> String {
+    format!("{}{}", name, THUMBNAIL_SUFFIX)
+}
+
+fn render_thumbnail(bytes: &[u8]) -> Option<Vec<u8>> {
+    let image = image::load_from_memory(bytes).ok()?;
+    let thumb = image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+    let mut buffer: Vec<u8> = Vec::new();
+    thumb.write_to(&mut Cursor::new(&mut buffer), ImageFormat::Png).ok()?;
+    Some(buffer)
+}
+
+// Attachments of a herb, with thumbnails folded into their originals; URLs start with `base`
+pub async fn list_for_herb(state: &AppState, herb_id: &str, base: &str) -> Result<Vec<AttachmentInfo>, StoreError> {
+    let raw = state.docs.list_files(herb_id).await?;
+    let mut attachments: Vec<AttachmentInfo> = raw
+        .iter()
+        .filter(|file| !file.name.ends_with(THUMBNAIL_SUFFIX))
+        .map(|FileInfo { name, content_type, length }| {
+            let has_thumb = raw.iter().any(|other| other.name == thumbnail_name(name));
+            AttachmentInfo {
+                name: name.clone(),
+                content_type: content_type.clone(),
+                length: *length,
+                url: format!("{}/herbs/{}/attachments/{}", base, herb_id, name),
+                thumbnail_url: has_thumb
+                    .then(|| format!("{}/herbs/{}/attachments/{}/thumbnail", base, herb_id, name)),
+            }
+        })
+        .collect();
+    attachments.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(attachments)
+}
+
+async fn stream_attachment(state: &AppState, herb_id: &str, name: &str) -> axum::response::Response {
+    match state.docs.get_file(herb_id, name).await {
+        Ok(file) => {
+            let disposition = format!("inline; filename=\"{}\"", name);
+            (
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, file.content_type), (header::CONTENT_DISPOSITION, disposition)],
+                file.body,
+            )
+                .into_response()
+        }
+        Err(StoreError::NotFound) => (StatusCode::NOT_FOUND, "Attachment not found").into_response(),
+        Err(err) => {
+            eprintln!("get_attachment failed for {}/{}: {}", herb_id, name, err);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch attachment").into_response()
+        }
+    }
+}
+
+// Handlers
+
+// POST /herbs/{id}/attachments - multipart/form-data, one or more file fields
+#[utoipa::path(
+    post,
+    path = "/herbs/{id}/attachments",
+    tag = "attachments",
+    params(("id" = String, Path, description = "Herb id")),
+    request_body(content_type = "multipart/form-data", description = "One or more file fields (JPEG, PNG, WebP or PDF)"),
+    responses(
+        (status = 201, description = "Uploaded attachments", body = [AttachmentInfo]),
+        (status = 400, description = "Invalid upload"),
+        (status = 404, description = "Herb not found"),
+        (status = 413, description = "File too large"),
+        (status = 415, description = "Unsupported content type, or content that does not match it"),
+    ),
+)]
+pub async fn upload_attachments(
+    State(state): State<AppState>,
+    Path(herb_id): Path<String>,
+    base: BasePath,
+    mut multipart: Multipart,
+) -> impl IntoResponse {
+    if let Err(err) = state.herbs.get(&herb_id).await {
+        eprintln!("upload_attachments could not fetch herb {}: {}", herb_id, err);
+        return (StatusCode::NOT_FOUND, "Herb not found").into_response();
+    }
+
+    let mut uploaded = Vec::new();
+    loop {
+        let mut field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(err) => return (StatusCode::BAD_REQUEST, format!("invalid multipart body: {}", err)).into_response(),
+        };
+        let Some(file_name) = field.file_name().map(str::to_string) else {
+            continue; // plain form fields are ignored
+        };
+        let Some(name) = sanitize_name(&file_name) else {
+            return (StatusCode::BAD_REQUEST, "invalid file name").into_response();
+        };
+        let content_type = field.content_type().unwrap_or("application/octet-stream").to_string();
+        if !ALLOWED_CONTENT_TYPES.contains(&content_type.as_str()) {
+            return (StatusCode::UNSUPPORTED_MEDIA_TYPE, format!("unsupported content type: {}", content_type)).into_response();
+        }
+
+        let mut bytes: Vec<u8> = Vec::new();
+        loop {
+            match field.chunk().await {
+                Ok(Some(chunk)) => {
+                    if bytes.len() + chunk.len() > MAX_ATTACHMENT_BYTES {
+                        return (StatusCode::PAYLOAD_TOO_LARGE, format!("{} exceeds {} bytes", name, MAX_ATTACHMENT_BYTES)).into_response();
+                    }
+                    bytes.extend_from_slice(&chunk);
+                }
+                Ok(None) => break,
+                Err(err) => return (StatusCode::BAD_REQUEST, format!("failed to read {}: {}", name, err)).into_response(),
+            }
+        }
+        if bytes.is_empty() {
+            return (StatusCode::BAD_REQUEST, format!("{} is empty", name)).into_response();
+        }
+        if !content_matches(&content_type, &bytes) {
+            return (StatusCode::UNSUPPORTED_MEDIA_TYPE, format!("{} is not a valid {} file", name, content_type)).into_response();
+        }
+
+        let thumbnail = if content_type.starts_with("image/") {
+            let source = bytes.clone();
+            tokio::task::spawn_blocking(move || render_thumbnail(&source)).await.ok().flatten()
+        } else {
+            None
+        };
+
+        let length = bytes.len() as u64;
+        if let Err(err) = state.docs.put_file(&herb_id, &name, &content_type, bytes).await {
+            eprintln!("put_attachment failed for {}/{}: {}", herb_id, name, err);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to store attachment").into_response();
+        }
+        let mut thumbnail_url = None;
+        if let Some(thumb) = thumbnail {
+            match state.docs.put_file(&herb_id, &thumbnail_name(&name), "image/png", thumb).await {
+                Ok(_) => thumbnail_url = Some(format!("{}/herbs/{}/attachments/{}/thumbnail", base.0, herb_id, name)),
+                Err(err) => eprintln!("thumbnail upload failed for {}/{}: {}", herb_id, name, err),
+            }
+        }
+
+        uploaded.push(AttachmentInfo {
+            url: format!("{}/herbs/{}/attachments/{}", base.0, herb_id, name),
+            name,
+            content_type,
+            length,
+            thumbnail_url,
+        });
+    }
+
+    if uploaded.is_empty() {
+        return (StatusCode::BAD_REQUEST, "no files in request").into_response();
+    }
+    (StatusCode::CREATED, Json(uploaded)).into_response()
+}
+
+// GET /herbs/{id}/attachments
+#[utoipa::path(
+    get,
+    path = "/herbs/{id}/attachments",
+    tag = "attachments",
+    params(("id" = String, Path, description = "Herb id")),
+    responses((status = 200, description = "Attachments", body = [AttachmentInfo])),
+)]
+pub async fn list_attachments(
+    State(state): State<AppState>,
+    Path(herb_id): Path<String>,
+    base: BasePath,
+) -> impl IntoResponse {
+    match list_for_herb(&state, &herb_id, &base.0).await {
+        Ok(attachments) => (StatusCode::OK, Json(attachments)).into_response(),
+        Err(err) => {
+            eprintln!("list_attachments failed for herb {}: {}", herb_id, err);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch attachments").into_response()
+        }
+    }
+}
+
+// GET /herbs/{id}/attachments/{name} - Streamed from the store
+#[utoipa::path(
+    get,
+    path = "/herbs/{id}/attachments/{name}",
+    tag = "attachments",
+    params(("id" = String, Path, description = "Herb id"), ("name" = String, Path)),
+    responses(
+        (status = 200, description = "Attachment content", content(("image/jpeg"), ("image/png"), ("image/webp"), ("application/pdf"))),
+        (status = 404, description = "Attachment not found"),
+        (status = 500, description = "Failed to fetch attachment"),
+    ),
+)]
+pub async fn download_attachment(
+    State(state): State<AppState>,
+    Path((herb_id, name)): Path<(String, String)>,
+) -> impl IntoResponse {
+    let Some(name) = sanitize_name(&name) else {
+        return (StatusCode::NOT_FOUND, "Attachment not found").into_response();
+    };
+    stream_attachment(&state, &herb_id, &name).await
+}
+
+// GET /herbs/{id}/attachments/{name}/thumbnail
+#[utoipa::path(
+    get,
+    path = "/herbs/{id}/attachments/{name}/thumbnail",
+    tag = "attachments",
+    params(("id" = String, Path, description = "Herb id"), ("name" = String, Path)),
+    responses(
+        (status = 200, description = "256px PNG thumbnail", content_type = "image/png"),
+        (status = 404, description = "Thumbnail not found"),
+        (status = 500, description = "Failed to fetch attachment"),
+    ),
+)]
+pub async fn download_thumbnail(
+    State(state): State<AppState>,
+    Path((herb_id, name)): Path<(String, String)>,
+) -> impl IntoResponse {
+    let Some(name) = sanitize_name(&name) else {
+        return (StatusCode::NOT_FOUND, "Thumbnail not found").into_response();
+    };
+    stream_attachment(&state, &herb_id, &thumbnail_name(&name)).await
+}
+
+// DELETE /herbs/{id}/attachments/{name}
+#[utoipa::path(
+    delete,
+    path = "/herbs/{id}/attachments/{name}",
+    tag = "attachments",
+    params(("id" = String, Path, description = "Herb id"), ("name" = String, Path)),
+    responses(
+        (status = 200, description = "Attachment deleted"),
+        (status = 404, description = "Attachment not found"),
+    ),
+)]
+pub async fn delete_attachment(
+    State(state): State<AppState>,
+    Path((herb_id, name)): Path<(String, String)>,
+) -> impl IntoResponse {
+    let Some(name) = sanitize_name(&name) else {
+        return (StatusCode::NOT_FOUND, "Attachment not found").into_response();
+    };
+    let existing = match state.docs.list_files(&herb_id).await {
+        Ok(existing) => existing,
+        Err(err) => {
+            eprintln!("delete_attachment lookup failed for {}/{}: {}", herb_id, name, err);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete attachment").into_response();
+        }
+    };
+    if !existing.iter().any(|file| file.name == name) {
+        return (StatusCode::NOT_FOUND, "Attachment not found").into_response();
+    }
+
+    let thumb = thumbnail_name(&name);
+    let mut targets = vec![name.clone()];
+    if existing.iter().any(|file| file.name == thumb) {
+        targets.push(thumb);
+    }
+    for target in targets {
+        if let Err(err) = state.docs.delete_file(&herb_id, &target).await {
+            eprintln!("delete_attachment failed for {}/{}: {}", herb_id, target, err);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete attachment").into_response();
+        }
+    }
+    (StatusCode::OK, format!("Attachment {} deleted successfully", name)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::test_herb;
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    const BOUNDARY: &str = "XXBOUNDARY";
+
+    fn png() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        image::RgbImage::new(4, 4).write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png).unwrap();
+        bytes
+    }
+
+    // A herb in a fresh store and an upload of one file to it, through the app's routes so their
+    // body limit applies
+    async fn upload(file_name: &str, content_type: &str, bytes: &[u8]) -> (AppState, String, StatusCode) {
+        let state = AppState::for_tests();
+        let herb = test_herb("Tulsi", "Asha");
+        state.herbs.put(&herb).await.unwrap();
+
+        let mut body = format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+            BOUNDARY, file_name, content_type,
+        )
+        .into_bytes();
+        body.extend_from_slice(bytes);
+        body.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+        let request = Request::post(format!("/herbs/{}/attachments", herb.id))
+            .header(header::CONTENT_TYPE, format!("multipart/form-data; boundary={}", BOUNDARY))
+            .body(Body::from(body))
+            .unwrap();
+        let response = crate::tenant_app(state.clone()).oneshot(request).await.unwrap();
+        (state, herb.id, response.status())
+    }
+
+    #[test]
+    fn names_are_reduced_to_a_safe_base_name() {
+        assert_eq!(sanitize_name("../../etc/passwd").as_deref(), Some("passwd"));
+        assert_eq!(sanitize_name("C:\\scans\\coa 2026.pdf").as_deref(), Some("coa_2026.pdf"));
+        assert_eq!(sanitize_name(".htaccess").as_deref(), Some("htaccess"));
+        assert_eq!(sanitize_name("तुलसी.jpg").as_deref(), Some("_____.jpg"));
+        assert_eq!(sanitize_name("photo.jpg.thumb.png"), None);
+        assert_eq!(sanitize_name("..."), None);
+        assert_eq!(sanitize_name(&"a".repeat(101)), None);
+    }
+
+    #[test]
+    fn magic_bytes_must_match_the_declared_type() {
+        assert!(content_matches("image/png", &png()));
+        assert!(!content_matches("image/jpeg", &png()));
+        assert!(content_matches("application/pdf", b"%PDF-1.7\n"));
+        assert!(!content_matches("image/png", b"<script>alert(1)</script>"));
+        assert!(!content_matches("application/pdf", &png()));
+    }
+
+    #[tokio::test]
+    async fn images_are_stored_with_a_thumbnail() {
+        let (state, herb_id, status) = upload("leaf.png", "image/png", &png()).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let listed = list_for_herb(&state, &herb_id, "").await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert!(listed[0].thumbnail_url.is_some());
+        let listed = list_for_herb(&state, &herb_id, "/t/acme").await.unwrap();
+        assert_eq!(listed[0].url, format!("/t/acme/herbs/{}/attachments/leaf.png", herb_id));
+        assert_eq!(listed[0].thumbnail_url, Some(format!("{}/thumbnail", listed[0].url)));
+    }
+
+    #[tokio::test]
+    async fn uploads_outside_the_allowlist_or_limit_are_refused() {
+        let (_, _, status) = upload("page.html", "text/html", b"<html></html>").await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let (_, _, status) = upload("fake.png", "image/png", b"<svg onload=alert(1)>").await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let (_, _, status) = upload("big.pdf", "application/pdf", &vec![b'x'; MAX_ATTACHMENT_BYTES + 1]).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -1,6 +1,41 @@
+use async_trait::async_trait;
+use axum::body::Body;
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::time::Instant;
+use url::Url;
+use utoipa::ToSchema;
+use crate::docs::{Collection, DocQuery, DocStore, FileContent, FileInfo};
+use crate::handlers::Herb;
+use crate::metrics;
+use crate::store::{HerbQuery, HerbStore, StoreError};
+
+// Sends a request, recording its latency under the CouchDb method that made it
+trait Timed {
+    async fn timed(self, method: &'static str) -> Result<reqwest::Response, reqwest::Error>;
+}
+
+impl Timed for reqwest::RequestBuilder {
+    async fn timed(self, method: &'static str) -> Result<reqwest::Response, reqwest::Error> {
+        let started = Instant::now();
+        let result = self.send().await;
+        let outcome = match &result {
+            Ok(res) => format!("{}xx", res.status().as_u16() / 100),
+            Err(_) => "error".to_string(),
+        };
+        metrics::couch_request(method, &outcome, started.elapsed());
+        result
+    }
+}
+
+// Upper bound on documents returned by a single Mango query (CouchDB defaults to 25)
+const FIND_LIMIT: usize = 10_000;
+// Design document holding the backend's Mango indexes
+pub const INDEX_DDOC: &str = "backend";
+// Indexes behind CouchHerbStore::find
+const HERB_INDEXES: [(&str, &[&str]); 2] = [("by-farmer", &["farmer"]), ("by-gtin-lot", &["gtin", "lot"])];
 
 #[derive(Clone)]
 pub struct CouchDb {
@@ -25,7 +60,7 @@ impl CouchDb {
         self.client
             .put(&url)
             .basic_auth(&self.username, Some(&self.password))
-            .send()
+            .timed("create_db")
             .await?
             .error_for_status()?;
         Ok(())
@@ -36,12 +71,101 @@ impl CouchDb {
         self.client
             .delete(&url)
             .basic_auth(&self.username, Some(&self.password))
-            .send()
+            .timed("delete_db")
             .await?;
         // Ignore error_for_status here to allow deleting non-existent DB without failing
         Ok(())
     }
 
+    pub async fn ensure_db(&self, db: &str) -> Result<(), reqwest::Error> {
+        let url = format!("{}/{}", self.base_url, db);
+        let res = self.client
+            .put(&url)
+            .basic_auth(&self.username, Some(&self.password))
+            .timed("ensure_db")
+            .await?;
+        // 412 Precondition Failed means the database already exists
+        if res.status() == reqwest::StatusCode::PRECONDITION_FAILED {
+            return Ok(());
+        }
+        res.error_for_status()?;
+        Ok(())
+    }
+
+    // Who CouchDB thinks we are (GET /_session); wrong credentials fail with 401, and None
+    // means the server let us in anonymously
+    pub async fn session_user(&self) -> Result<Option<String>, reqwest::Error> {
+        let url = format!("{}/_session", self.base_url);
+        let res = self
+            .client
+            .get(&url)
+            .basic_auth(&self.username, Some(&self.password))
+            .timed("session_user")
+            .await?
+            .error_for_status()?
+            .json::<Value>()
+            .await?;
+        Ok(res.pointer("/userCtx/name").and_then(|v| v.as_str()).map(str::to_string))
+    }
+
+    // Documents in a database, not counting design documents
+    pub async fn doc_count(&self, db: &str) -> Result<u64, reqwest::Error> {
+        let url = format!("{}/{}", self.base_url, db);
+        let info = self
+            .client
+            .get(&url)
+            .basic_auth(&self.username, Some(&self.password))
+            .timed("doc_count")
+            .await?
+            .error_for_status()?
+            .json::<Value>()
+            .await?;
+        let url = format!("{}/{}/_design_docs", self.base_url, db);
+        let design = self
+            .client
+            .get(&url)
+            .basic_auth(&self.username, Some(&self.password))
+            .timed("doc_count")
+            .await?
+            .error_for_status()?
+            .json::<Value>()
+            .await?;
+        let total = info.get("doc_count").and_then(|v| v.as_u64()).unwrap_or(0);
+        let design = design.get("rows").and_then(|v| v.as_array()).map_or(0, |rows| rows.len() as u64);
+        Ok(total.saturating_sub(design))
+    }
+
+    // Mango index via POST /{db}/_index; CouchDB answers "exists" for an identical index,
+    // so this is safe to repeat
+    pub async fn ensure_index(&self, db: &str, ddoc: &str, name: &str, fields: &[&str]) -> Result<(), reqwest::Error> {
+        let url = format!("{}/{}/_index", self.base_url, db);
+        self.client
+            .post(&url)
+            .basic_auth(&self.username, Some(&self.password))
+            .json(&serde_json::json!({
+                "index": { "fields": fields },
+                "ddoc": ddoc,
+                "name": name,
+                "type": "json",
+            }))
+            .timed("ensure_index")
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    // URL of a document (or attachment) path. Each part is percent-encoded as one segment, so an
+    // id holding '/' or '..' cannot climb out of its database.
+    fn doc_url(&self, parts: &[&str]) -> String {
+        let Ok(mut url) = Url::parse(&self.base_url) else {
+            return format!("{}/{}", self.base_url, parts.join("/"));
+        };
+        if let Ok(mut path) = url.path_segments_mut() {
+            path.pop_if_empty().extend(parts);
+        }
+        url.into()
+    }
+
     pub async fn reset_db(&self, db: &str) -> Result<(), reqwest::Error> {
         // Best-effort delete, then create
         let _ = self.delete_db(db).await;
@@ -49,12 +173,12 @@ impl CouchDb {
     }
 
     pub async fn add_doc<T: Serialize>(&self, db: &str, id: &str, doc: &T) -> Result<(), reqwest::Error> {
-        let url = format!("{}/{}/{}", self.base_url, db, id);
+        let url = self.doc_url(&[db, id]);
         self.client
             .put(&url) // Use PUT with ID for deterministic IDs
             .basic_auth(&self.username, Some(&self.password))
             .json(doc)
-            .send()
+            .timed("add_doc")
             .await?
             .error_for_status()?;
         Ok(())
@@ -65,12 +189,12 @@ impl CouchDb {
         db: &str,
         id: &str,
     ) -> Result<T, reqwest::Error> {
-        let url = format!("{}/{}/{}", self.base_url, db, id);
+        let url = self.doc_url(&[db, id]);
         let res = self
             .client
             .get(&url)
             .basic_auth(&self.username, Some(&self.password))
-            .send()
+            .timed("get_doc")
             .await?
             .error_for_status()?
             .json::<T>()
@@ -78,17 +202,14 @@ impl CouchDb {
         Ok(res)
     }
 
-    pub async fn get_doc_with_rev<T: for<'de> Deserialize<'de>>(
-        &self,
-        db: &str,
-        id: &str,
-    ) -> Result<(T, String), reqwest::Error> {
-        let url = format!("{}/{}/{}", self.base_url, db, id);
+    // The raw document and its _rev; callers decide what a document of the wrong shape means
+    pub async fn get_doc_with_rev(&self, db: &str, id: &str) -> Result<(Value, String), reqwest::Error> {
+        let url = self.doc_url(&[db, id]);
         let value = self
             .client
             .get(&url)
             .basic_auth(&self.username, Some(&self.password))
-            .send()
+            .timed("get_doc_with_rev")
             .await?
             .error_for_status()?
             .json::<Value>()
@@ -98,8 +219,7 @@ impl CouchDb {
             .and_then(|v| v.as_str())
             .unwrap_or_default()
             .to_string();
-        let doc: T = serde_json::from_value(value).unwrap();
-        Ok((doc, rev))
+        Ok((value, rev))
     }
 
     pub async fn update_doc<T: Serialize>(
@@ -108,70 +228,930 @@ impl CouchDb {
         id: &str,
         rev: &str,
         doc: &T,
-    ) -> Result<(), reqwest::Error> {
+    ) -> Result<String, reqwest::Error> {
         let mut value = serde_json::to_value(doc).expect("serialize doc");
         if let Value::Object(ref mut map) = value {
             map.insert("_rev".to_string(), Value::String(rev.to_string()));
         }
-        let url = format!("{}/{}/{}", self.base_url, db, id);
-        self.client
+        let url = self.doc_url(&[db, id]);
+        let res = self.client
             .put(&url)
             .basic_auth(&self.username, Some(&self.password))
             .json(&value)
-            .send()
+            .timed("update_doc")
             .await?
-            .error_for_status()?;
-        Ok(())
+            .error_for_status()?
+            .json::<Value>()
+            .await?;
+        // The new revision
+        Ok(res.get("rev").and_then(|v| v.as_str()).unwrap_or_default().to_string())
     }
 
-    pub async fn list_docs<T: for<'de> Deserialize<'de>>(
+    // Insert or overwrite a document, fetching the current _rev first if it exists
+    pub async fn upsert_doc<T: Serialize>(&self, db: &str, id: &str, doc: &T) -> Result<(), reqwest::Error> {
+        let url = self.doc_url(&[db, id]);
+        let res = self.client
+            .get(&url)
+            .basic_auth(&self.username, Some(&self.password))
+            .timed("upsert_doc")
+            .await?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return self.add_doc(db, id, doc).await;
+        }
+        let current = res.error_for_status()?.json::<Value>().await?;
+        let rev = current.get("_rev").and_then(|v| v.as_str()).unwrap_or_default();
+        self.update_doc(db, id, rev, doc).await.map(|_| ())
+    }
+
+    // Mango query via POST /{db}/_find; documents that do not deserialize into T are skipped
+    pub async fn find_docs<T: for<'de> Deserialize<'de>>(
         &self,
         db: &str,
+        selector: Value,
     ) -> Result<Vec<T>, reqwest::Error> {
-        let url = format!("{}/_all_docs?include_docs=true", format!("{}/{}", self.base_url, db));
+        let url = format!("{}/{}/_find", self.base_url, db);
         let res = self
             .client
-            .get(&url)
+            .post(&url)
             .basic_auth(&self.username, Some(&self.password))
-            .send()
+            .json(&serde_json::json!({ "selector": selector, "limit": FIND_LIMIT }))
+            .timed("find_docs")
             .await?
             .error_for_status()?
             .json::<Value>()
             .await?;
 
-        let mut herbs = Vec::new();
+        let docs = res
+            .get("docs")
+            .and_then(|v| v.as_array())
+            .map(|docs| {
+                docs.iter()
+                    .filter_map(|doc| serde_json::from_value(doc.clone()).ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(docs)
+    }
+
+    // One page of a Mango query, continuing from the bookmark of the previous page. Also returns
+    // the bookmark to continue from, or None once a page came back short. `fields` trims the
+    // returned documents.
+    pub async fn find_page(
+        &self,
+        db: &str,
+        selector: Value,
+        bookmark: Option<&str>,
+        limit: usize,
+        fields: Option<&[&str]>,
+    ) -> Result<(Vec<Value>, Option<String>), reqwest::Error> {
+        let url = format!("{}/{}/_find", self.base_url, db);
+        let mut body = serde_json::json!({ "selector": selector, "limit": limit });
+        if let Some(bookmark) = bookmark {
+            body["bookmark"] = serde_json::json!(bookmark);
+        }
+        if let Some(fields) = fields {
+            body["fields"] = serde_json::json!(fields);
+        }
+        let res = self
+            .client
+            .post(&url)
+            .basic_auth(&self.username, Some(&self.password))
+            .json(&body)
+            .timed("find_page")
+            .await?
+            .error_for_status()?
+            .json::<Value>()
+            .await?;
+        let docs = res.get("docs").and_then(|v| v.as_array()).cloned().unwrap_or_default();
+        let next = if docs.len() < limit {
+            None
+        } else {
+            res.get("bookmark").and_then(|v| v.as_str()).map(str::to_string)
+        };
+        Ok((docs, next))
+    }
+
+    // Write many documents in one request via POST /{db}/_bulk_docs.
+    // Returns CouchDB's per-document result rows ({id, rev} or {id, error, reason}).
+    pub async fn bulk_docs<T: Serialize>(&self, db: &str, docs: &[T]) -> Result<Vec<Value>, reqwest::Error> {
+        let url = format!("{}/{}/_bulk_docs", self.base_url, db);
+        let res = self
+            .client
+            .post(&url)
+            .basic_auth(&self.username, Some(&self.password))
+            .json(&serde_json::json!({ "docs": docs }))
+            .timed("bulk_docs")
+            .await?
+            .error_for_status()?
+            .json::<Vec<Value>>()
+            .await?;
+        Ok(res)
+    }
+
+    // Fetch several documents in one request (POST /{db}/_all_docs with keys and include_docs).
+    // Missing, deleted or undeserializable documents are skipped; order follows `ids`.
+    pub async fn get_docs<T: for<'de> Deserialize<'de>>(&self, db: &str, ids: &[String]) -> Result<Vec<T>, reqwest::Error> {
+        let url = format!("{}/{}/_all_docs?include_docs=true", self.base_url, db);
+        let res = self
+            .client
+            .post(&url)
+            .basic_auth(&self.username, Some(&self.password))
+            .json(&serde_json::json!({ "keys": ids }))
+            .timed("get_docs")
+            .await?
+            .error_for_status()?
+            .json::<Value>()
+            .await?;
+
+        let docs = res
+            .get("rows")
+            .and_then(|v| v.as_array())
+            .map(|rows| {
+                rows.iter()
+                    .filter_map(|row| row.get("doc"))
+                    .filter(|doc| !doc.is_null())
+                    .filter_map(|doc| serde_json::from_value(doc.clone()).ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(docs)
+    }
+
+    // Which of the given ids already exist (POST /{db}/_all_docs with keys)
+    pub async fn existing_ids(&self, db: &str, ids: &[String]) -> Result<Vec<String>, reqwest::Error> {
+        let url = format!("{}/{}/_all_docs", self.base_url, db);
+        let res = self
+            .client
+            .post(&url)
+            .basic_auth(&self.username, Some(&self.password))
+            .json(&serde_json::json!({ "keys": ids }))
+            .timed("existing_ids")
+            .await?
+            .error_for_status()?
+            .json::<Value>()
+            .await?;
+
+        let mut existing = Vec::new();
         if let Some(rows) = res.get("rows").and_then(|v| v.as_array()) {
             for row in rows {
-                if let Some(doc) = row.get("doc") {
-                    let herb: T = serde_json::from_value(doc.clone()).unwrap();
-                    herbs.push(herb);
+                // Missing keys come back as {"key": ..., "error": "not_found"}; deleted docs carry value.deleted
+                let deleted = row.pointer("/value/deleted").and_then(|v| v.as_bool()).unwrap_or(false);
+                if row.get("error").is_none() && !deleted {
+                    if let Some(id) = row.get("id").and_then(|v| v.as_str()) {
+                        existing.push(id.to_string());
+                    }
                 }
             }
         }
-        Ok(herbs)
+        Ok(existing)
+    }
+
+    pub async fn list_docs<T: for<'de> Deserialize<'de>>(
+        &self,
+        db: &str,
+    ) -> Result<Vec<T>, reqwest::Error> {
+        let url = format!("{}/{}/_all_docs?include_docs=true", self.base_url, db);
+        let res = self
+            .client
+            .get(&url)
+            .basic_auth(&self.username, Some(&self.password))
+            .timed("list_docs")
+            .await?
+            .error_for_status()?
+            .json::<Value>()
+            .await?;
+
+        // Design documents (indexes) and documents that do not deserialize into T are skipped
+        let docs = res
+            .get("rows")
+            .and_then(|v| v.as_array())
+            .map(|rows| {
+                rows.iter()
+                    .filter(|row| !row.get("id").and_then(|v| v.as_str()).unwrap_or_default().starts_with("_design/"))
+                    .filter_map(|row| row.get("doc"))
+                    .filter_map(|doc| serde_json::from_value(doc.clone()).ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(docs)
+    }
+
+    // One page of _all_docs in id order, starting after `after`. Also returns the last id
+    // seen so callers can continue, or None once the final page has been read.
+    // Design documents are skipped.
+    pub async fn list_docs_page<T: for<'de> Deserialize<'de>>(
+        &self,
+        db: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<(Vec<T>, Option<String>), reqwest::Error> {
+        let url = format!("{}/{}/_all_docs", self.base_url, db);
+        let mut query = vec![("include_docs", "true".to_string()), ("limit", limit.to_string())];
+        if let Some(after) = after {
+            query.push(("startkey", Value::String(after.to_string()).to_string()));
+            query.push(("skip", "1".to_string()));
+        }
+        let res = self
+            .client
+            .get(&url)
+            .basic_auth(&self.username, Some(&self.password))
+            .query(&query)
+            .timed("list_docs_page")
+            .await?
+            .error_for_status()?
+            .json::<Value>()
+            .await?;
+
+        let rows = res.get("rows").and_then(|v| v.as_array()).cloned().unwrap_or_default();
+        let last_id = rows
+            .last()
+            .and_then(|row| row.get("id"))
+            .and_then(|v| v.as_str())
+            .map(str::to_string);
+        let mut docs = Vec::new();
+        for row in &rows {
+            let id = row.get("id").and_then(|v| v.as_str()).unwrap_or_default();
+            if id.starts_with("_design/") {
+                continue;
+            }
+            match row.get("doc").map(|doc| serde_json::from_value(doc.clone())) {
+                Some(Ok(doc)) => docs.push(doc),
+                _ => eprintln!("Skipping unreadable document {} in {}", id, db),
+            }
+        }
+        let next = if rows.len() < limit { None } else { last_id };
+        Ok((docs, next))
     }
 
     pub async fn delete_doc(&self, db: &str, id: &str) -> Result<(), reqwest::Error> {
-        let doc_url = format!("{}/{}/{}", self.base_url, db, id);
+        let doc_url = self.doc_url(&[db, id]);
         let res = self.client
             .get(&doc_url)
             .basic_auth(&self.username, Some(&self.password))
-            .send()
+            .timed("delete_doc")
             .await?
             .error_for_status()?
             .json::<serde_json::Value>()
             .await?;
 
         if let Some(rev) = res.get("_rev").and_then(|v| v.as_str()) {
-            let delete_url = format!("{}?rev={}", doc_url, rev);
-            self.client
-                .delete(&delete_url)
-                .basic_auth(&self.username, Some(&self.password))
-                .send()
-                .await?
-                .error_for_status()?;
+            self.delete_doc_rev(db, id, rev).await?;
+        }
+
+        Ok(())
+    }
+
+    // Delete a specific revision; fails with 409 if it is no longer the current one
+    pub async fn delete_doc_rev(&self, db: &str, id: &str, rev: &str) -> Result<(), reqwest::Error> {
+        let url = self.doc_url(&[db, id]);
+        self.client
+            .delete(&url)
+            .basic_auth(&self.username, Some(&self.password))
+            .query(&[("rev", rev)])
+            .timed("delete_doc_rev")
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    // Current _rev of a document, or None if it does not exist (HEAD returns it as the ETag)
+    pub async fn doc_rev(&self, db: &str, id: &str) -> Result<Option<String>, reqwest::Error> {
+        let url = self.doc_url(&[db, id]);
+        let res = self.client
+            .head(&url)
+            .basic_auth(&self.username, Some(&self.password))
+            .timed("doc_rev")
+            .await?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let res = res.error_for_status()?;
+        let rev = res
+            .headers()
+            .get(reqwest::header::ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.trim_matches('"').to_string());
+        Ok(rev)
+    }
+
+    // Store an attachment on a document, creating the document if needed. Returns the new _rev.
+    pub async fn put_attachment(
+        &self,
+        db: &str,
+        id: &str,
+        name: &str,
+        content_type: &str,
+        bytes: Vec<u8>,
+    ) -> Result<String, reqwest::Error> {
+        let mut url = self.doc_url(&[db, id, name]);
+        if let Some(rev) = self.doc_rev(db, id).await? {
+            url = format!("{}?rev={}", url, rev);
+        }
+        let res = self.client
+            .put(&url)
+            .basic_auth(&self.username, Some(&self.password))
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .body(bytes)
+            .timed("put_attachment")
+            .await?
+            .error_for_status()?
+            .json::<Value>()
+            .await?;
+        Ok(res.get("rev").and_then(|v| v.as_str()).unwrap_or_default().to_string())
+    }
+
+    // Raw attachment response so callers can stream the body instead of buffering it
+    pub async fn get_attachment(&self, db: &str, id: &str, name: &str) -> Result<reqwest::Response, reqwest::Error> {
+        let url = self.doc_url(&[db, id, name]);
+        self.client
+            .get(&url)
+            .basic_auth(&self.username, Some(&self.password))
+            .timed("get_attachment")
+            .await?
+            .error_for_status()
+    }
+
+    pub async fn delete_attachment(&self, db: &str, id: &str, name: &str) -> Result<(), reqwest::Error> {
+        let Some(rev) = self.doc_rev(db, id).await? else {
+            return Ok(());
+        };
+        let url = format!("{}?rev={}", self.doc_url(&[db, id, name]), rev);
+        self.client
+            .delete(&url)
+            .basic_auth(&self.username, Some(&self.password))
+            .timed("delete_attachment")
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    // Attachment stubs of a document as (name, content_type, length); empty if the document does not exist
+    pub async fn list_attachments(&self, db: &str, id: &str) -> Result<Vec<(String, String, u64)>, reqwest::Error> {
+        let url = self.doc_url(&[db, id]);
+        let res = self.client
+            .get(&url)
+            .basic_auth(&self.username, Some(&self.password))
+            .timed("list_attachments")
+            .await?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(Vec::new());
+        }
+        let doc = res.error_for_status()?.json::<Value>().await?;
+        let mut attachments = Vec::new();
+        if let Some(map) = doc.get("_attachments").and_then(|v| v.as_object()) {
+            for (name, stub) in map {
+                let content_type = stub
+                    .get("content_type")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("application/octet-stream")
+                    .to_string();
+                let length = stub.get("length").and_then(|v| v.as_u64()).unwrap_or(0);
+                attachments.push((name.clone(), content_type, length));
+            }
+        }
+        Ok(attachments)
+    }
+
+    // Starts (or restarts with new settings) a replication by writing its _replicator document
+    pub async fn start_replication(&self, id: &str, doc: &ReplicationDoc) -> Result<(), reqwest::Error> {
+        self.ensure_db(REPLICATOR_DB).await?;
+        self.upsert_doc(REPLICATOR_DB, id, doc).await
+    }
+
+    // Stops a replication by deleting its _replicator document; stopping one that is not running is fine
+    pub async fn stop_replication(&self, id: &str) -> Result<(), reqwest::Error> {
+        match self.delete_doc(REPLICATOR_DB, id).await {
+            Err(e) if e.status() == Some(reqwest::StatusCode::NOT_FOUND) => Ok(()),
+            result => result,
+        }
+    }
+
+    // Scheduler state of a replication, or None if CouchDB has no job for it
+    // (not started, or not picked up by the scheduler yet)
+    pub async fn replication_status(&self, id: &str) -> Result<Option<ReplicationStatus>, reqwest::Error> {
+        let url = self.doc_url(&["_scheduler", "docs", REPLICATOR_DB, id]);
+        let res = self.client
+            .get(&url)
+            .basic_auth(&self.username, Some(&self.password))
+            .timed("replication_status")
+            .await?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let doc = res.error_for_status()?.json::<Value>().await?;
+        Ok(Some(ReplicationStatus::from_scheduler_doc(&doc)))
+    }
+}
+
+// CouchDB's replication database; the scheduler runs one job per document in it
+const REPLICATOR_DB: &str = "_replicator";
+
+// A _replicator document. Source and target are full database URLs, credentials included.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ReplicationDoc {
+    pub source: String,
+    pub target: String,
+    pub continuous: bool,
+    pub create_target: bool,
+}
+
+// One replication job as reported by /_scheduler/docs
+#[derive(Serialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct ReplicationStatus {
+    // initializing, running, pending, crashing, error, failed or completed
+    pub state: String,
+    pub docs_read: u64,
+    pub docs_written: u64,
+    pub doc_write_failures: u64,
+    // Source changes not yet replicated: how far the target lags behind
+    pub changes_pending: Option<u64>,
+    pub error_count: u64,
+    // Reason for the last failure while crashing, erroring or failed
+    pub error: Option<String>,
+    pub last_updated: Option<String>,
+}
+
+impl ReplicationStatus {
+    fn from_scheduler_doc(doc: &Value) -> Self {
+        let info = doc.get("info").filter(|v| v.is_object());
+        let count = |key: &str| info.and_then(|i| i.get(key)).and_then(|v| v.as_u64());
+        let text = |v: Option<&Value>| v.and_then(|v| v.as_str()).map(str::to_string);
+        Self {
+            state: text(doc.get("state")).unwrap_or_else(|| "unknown".to_string()),
+            docs_read: count("docs_read").unwrap_or(0),
+            docs_written: count("docs_written").unwrap_or(0),
+            doc_write_failures: count("doc_write_failures").unwrap_or(0),
+            changes_pending: count("changes_pending"),
+            error_count: doc.get("error_count").and_then(|v| v.as_u64()).unwrap_or(0),
+            error: text(info.and_then(|i| i.get("error"))),
+            last_updated: text(doc.get("last_updated")),
+        }
+    }
+}
+
+// Ids come from request paths. Ones with a '/', a dot segment or CouchDB's reserved '_' prefix
+// (_design, _local, _all_docs...) would address something other than a document, so the stores
+// treat them as missing.
+fn is_doc_id(id: &str) -> bool {
+    !id.is_empty() && !id.contains('/') && !id.starts_with('_') && id != "." && id != ".."
+}
+
+fn doc_id(id: &str) -> Result<&str, StoreError> {
+    if is_doc_id(id) { Ok(id) } else { Err(StoreError::NotFound) }
+}
+
+// The herbs database as a HerbStore
+#[derive(Clone)]
+pub struct CouchHerbStore {
+    couch: CouchDb,
+    db: String,
+}
+
+impl CouchHerbStore {
+    pub fn new(couch: CouchDb, db: &str) -> Self {
+        Self { couch, db: db.to_string() }
+    }
+}
+
+#[async_trait]
+impl HerbStore for CouchHerbStore {
+    async fn get(&self, id: &str) -> Result<(Herb, String), StoreError> {
+        let (doc, rev) = self.couch.get_doc_with_rev(&self.db, doc_id(id)?).await?;
+        // Anything in the herbs database that is not a herb is not one to callers
+        let herb = serde_json::from_value(doc).map_err(|err| {
+            eprintln!("document {} in {} is not a herb: {}", id, self.db, err);
+            StoreError::NotFound
+        })?;
+        Ok((herb, rev))
+    }
+
+    async fn rev(&self, id: &str) -> Result<Option<String>, StoreError> {
+        if !is_doc_id(id) {
+            return Ok(None);
+        }
+        Ok(self.couch.doc_rev(&self.db, id).await?)
+    }
+
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Herb>, StoreError> {
+        Ok(self.couch.get_docs(&self.db, ids).await?)
+    }
+
+    async fn existing_ids(&self, ids: &[String]) -> Result<Vec<String>, StoreError> {
+        Ok(self.couch.existing_ids(&self.db, ids).await?)
+    }
+
+    async fn put(&self, herb: &Herb) -> Result<(), StoreError> {
+        Ok(self.couch.add_doc(&self.db, doc_id(&herb.id)?, herb).await?)
+    }
+
+    async fn update(&self, herb: &Herb, rev: &str) -> Result<String, StoreError> {
+        Ok(self.couch.update_doc(&self.db, doc_id(&herb.id)?, rev, herb).await?)
+    }
+
+    async fn delete(&self, id: &str, rev: &str) -> Result<(), StoreError> {
+        Ok(self.couch.delete_doc_rev(&self.db, doc_id(id)?, rev).await?)
+    }
+
+    async fn list(&self) -> Result<Vec<Herb>, StoreError> {
+        Ok(self.couch.list_docs(&self.db).await?)
+    }
+
+    async fn list_page(&self, after: Option<&str>, limit: usize) -> Result<(Vec<Herb>, Option<String>), StoreError> {
+        Ok(self.couch.list_docs_page(&self.db, after, limit).await?)
+    }
+
+    async fn count(&self) -> Result<u64, StoreError> {
+        Ok(self.couch.doc_count(&self.db).await?)
+    }
+
+    async fn find(&self, query: &HerbQuery) -> Result<Vec<Herb>, StoreError> {
+        let mut selector = serde_json::json!({});
+        if !query.farmers.is_empty() {
+            selector["farmer"] = serde_json::json!({ "$in": query.farmers });
+        }
+        if let Some(gtin) = &query.gtin {
+            selector["gtin"] = serde_json::json!(gtin);
+        }
+        if let Some(lot) = &query.lot {
+            selector["lot"] = serde_json::json!(lot);
         }
+        let mut herbs = Vec::new();
+        let mut bookmark: Option<String> = None;
+        loop {
+            let (docs, next) = self.couch.find_page(&self.db, selector.clone(), bookmark.as_deref(), FIND_LIMIT, None).await?;
+            herbs.extend(docs.into_iter().filter_map(|doc| serde_json::from_value(doc).ok()));
+            match next {
+                Some(next) => bookmark = Some(next),
+                None => return Ok(herbs),
+            }
+        }
+    }
 
+    async fn put_many(&self, herbs: &[Herb]) -> Result<Vec<Result<(), StoreError>>, StoreError> {
+        let rows = self.couch.bulk_docs(&self.db, herbs).await?;
+        // _bulk_docs returns one row per submitted doc, in order
+        Ok(rows
+            .iter()
+            .map(|row| match row.get("error").and_then(|v| v.as_str()) {
+                None => Ok(()),
+                Some("conflict") => Err(StoreError::Conflict),
+                Some(error) => {
+                    let reason = row.get("reason").and_then(|v| v.as_str()).unwrap_or_default();
+                    Err(StoreError::Backend(format!("{}: {}", error, reason)))
+                }
+            })
+            .collect())
+    }
+
+    async fn reset(&self) -> Result<(), StoreError> {
+        self.couch.reset_db(&self.db).await?;
+        self.provision().await
+    }
+
+    async fn provision(&self) -> Result<(), StoreError> {
+        self.couch.ensure_db(&self.db).await?;
+        for (name, fields) in HERB_INDEXES {
+            self.couch.ensure_index(&self.db, INDEX_DDOC, name, fields).await?;
+        }
+        Ok(())
+    }
+}
+
+// Mango indexes behind CouchDocStore::find_page
+const COMPANION_INDEXES: [(Collection, &str, &[&str]); 5] = [
+    (Collection::LabResults, "by-herb", &["herb_id"]),
+    (Collection::Scans, "by-time", &["scanned_at"]),
+    (Collection::Scans, "by-herb-time", &["herb_id", "scanned_at"]),
+    (Collection::Alerts, "by-herb", &["herb_id"]),
+    (Collection::Alerts, "by-status", &["status"]),
+];
+
+// The companion databases of a herbs database as a DocStore: {db}_lab_results, {db}_specs,
+// {db}_scans and {db}_alerts, plus {db}_files with one document per herb holding its files as
+// CouchDB attachments
+#[derive(Clone)]
+pub struct CouchDocStore {
+    couch: CouchDb,
+    db: String,
+}
+
+impl CouchDocStore {
+    pub fn new(couch: CouchDb, db: &str) -> Self {
+        Self { couch, db: db.to_string() }
+    }
+
+    fn db(&self, collection: Collection) -> String {
+        format!("{}_{}", self.db, collection.name())
+    }
+
+    fn files_db(&self) -> String {
+        format!("{}_files", self.db)
+    }
+
+    // Stored timestamps are RFC 3339 strings in UTC, which sort lexically except for the
+    // variable-length fraction; the Mango range is widened by a second and DocQuery::matches
+    // applies the exact one
+    fn selector(collection: Collection, query: &DocQuery) -> Value {
+        let mut selector = serde_json::json!({});
+        match query.herb_ids.as_slice() {
+            [] => {}
+            [id] => selector["herb_id"] = serde_json::json!(id),
+            ids => selector["herb_id"] = serde_json::json!({ "$in": ids }),
+        }
+        if let Some(status) = &query.status {
+            selector["status"] = serde_json::json!(status);
+        }
+        if let Some(field) = collection.time_field() {
+            let bound = |t: DateTime<Utc>| t.to_rfc3339_opts(SecondsFormat::Secs, true);
+            let mut range = serde_json::Map::new();
+            if let Some(from) = query.from {
+                range.insert("$gte".to_string(), serde_json::json!(bound(from - Duration::seconds(1))));
+            }
+            if let Some(to) = query.to {
+                range.insert("$lt".to_string(), serde_json::json!(bound(to + Duration::seconds(1))));
+            }
+            if !range.is_empty() {
+                selector[field] = Value::Object(range);
+            }
+        }
+        if selector.as_object().is_some_and(|s| s.is_empty()) {
+            selector["_id"] = serde_json::json!({ "$gt": null });
+        }
+        selector
+    }
+}
+
+#[async_trait]
+impl DocStore for CouchDocStore {
+    async fn get(&self, collection: Collection, id: &str) -> Result<(Value, String), StoreError> {
+        Ok(self.couch.get_doc_with_rev(&self.db(collection), doc_id(id)?).await?)
+    }
+
+    async fn put(&self, collection: Collection, id: &str, doc: &Value) -> Result<(), StoreError> {
+        Ok(self.couch.add_doc(&self.db(collection), doc_id(id)?, doc).await?)
+    }
+
+    async fn update(&self, collection: Collection, id: &str, rev: &str, doc: &Value) -> Result<(), StoreError> {
+        self.couch.update_doc(&self.db(collection), doc_id(id)?, rev, doc).await?;
         Ok(())
     }
-}
\ No newline at end of file
+
+    async fn delete(&self, collection: Collection, id: &str) -> Result<(), StoreError> {
+        Ok(self.couch.delete_doc(&self.db(collection), doc_id(id)?).await?)
+    }
+
+    async fn find_page(
+        &self,
+        collection: Collection,
+        query: &DocQuery,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<(Vec<Value>, Option<String>), StoreError> {
+        let selector = Self::selector(collection, query);
+        let (mut docs, next) = self.couch.find_page(&self.db(collection), selector, after, limit, None).await?;
+        docs.retain(|doc| query.matches(collection, doc));
+        Ok((docs, next))
+    }
+
+    // Pages through only the fields DocQuery::matches looks at
+    async fn count(&self, collection: Collection, query: &DocQuery) -> Result<u64, StoreError> {
+        let selector = Self::selector(collection, query);
+        let mut fields = vec!["_id", "herb_id", "status"];
+        fields.extend(collection.time_field());
+        let mut total = 0;
+        let mut bookmark: Option<String> = None;
+        loop {
+            let (docs, next) = self
+                .couch
+                .find_page(&self.db(collection), selector.clone(), bookmark.as_deref(), FIND_LIMIT, Some(&fields))
+                .await?;
+            total += docs.iter().filter(|doc| query.matches(collection, doc)).count() as u64;
+            match next {
+                Some(next) => bookmark = Some(next),
+                None => return Ok(total),
+            }
+        }
+    }
+
+    async fn put_file(&self, herb_id: &str, name: &str, content_type: &str, bytes: Vec<u8>) -> Result<(), StoreError> {
+        self.couch.put_attachment(&self.files_db(), doc_id(herb_id)?, name, content_type, bytes).await?;
+        Ok(())
+    }
+
+    async fn get_file(&self, herb_id: &str, name: &str) -> Result<FileContent, StoreError> {
+        let res = self.couch.get_attachment(&self.files_db(), doc_id(herb_id)?, name).await?;
+        let content_type = res
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("application/octet-stream")
+            .to_string();
+        Ok(FileContent { content_type, body: Body::from_stream(res.bytes_stream()) })
+    }
+
+    async fn list_files(&self, herb_id: &str) -> Result<Vec<FileInfo>, StoreError> {
+        if !is_doc_id(herb_id) {
+            return Ok(Vec::new());
+        }
+        let attachments = self.couch.list_attachments(&self.files_db(), herb_id).await?;
+        Ok(attachments
+            .into_iter()
+            .map(|(name, content_type, length)| FileInfo { name, content_type, length })
+            .collect())
+    }
+
+    async fn delete_file(&self, herb_id: &str, name: &str) -> Result<(), StoreError> {
+        Ok(self.couch.delete_attachment(&self.files_db(), doc_id(herb_id)?, name).await?)
+    }
+
+    async fn delete_files(&self, herb_id: &str) -> Result<(), StoreError> {
+        if is_doc_id(herb_id) && self.couch.doc_rev(&self.files_db(), herb_id).await?.is_some() {
+            self.couch.delete_doc(&self.files_db(), herb_id).await?;
+        }
+        Ok(())
+    }
+
+    async fn reset(&self) -> Result<(), StoreError> {
+        for collection in Collection::ALL {
+            self.couch.reset_db(&self.db(collection)).await?;
+        }
+        self.couch.reset_db(&self.files_db()).await?;
+        // Recreated databases have lost their indexes
+        self.provision().await
+    }
+
+    async fn provision(&self) -> Result<(), StoreError> {
+        for collection in Collection::ALL {
+            self.couch.ensure_db(&self.db(collection)).await?;
+        }
+        self.couch.ensure_db(&self.files_db()).await?;
+        for (collection, name, fields) in COMPANION_INDEXES {
+            self.couch.ensure_index(&self.db(collection), INDEX_DDOC, name, fields).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::{Path, State}, http::StatusCode, routing::{get, put}, Json, Router};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    type Docs = Arc<Mutex<HashMap<String, Value>>>;
+
+    // Just enough of CouchDB's API for the replication, provisioning and document calls. Indexes are
+    // recorded in `docs` as "{db}/{name}"; only admin:secret may log in.
+    async fn mock_couch() -> (CouchDb, Docs) {
+        let docs: Docs = Arc::default();
+        let app = Router::new()
+            .route(
+                "/_session",
+                get(|headers: axum::http::HeaderMap| async move {
+                    // "admin:secret" in Basic auth
+                    match headers.get("authorization").and_then(|v| v.to_str().ok()) {
+                        Some("Basic YWRtaW46c2VjcmV0") => {
+                            Ok(Json(serde_json::json!({ "ok": true, "userCtx": { "name": "admin", "roles": ["_admin"] } })))
+                        }
+                        _ => Err(StatusCode::UNAUTHORIZED),
+                    }
+                }),
+            )
+            .route("/{db}", put(|| async { StatusCode::CREATED }))
+            .route(
+                "/{db}/_index",
+                axum::routing::post(|State(docs): State<Docs>, Path(db): Path<String>, Json(index): Json<Value>| async move {
+                    let name = index["name"].as_str().unwrap_or_default().to_string();
+                    docs.lock().unwrap().insert(format!("{}/{}", db, name), index);
+                    Json(serde_json::json!({ "result": "created" }))
+                }),
+            )
+            .route(
+                "/{db}/_all_docs",
+                get(|| async {
+                    Json(serde_json::json!({ "rows": [
+                        { "id": "_design/backend", "doc": { "_id": "_design/backend", "language": "query" } },
+                        { "id": "a", "doc": { "_id": "a", "n": 1 } },
+                        { "id": "b", "doc": { "_id": "b", "n": "not a number" } },
+                    ]}))
+                }),
+            )
+            // One document, in another tenant's database
+            .route(
+                "/{db}/{id}",
+                get(|Path((db, id)): Path<(String, String)>| async move {
+                    match (db.as_str(), id.as_str()) {
+                        ("herbs_t_b", "herb_1") => Ok(Json(serde_json::json!({ "_id": "herb_1", "_rev": "1-b" }))),
+                        _ => Err(StatusCode::NOT_FOUND),
+                    }
+                }),
+            )
+            .route("/herbs/not_a_herb", get(|| async { Json(serde_json::json!({ "_id": "not_a_herb", "_rev": "1-c", "n": 1 })) }))
+            .route("/_replicator", put(|| async { StatusCode::PRECONDITION_FAILED }))
+            .route(
+                "/_replicator/{id}",
+                get(|State(docs): State<Docs>, Path(id): Path<String>| async move {
+                    match docs.lock().unwrap().get(&id) {
+                        Some(doc) => Ok(Json(doc.clone())),
+                        None => Err(StatusCode::NOT_FOUND),
+                    }
+                })
+                .put(|State(docs): State<Docs>, Path(id): Path<String>, Json(mut doc): Json<Value>| async move {
+                    doc["_rev"] = serde_json::json!("1-a");
+                    docs.lock().unwrap().insert(id, doc);
+                    StatusCode::CREATED
+                })
+                .delete(|State(docs): State<Docs>, Path(id): Path<String>| async move {
+                    docs.lock().unwrap().remove(&id);
+                    StatusCode::OK
+                }),
+            )
+            .route(
+                "/_scheduler/docs/_replicator/{id}",
+                get(|State(docs): State<Docs>, Path(id): Path<String>| async move {
+                    if !docs.lock().unwrap().contains_key(&id) {
+                        return Err(StatusCode::NOT_FOUND);
+                    }
+                    Ok(Json(serde_json::json!({
+                        "doc_id": id,
+                        "state": "running",
+                        "info": { "docs_read": 12, "docs_written": 12, "doc_write_failures": 0, "changes_pending": 3 },
+                        "error_count": 0,
+                        "last_updated": "2026-10-18T06:00:00Z",
+                    })))
+                }),
+            )
+            .with_state(docs.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (CouchDb::new(&url, "admin", "secret"), docs)
+    }
+
+    #[tokio::test]
+    async fn replications_start_report_lag_and_stop() {
+        let (couch, docs) = mock_couch().await;
+        let doc = ReplicationDoc {
+            source: "http://127.0.0.1:5984/herbs".to_string(),
+            target: "https://hq.example.org/herbs".to_string(),
+            continuous: true,
+            create_target: false,
+        };
+        couch.start_replication("mysuru-to-hq", &doc).await.unwrap();
+        assert_eq!(docs.lock().unwrap()["mysuru-to-hq"]["continuous"], true);
+
+        let status = couch.replication_status("mysuru-to-hq").await.unwrap().unwrap();
+        assert_eq!(status.state, "running");
+        assert_eq!(status.changes_pending, Some(3));
+        assert_eq!(status.docs_written, 12);
+
+        couch.stop_replication("mysuru-to-hq").await.unwrap();
+        assert_eq!(couch.replication_status("mysuru-to-hq").await.unwrap(), None);
+        // Stopping again is not an error
+        couch.stop_replication("mysuru-to-hq").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn sessions_indexes_and_listing_skip_design_docs() {
+        let (couch, docs) = mock_couch().await;
+        assert_eq!(couch.session_user().await.unwrap().as_deref(), Some("admin"));
+        let wrong = CouchDb::new(&couch.base_url, "admin", "guess");
+        assert_eq!(wrong.session_user().await.unwrap_err().status(), Some(reqwest::StatusCode::UNAUTHORIZED));
+
+        couch.ensure_index("herbs", "backend", "by-farmer", &["farmer"]).await.unwrap();
+        assert_eq!(docs.lock().unwrap()["herbs/by-farmer"]["index"]["fields"], serde_json::json!(["farmer"]));
+
+        #[derive(Deserialize)]
+        struct Numbered {
+            n: u32,
+        }
+        let listed: Vec<Numbered> = couch.list_docs("herbs").await.unwrap();
+        assert_eq!(listed.iter().map(|d| d.n).collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[tokio::test]
+    async fn ids_cannot_reach_another_database() {
+        let (couch, _) = mock_couch().await;
+        let (_, rev) = couch.get_doc_with_rev("herbs_t_b", "herb_1").await.unwrap();
+        assert_eq!(rev, "1-b");
+
+        let traversal = "../herbs_t_b/herb_1";
+        assert!(couch.doc_url(&["herbs_t_a", traversal]).ends_with("/herbs_t_a/..%2Fherbs_t_b%2Fherb_1"));
+        let err = couch.get_doc_with_rev("herbs_t_a", traversal).await.unwrap_err();
+        assert_eq!(err.status(), Some(reqwest::StatusCode::NOT_FOUND));
+
+        let store = CouchHerbStore::new(couch, "herbs_t_a");
+        for id in [traversal, "..", "_design/backend", "_all_docs", ""] {
+            assert!(matches!(store.get(id).await, Err(StoreError::NotFound)), "{:?} was looked up", id);
+            assert_eq!(store.rev(id).await.unwrap(), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_document_that_is_not_a_herb_is_not_found() {
+        let (couch, _) = mock_couch().await;
+        let store = CouchHerbStore::new(couch, "herbs");
+        assert!(matches!(store.get("not_a_herb").await, Err(StoreError::NotFound)));
+    }
+}
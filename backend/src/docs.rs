@@ -0,0 +1,453 @@
+use async_trait::async_trait;
+use axum::body::Body;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::Mutex;
+use crate::store::StoreError;
+
+// Documents kept next to the herbs, in the same backend (HERB_STORE): lab results, specs, scan
+// events and alerts, plus each herb's attachment files. Revisions work as in HerbStore.
+// Page size used by find_docs and count to walk a whole collection
+const PAGE: usize = 1_000;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Collection {
+    LabResults,
+    Specs,
+    Scans,
+    Alerts,
+    // The tenant registry, only in the default tenant's store
+    Tenants,
+}
+
+impl Collection {
+    // The companion collections, which reset clears; the registry survives a reset
+    pub const ALL: [Collection; 4] = [Collection::LabResults, Collection::Specs, Collection::Scans, Collection::Alerts];
+
+    // Suffix of the CouchDB database, and the SQL `collection` column
+    pub fn name(self) -> &'static str {
+        match self {
+            Collection::LabResults => "lab_results",
+            Collection::Specs => "specs",
+            Collection::Scans => "scans",
+            Collection::Alerts => "alerts",
+            Collection::Tenants => "tenants",
+        }
+    }
+
+    // The timestamp DocQuery::from and DocQuery::to compare against
+    pub fn time_field(self) -> Option<&'static str> {
+        match self {
+            Collection::LabResults => Some("tested_at"),
+            Collection::Scans => Some("scanned_at"),
+            Collection::Alerts => Some("last_detected_at"),
+            Collection::Specs | Collection::Tenants => None,
+        }
+    }
+}
+
+// Filters on the fields companion documents share; empty fields match everything
+#[derive(Default, Clone)]
+pub struct DocQuery {
+    // Any of these herbs (the `herb_id` field)
+    pub herb_ids: Vec<String>,
+    pub status: Option<String>,
+    // Collection::time_field at or after `from` and before `to`
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl DocQuery {
+    pub fn herb(id: &str) -> Self {
+        Self { herb_ids: vec![id.to_string()], ..Self::default() }
+    }
+
+    pub fn matches(&self, collection: Collection, doc: &Value) -> bool {
+        let text = |field: &str| doc.get(field).and_then(|v| v.as_str());
+        let in_range = || {
+            timestamp(collection, doc)
+                .is_some_and(|at| self.from.is_none_or(|from| at >= from) && self.to.is_none_or(|to| at < to))
+        };
+        (self.herb_ids.is_empty() || text("herb_id").is_some_and(|id| self.herb_ids.iter().any(|h| h == id)))
+            && self.status.as_deref().is_none_or(|status| text("status") == Some(status))
+            && ((self.from.is_none() && self.to.is_none()) || in_range())
+    }
+}
+
+// A document's Collection::time_field
+pub fn timestamp(collection: Collection, doc: &Value) -> Option<DateTime<Utc>> {
+    let value = doc.get(collection.time_field()?)?.as_str()?;
+    DateTime::parse_from_rfc3339(value).ok().map(|t| t.with_timezone(&Utc))
+}
+
+// RFC 3339 with a fixed-width fraction, so stored timestamps compare correctly as text
+pub fn sortable(t: DateTime<Utc>) -> String {
+    t.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileInfo {
+    pub name: String,
+    pub content_type: String,
+    pub length: u64,
+}
+
+// A file's content, streamed where the backend allows it
+pub struct FileContent {
+    pub content_type: String,
+    pub body: Body,
+}
+
+#[async_trait]
+pub trait DocStore: Send + Sync {
+    // The document and its current revision
+    async fn get(&self, collection: Collection, id: &str) -> Result<(Value, String), StoreError>;
+
+    // Creates a document; Conflict if the id is taken
+    async fn put(&self, collection: Collection, id: &str, doc: &Value) -> Result<(), StoreError>;
+
+    // Replaces a document; Conflict unless `rev` is the current revision
+    async fn update(&self, collection: Collection, id: &str, rev: &str, doc: &Value) -> Result<(), StoreError>;
+
+    async fn delete(&self, collection: Collection, id: &str) -> Result<(), StoreError>;
+
+    // One page of matching documents, in no particular order, plus where the next page starts
+    // (None once the last page has been read)
+    async fn find_page(
+        &self,
+        collection: Collection,
+        query: &DocQuery,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<(Vec<Value>, Option<String>), StoreError>;
+
+    // How many documents match
+    async fn count(&self, collection: Collection, query: &DocQuery) -> Result<u64, StoreError> {
+        let mut total = 0;
+        let mut after: Option<String> = None;
+        loop {
+            let (page, next) = self.find_page(collection, query, after.as_deref(), PAGE).await?;
+            total += page.len() as u64;
+            match next {
+                Some(next) => after = Some(next),
+                None => return Ok(total),
+            }
+        }
+    }
+
+    // Stores one of a herb's files, replacing any file of the same name
+    async fn put_file(&self, herb_id: &str, name: &str, content_type: &str, bytes: Vec<u8>) -> Result<(), StoreError>;
+
+    async fn get_file(&self, herb_id: &str, name: &str) -> Result<FileContent, StoreError>;
+
+    // A herb's files; empty if it has none
+    async fn list_files(&self, herb_id: &str) -> Result<Vec<FileInfo>, StoreError>;
+
+    async fn delete_file(&self, herb_id: &str, name: &str) -> Result<(), StoreError>;
+
+    // Removes every file of a herb
+    async fn delete_files(&self, herb_id: &str) -> Result<(), StoreError>;
+
+    // Removes every document and file
+    async fn reset(&self) -> Result<(), StoreError>;
+
+    // Creates the databases and indexes the store needs, if missing
+    async fn provision(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+}
+
+fn to_value<T: Serialize>(doc: &T) -> Result<Value, StoreError> {
+    serde_json::to_value(doc).map_err(|e| StoreError::Backend(e.to_string()))
+}
+
+// Typed access to the documents
+impl<'a> dyn DocStore + 'a {
+    pub async fn get_doc<T: DeserializeOwned>(&self, collection: Collection, id: &str) -> Result<(T, String), StoreError> {
+        let (doc, rev) = self.get(collection, id).await?;
+        let doc = serde_json::from_value(doc).map_err(|e| StoreError::Backend(e.to_string()))?;
+        Ok((doc, rev))
+    }
+
+    pub async fn put_doc<T: Serialize>(&self, collection: Collection, id: &str, doc: &T) -> Result<(), StoreError> {
+        let doc = to_value(doc)?;
+        self.put(collection, id, &doc).await
+    }
+
+    pub async fn update_doc<T: Serialize>(&self, collection: Collection, id: &str, rev: &str, doc: &T) -> Result<(), StoreError> {
+        let doc = to_value(doc)?;
+        self.update(collection, id, rev, &doc).await
+    }
+
+    // Creates or replaces a document, whatever its current revision
+    pub async fn upsert_doc<T: Serialize>(&self, collection: Collection, id: &str, doc: &T) -> Result<(), StoreError> {
+        let doc = to_value(doc)?;
+        match self.get(collection, id).await {
+            Ok((_, rev)) => self.update(collection, id, &rev, &doc).await,
+            Err(StoreError::NotFound) => self.put(collection, id, &doc).await,
+            Err(e) => Err(e),
+        }
+    }
+
+    // Every matching document, read page by page; documents that do not deserialize into T
+    // are skipped
+    pub async fn find_docs<T: DeserializeOwned + Send>(&self, collection: Collection, query: &DocQuery) -> Result<Vec<T>, StoreError> {
+        let mut docs = Vec::new();
+        let mut after: Option<String> = None;
+        loop {
+            let (page, next) = self.find_page(collection, query, after.as_deref(), PAGE).await?;
+            docs.extend(page.into_iter().filter_map(|doc| serde_json::from_value(doc).ok()));
+            match next {
+                Some(next) => after = Some(next),
+                None => return Ok(docs),
+            }
+        }
+    }
+}
+
+// Content type and bytes
+type StoredFile = (String, Vec<u8>);
+
+// Documents and files held in process memory, next to a MemoryStore
+#[derive(Default)]
+pub struct MemoryDocStore {
+    // (collection, id) -> (document, revision number)
+    docs: Mutex<BTreeMap<(Collection, String), (Value, u64)>>,
+    // (herb id, file name) -> (content type, bytes)
+    files: Mutex<BTreeMap<(String, String), StoredFile>>,
+}
+
+impl MemoryDocStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn rev(generation: u64) -> String {
+        format!("{}-mem", generation)
+    }
+}
+
+#[async_trait]
+impl DocStore for MemoryDocStore {
+    async fn get(&self, collection: Collection, id: &str) -> Result<(Value, String), StoreError> {
+        let docs = self.docs.lock().unwrap();
+        let (doc, generation) = docs.get(&(collection, id.to_string())).ok_or(StoreError::NotFound)?;
+        Ok((doc.clone(), Self::rev(*generation)))
+    }
+
+    async fn put(&self, collection: Collection, id: &str, doc: &Value) -> Result<(), StoreError> {
+        let mut docs = self.docs.lock().unwrap();
+        let key = (collection, id.to_string());
+        if docs.contains_key(&key) {
+            return Err(StoreError::Conflict);
+        }
+        docs.insert(key, (doc.clone(), 1));
+        Ok(())
+    }
+
+    async fn update(&self, collection: Collection, id: &str, rev: &str, doc: &Value) -> Result<(), StoreError> {
+        let mut docs = self.docs.lock().unwrap();
+        let (stored, generation) = docs.get_mut(&(collection, id.to_string())).ok_or(StoreError::NotFound)?;
+        if Self::rev(*generation) != rev {
+            return Err(StoreError::Conflict);
+        }
+        *stored = doc.clone();
+        *generation += 1;
+        Ok(())
+    }
+
+    async fn delete(&self, collection: Collection, id: &str) -> Result<(), StoreError> {
+        let removed = self.docs.lock().unwrap().remove(&(collection, id.to_string()));
+        removed.map(|_| ()).ok_or(StoreError::NotFound)
+    }
+
+    async fn find_page(
+        &self,
+        collection: Collection,
+        query: &DocQuery,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<(Vec<Value>, Option<String>), StoreError> {
+        let docs = self.docs.lock().unwrap();
+        let start = match after {
+            Some(after) => Bound::Excluded((collection, after.to_string())),
+            None => Bound::Included((collection, String::new())),
+        };
+        let page: Vec<(&String, &Value)> = docs
+            .range((start, Bound::Unbounded))
+            .take_while(|((c, _), _)| *c == collection)
+            .filter(|(_, (doc, _))| query.matches(collection, doc))
+            .take(limit)
+            .map(|((_, id), (doc, _))| (id, doc))
+            .collect();
+        let next = if page.len() < limit { None } else { page.last().map(|(id, _)| id.to_string()) };
+        Ok((page.into_iter().map(|(_, doc)| doc.clone()).collect(), next))
+    }
+
+    async fn put_file(&self, herb_id: &str, name: &str, content_type: &str, bytes: Vec<u8>) -> Result<(), StoreError> {
+        let key = (herb_id.to_string(), name.to_string());
+        self.files.lock().unwrap().insert(key, (content_type.to_string(), bytes));
+        Ok(())
+    }
+
+    async fn get_file(&self, herb_id: &str, name: &str) -> Result<FileContent, StoreError> {
+        let files = self.files.lock().unwrap();
+        let (content_type, bytes) = files.get(&(herb_id.to_string(), name.to_string())).ok_or(StoreError::NotFound)?;
+        Ok(FileContent { content_type: content_type.clone(), body: Body::from(bytes.clone()) })
+    }
+
+    async fn list_files(&self, herb_id: &str) -> Result<Vec<FileInfo>, StoreError> {
+        let files = self.files.lock().unwrap();
+        Ok(files
+            .iter()
+            .filter(|((herb, _), _)| herb == herb_id)
+            .map(|((_, name), (content_type, bytes))| FileInfo {
+                name: name.clone(),
+                content_type: content_type.clone(),
+                length: bytes.len() as u64,
+            })
+            .collect())
+    }
+
+    async fn delete_file(&self, herb_id: &str, name: &str) -> Result<(), StoreError> {
+        let removed = self.files.lock().unwrap().remove(&(herb_id.to_string(), name.to_string()));
+        removed.map(|_| ()).ok_or(StoreError::NotFound)
+    }
+
+    async fn delete_files(&self, herb_id: &str) -> Result<(), StoreError> {
+        self.files.lock().unwrap().retain(|(herb, _), _| herb != herb_id);
+        Ok(())
+    }
+
+    async fn reset(&self) -> Result<(), StoreError> {
+        self.docs.lock().unwrap().retain(|(collection, _), _| !Collection::ALL.contains(collection));
+        self.files.lock().unwrap().clear();
+        Ok(())
+    }
+}
+
+// Behaviour every DocStore must share, run against each implementation's own tests
+#[cfg(test)]
+pub mod conformance {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn scan(id: &str, herb_id: &str, minute: u32) -> Value {
+        let at = Utc.with_ymd_and_hms(2026, 10, 18, 6, minute, 0).unwrap();
+        serde_json::json!({ "id": id, "herb_id": herb_id, "scanned_at": at.to_rfc3339() })
+    }
+
+    pub async fn updates_need_the_current_revision(store: &dyn DocStore) {
+        let spec = serde_json::json!({ "species": "Tulsi", "moisture_max_pct": 12.0 });
+        store.put(Collection::Specs, "spec_tulsi", &spec).await.unwrap();
+        assert_eq!(store.put(Collection::Specs, "spec_tulsi", &spec).await, Err(StoreError::Conflict));
+        // Same id in another collection is a different document
+        store.put(Collection::Alerts, "spec_tulsi", &spec).await.unwrap();
+
+        let (_, rev) = store.get(Collection::Specs, "spec_tulsi").await.unwrap();
+        let drier = serde_json::json!({ "species": "Tulsi", "moisture_max_pct": 10.0 });
+        store.update(Collection::Specs, "spec_tulsi", &rev, &drier).await.unwrap();
+        assert_eq!(store.update(Collection::Specs, "spec_tulsi", &rev, &drier).await, Err(StoreError::Conflict));
+        let (stored, _) = store.get(Collection::Specs, "spec_tulsi").await.unwrap();
+        assert_eq!(stored["moisture_max_pct"], 10.0);
+
+        store.upsert_doc(Collection::Specs, "spec_neem", &spec).await.unwrap();
+        store.upsert_doc(Collection::Specs, "spec_neem", &drier).await.unwrap();
+        store.delete(Collection::Specs, "spec_tulsi").await.unwrap();
+        assert_eq!(store.get(Collection::Specs, "spec_tulsi").await.map(|_| ()), Err(StoreError::NotFound));
+        assert_eq!(store.delete(Collection::Specs, "spec_tulsi").await, Err(StoreError::NotFound));
+        let specs: Vec<Value> = store.find_docs(Collection::Specs, &DocQuery::default()).await.unwrap();
+        assert_eq!(specs, vec![drier]);
+
+        // Resets leave the tenant registry alone
+        store.put(Collection::Tenants, "acme", &spec).await.unwrap();
+        store.reset().await.unwrap();
+        assert_eq!(store.find_docs::<Value>(Collection::Specs, &DocQuery::default()).await, Ok(Vec::new()));
+        assert!(store.get(Collection::Tenants, "acme").await.is_ok());
+    }
+
+    pub async fn queries_filter_and_page(store: &dyn DocStore) {
+        for (n, herb) in ["herb_a", "herb_b", "herb_a", "herb_c", "herb_a"].iter().enumerate() {
+            let id = format!("scan_{}", n);
+            store.put(Collection::Scans, &id, &scan(&id, herb, n as u32)).await.unwrap();
+        }
+        store.put(Collection::Alerts, "alert_1", &serde_json::json!({ "herb_id": "herb_a", "status": "open" })).await.unwrap();
+
+        assert_eq!(store.count(Collection::Scans, &DocQuery::default()).await, Ok(5));
+        assert_eq!(store.count(Collection::Scans, &DocQuery::herb("herb_a")).await, Ok(3));
+        let a_or_b = DocQuery { herb_ids: vec!["herb_a".to_string(), "herb_b".to_string()], ..DocQuery::default() };
+        assert_eq!(store.count(Collection::Scans, &a_or_b).await, Ok(4));
+        // Minutes 1 up to (not including) 4
+        let window = DocQuery {
+            from: Some(Utc.with_ymd_and_hms(2026, 10, 18, 6, 1, 0).unwrap()),
+            to: Some(Utc.with_ymd_and_hms(2026, 10, 18, 6, 4, 0).unwrap()),
+            ..DocQuery::default()
+        };
+        let mut ids: Vec<String> = store
+            .find_docs::<Value>(Collection::Scans, &window)
+            .await
+            .unwrap()
+            .iter()
+            .map(|s| s["id"].as_str().unwrap().to_string())
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec!["scan_1", "scan_2", "scan_3"]);
+        let open = DocQuery { status: Some("open".to_string()), ..DocQuery::default() };
+        assert_eq!(store.count(Collection::Alerts, &open).await, Ok(1));
+
+        let (first, next) = store.find_page(Collection::Scans, &DocQuery::default(), None, 3).await.unwrap();
+        assert_eq!(first.len(), 3);
+        let (rest, next) = store.find_page(Collection::Scans, &DocQuery::default(), next.as_deref(), 3).await.unwrap();
+        assert_eq!(rest.len(), 2);
+        assert_eq!(next, None);
+
+        store.reset().await.unwrap();
+        assert_eq!(store.count(Collection::Scans, &DocQuery::default()).await, Ok(0));
+        assert_eq!(store.count(Collection::Alerts, &DocQuery::default()).await, Ok(0));
+    }
+
+    pub async fn files_belong_to_one_herb(store: &dyn DocStore) {
+        store.put_file("herb_a", "coa.pdf", "application/pdf", b"%PDF-1.4".to_vec()).await.unwrap();
+        store.put_file("herb_a", "photo.png", "image/png", vec![1, 2, 3]).await.unwrap();
+        store.put_file("herb_b", "photo.png", "image/png", vec![4]).await.unwrap();
+        // Uploading again replaces the file
+        store.put_file("herb_a", "photo.png", "image/png", vec![5, 6]).await.unwrap();
+
+        let mut files = store.list_files("herb_a").await.unwrap();
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(files.iter().map(|f| (f.name.as_str(), f.length)).collect::<Vec<_>>(), vec![("coa.pdf", 8), ("photo.png", 2)]);
+        let content = store.get_file("herb_a", "photo.png").await.unwrap();
+        assert_eq!(content.content_type, "image/png");
+        let bytes = axum::body::to_bytes(content.body, usize::MAX).await.unwrap();
+        assert_eq!(bytes.as_ref(), &[5, 6]);
+
+        store.delete_file("herb_a", "coa.pdf").await.unwrap();
+        assert!(matches!(store.get_file("herb_a", "coa.pdf").await, Err(StoreError::NotFound)));
+        store.delete_files("herb_a").await.unwrap();
+        assert!(store.list_files("herb_a").await.unwrap().is_empty());
+        assert_eq!(store.list_files("herb_b").await.unwrap().len(), 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn updates_need_the_current_revision() {
+        conformance::updates_need_the_current_revision(&MemoryDocStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn queries_filter_and_page() {
+        conformance::queries_filter_and_page(&MemoryDocStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn files_belong_to_one_herb() {
+        conformance::files_belong_to_one_herb(&MemoryDocStore::new()).await;
+    }
+}
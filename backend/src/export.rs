@@ -0,0 +1,467 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use chrono::Utc;
+use futures_util::{stream, Stream, StreamExt};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use crate::docs::{Collection, DocQuery};
+use crate::handlers::{AppState, Herb, HerbFilter, HerbStatus};
+use crate::gs1;
+use crate::store::StoreError;
+use crate::lab::{LabResult, Verdict};
+
+// Herbs are read from the store page by page so exports never hold the whole database
+const EXPORT_PAGE_SIZE: usize = 500;
+const CSV_COLUMNS: [&str; 8] = ["id", "name", "farmer", "location", "created_at", "status", "gtin", "lot"];
+const EPCIS_CONTEXT: &str = "https://ref.gs1.org/standards/epcis/2.0.0/epcis-context.jsonld";
+// Last line of a CSV or NDJSON export that failed part-way
+const CSV_ERROR_ROW: &str = "ERROR: export incomplete; a page of herbs could not be read\n";
+const NDJSON_ERROR_LINE: &str = "{\"error\":\"export incomplete; a page of herbs could not be read\"}\n";
+// JSON-LD namespace for herb fields that have no EPCIS/CBV equivalent
+const HERB_NAMESPACE: &str = "urn:herb:ns:";
+
+fn herb_pages(state: AppState, filter: HerbFilter) -> impl Stream<Item = Result<Vec<Herb>, StoreError>> {
+    // Outer None = finished, inner Option = id to continue after
+    stream::unfold(Some(None::<String>), move |cursor| {
+        let state = state.clone();
+        let filter = filter.clone();
+        async move {
+            let after = cursor?;
+            match state.herbs.list_page(after.as_deref(), EXPORT_PAGE_SIZE).await {
+                Ok((herbs, next)) => {
+                    let herbs = herbs.into_iter().filter(|h| filter.matches(h)).collect();
+                    Some((Ok(herbs), next.map(Some)))
+                }
+                Err(err) => {
+                    eprintln!("export page read failed: {}", err);
+                    Some((Err(err), None))
+                }
+            }
+        }
+    })
+}
+
+// Spreadsheets run cells starting with one of these as formulas
+fn csv_cell(value: &str) -> String {
+    if value.starts_with(['=', '+', '-', '@']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+fn csv_chunk(herbs: &[Herb]) -> Bytes {
+    let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(Vec::new());
+    for herb in herbs {
+        let status = serde_json::to_value(herb.status)
+            .ok()
+            .and_then(|v| v.as_str().map(str::to_string))
+            .unwrap_or_default();
+        let created_at = herb.created_at.to_rfc3339();
+        let gtin = herb.gtin.clone().unwrap_or_default();
+        let lot = herb.lot.clone().unwrap_or_default();
+        let record = [&herb.id, &herb.name, &herb.farmer, &herb.location, &created_at, &status, &gtin, &lot];
+        let _ = writer.write_record(record.map(|cell| csv_cell(cell)));
+    }
+    Bytes::from(writer.into_inner().unwrap_or_default())
+}
+
+fn ndjson_chunk(herbs: &[Herb]) -> Bytes {
+    let mut out = Vec::new();
+    for herb in herbs {
+        if serde_json::to_writer(&mut out, herb).is_ok() {
+            out.push(b'\n');
+        }
+    }
+    Bytes::from(out)
+}
+
+// A page that cannot be read after the response has started ends the body with `marker`, so the
+// file itself shows it is incomplete, and then aborts the transfer
+fn with_error_marker(
+    chunks: impl Stream<Item = Result<Bytes, StoreError>>,
+    marker: &'static str,
+) -> impl Stream<Item = Result<Bytes, StoreError>> {
+    chunks.flat_map(move |chunk| match chunk {
+        Ok(bytes) => stream::iter(vec![Ok(bytes)]),
+        Err(err) => stream::iter(vec![Ok(Bytes::from_static(marker.as_bytes())), Err(err)]),
+    })
+}
+
+fn attachment_headers(content_type: &'static str, file_name: &str) -> [(header::HeaderName, String); 2] {
+    [
+        (header::CONTENT_TYPE, content_type.to_string()),
+        (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", file_name)),
+    ]
+}
+
+// EPC identifying a herb: its GS1 Digital Link or public product URL when configured,
+// otherwise a URN
+pub fn epc_uri(herb: &Herb, base: Option<&str>) -> String {
+    match base {
+        Some(base) => gs1::digital_link(base, herb)
+            .unwrap_or_else(|| format!("{}/p/{}", base.trim_end_matches('/'), herb.id)),
+        None => format!("urn:herb:{}", herb.id),
+    }
+}
+
+// What an event is about: a Digital Link names the GTIN and lot, a class of products, so it goes
+// in quantityList; the product URL and URN name the one herb, so they go in epcList
+fn event_object(herb: &Herb, base: Option<&str>) -> (&'static str, Value) {
+    match base.and_then(|base| gs1::digital_link(base, herb)) {
+        Some(link) => {
+            let mut quantity = json!({ "epcClass": link });
+            if let Some(units) = herb.units {
+                quantity["quantity"] = json!(units);
+            }
+            ("quantityList", json!([quantity]))
+        }
+        None => ("epcList", json!([epc_uri(herb, base)])),
+    }
+}
+
+// ObjectEvent about a herb, with the other fields of `fields`
+fn object_event(herb: &Herb, base: Option<&str>, fields: Value) -> Value {
+    let (key, object) = event_object(herb, base);
+    let mut event = json!({ "type": "ObjectEvent", "eventTimeZoneOffset": "+00:00" });
+    event[key] = object;
+    if let (Some(event), Value::Object(fields)) = (event.as_object_mut(), fields) {
+        event.extend(fields);
+    }
+    event
+}
+
+// Herb registration maps to a commissioning ObjectEvent
+fn commissioning_event(herb: &Herb, base: Option<&str>) -> Value {
+    object_event(herb, base, json!({
+        "eventTime": herb.created_at.to_rfc3339(),
+        "action": "ADD",
+        "bizStep": "commissioning",
+        "disposition": "active",
+        "herb:name": herb.name,
+        "herb:farmer": herb.farmer,
+        "herb:location": herb.location,
+    }))
+}
+
+// A release or recall maps to an ObjectEvent with the matching disposition. Herbs that got their
+// status without a recorded change time (imports) use their registration time.
+fn status_event(herb: &Herb, base: Option<&str>) -> Option<Value> {
+    let event_time = herb.status_changed_at.unwrap_or(herb.created_at).to_rfc3339();
+    match herb.status {
+        HerbStatus::Pending => None,
+        HerbStatus::Released => Some(object_event(herb, base, json!({
+            "eventTime": event_time,
+            "action": "OBSERVE",
+            "bizStep": "staging_outbound",
+            "disposition": "sellable_not_accessible",
+        }))),
+        HerbStatus::Recalled => Some(object_event(herb, base, json!({
+            "eventTime": event_time,
+            "action": "OBSERVE",
+            "bizStep": "holding",
+            "disposition": "recalled",
+            "herb:recallReason": herb.recall_reason,
+        }))),
+    }
+}
+
+// A lab result maps to an inspecting ObjectEvent with a conformance disposition
+fn inspection_event(herb: &Herb, result: &LabResult, base: Option<&str>) -> Value {
+    let disposition = match result.verdict {
+        Verdict::Pass => "conformant",
+        Verdict::Fail => "non_conformant",
+        Verdict::NoSpec => "active",
+    };
+    object_event(herb, base, json!({
+        "eventTime": result.tested_at.to_rfc3339(),
+        "action": "OBSERVE",
+        "bizStep": "inspecting",
+        "disposition": disposition,
+        "herb:labName": result.lab_name,
+        "herb:coaUrl": result.coa_url,
+    }))
+}
+
+// Handlers
+
+// GET /export/herbs.csv - Accepts the same filters as /listHerbs
+#[utoipa::path(
+    get,
+    path = "/export/herbs.csv",
+    tag = "export",
+    params(HerbFilter),
+    responses(
+        (status = 200, description = "Herbs as CSV", content_type = "text/csv", body = String),
+        (status = 500, description = "Failed to export herbs"),
+    ),
+)]
+pub async fn export_csv(
+    State(state): State<AppState>,
+    Query(filter): Query<HerbFilter>,
+) -> impl IntoResponse {
+    // A store that fails straight away still gets a 500
+    let mut pages = Box::pin(herb_pages(state, filter).peekable());
+    if let Some(Err(_)) = pages.as_mut().peek().await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to export herbs").into_response();
+    }
+    let header_row = stream::once(async { Ok::<_, StoreError>(Bytes::from(format!("{}\n", CSV_COLUMNS.join(",")))) });
+    let rows = pages.map(|page| page.map(|herbs| csv_chunk(&herbs)));
+    (
+        StatusCode::OK,
+        attachment_headers("text/csv; charset=utf-8", "herbs.csv"),
+        Body::from_stream(header_row.chain(with_error_marker(rows, CSV_ERROR_ROW))),
+    )
+        .into_response()
+}
+
+// GET /export/herbs.ndjson - One herb JSON object per line
+#[utoipa::path(
+    get,
+    path = "/export/herbs.ndjson",
+    tag = "export",
+    params(HerbFilter),
+    responses(
+        (status = 200, description = "One herb JSON object per line", content_type = "application/x-ndjson", body = String),
+        (status = 500, description = "Failed to export herbs"),
+    ),
+)]
+pub async fn export_ndjson(
+    State(state): State<AppState>,
+    Query(filter): Query<HerbFilter>,
+) -> impl IntoResponse {
+    let mut pages = Box::pin(herb_pages(state, filter).peekable());
+    if let Some(Err(_)) = pages.as_mut().peek().await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to export herbs").into_response();
+    }
+    let rows = pages.map(|page| page.map(|herbs| ndjson_chunk(&herbs)));
+    (
+        StatusCode::OK,
+        attachment_headers("application/x-ndjson", "herbs.ndjson"),
+        Body::from_stream(with_error_marker(rows, NDJSON_ERROR_LINE)),
+    )
+        .into_response()
+}
+
+// Commissioning, inspection, release and recall events for one page of herbs, with the page's
+// lab results
+async fn page_events(state: &AppState, herbs: Vec<Herb>) -> Result<Vec<Value>, StoreError> {
+    if herbs.is_empty() {
+        return Ok(Vec::new());
+    }
+    let query = DocQuery { herb_ids: herbs.iter().map(|h| h.id.clone()).collect(), ..DocQuery::default() };
+    let mut results_by_herb: HashMap<String, Vec<LabResult>> = HashMap::new();
+    for result in state.docs.find_docs::<LabResult>(Collection::LabResults, &query).await? {
+        results_by_herb.entry(result.herb_id.clone()).or_default().push(result);
+    }
+    let base = state.public_base_url.as_deref();
+    let mut events = Vec::new();
+    for herb in herbs {
+        events.push(commissioning_event(&herb, base));
+        for result in results_by_herb.get(&herb.id).into_iter().flatten() {
+            events.push(inspection_event(&herb, result, base));
+        }
+        events.extend(status_event(&herb, base));
+    }
+    Ok(events)
+}
+
+// GET /export/epcis - GS1 EPCIS 2.0 JSON-LD document with herb registration, lab inspection,
+// release and recall events. The eventList is streamed page by page; a failure part-way aborts the transfer, leaving
+// the document unterminated.
+#[utoipa::path(
+    get,
+    path = "/export/epcis",
+    tag = "export",
+    params(HerbFilter),
+    responses(
+        (status = 200, description = "EPCIS 2.0 JSON-LD document", content_type = "application/ld+json", body = Object),
+        (status = 500, description = "Failed to export EPCIS"),
+    ),
+)]
+pub async fn export_epcis(
+    State(state): State<AppState>,
+    Query(filter): Query<HerbFilter>,
+) -> impl IntoResponse {
+    let events = herb_pages(state.clone(), filter).then(move |page| {
+        let state = state.clone();
+        async move { page_events(&state, page?).await }
+    });
+    let mut events = Box::pin(events.peekable());
+    if let Some(Err(err)) = events.as_mut().peek().await {
+        eprintln!("export_epcis failed: {}", err);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to export EPCIS").into_response();
+    }
+
+    let head = json!({
+        "@context": [EPCIS_CONTEXT, { "herb": HERB_NAMESPACE }],
+        "type": "EPCISDocument",
+        "schemaVersion": "2.0",
+        "creationDate": Utc::now().to_rfc3339(),
+    });
+    // The head object, reopened to hold epcisBody
+    let mut open = serde_json::to_vec(&head).unwrap_or_default();
+    open.pop();
+    open.extend_from_slice(b",\"epcisBody\":{\"eventList\":[");
+    let mut first = true;
+    let list = events.map(move |page| {
+        page.map(|events| {
+            let mut out = Vec::new();
+            for event in events {
+                if !first {
+                    out.push(b',');
+                }
+                first = false;
+                let _ = serde_json::to_writer(&mut out, &event);
+            }
+            Bytes::from(out)
+        })
+    });
+    let body = stream::once(async move { Ok::<_, StoreError>(Bytes::from(open)) })
+        .chain(list)
+        .chain(stream::once(async { Ok(Bytes::from_static(b"]}}")) }));
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/ld+json")],
+        Body::from_stream(body),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::test_herb;
+    use axum::body::to_bytes;
+
+    fn lab_result(herb: &Herb, verdict: Verdict) -> LabResult {
+        LabResult {
+            id: format!("lab_{}", herb.id),
+            herb_id: herb.id.clone(),
+            lab_name: "Spice Lab".to_string(),
+            tested_at: Utc::now(),
+            measurements: Default::default(),
+            coa_url: Some("https://example.com/coa.pdf".to_string()),
+            verdict,
+            checks: Vec::new(),
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn csv_rows_follow_the_columns_and_quote_awkward_names() {
+        let herb = test_herb("Tulsi, \"holy\"\nbasil", "Asha");
+        let bytes = csv_chunk(std::slice::from_ref(&herb));
+        let mut reader = csv::ReaderBuilder::new().has_headers(false).from_reader(&bytes[..]);
+        let records: Vec<_> = reader.records().map(Result::unwrap).collect();
+        assert_eq!(records.len(), 1);
+        let row = &records[0];
+        assert_eq!(row.len(), CSV_COLUMNS.len());
+        assert_eq!(&row[0], herb.id);
+        assert_eq!(&row[1], "Tulsi, \"holy\"\nbasil");
+        assert_eq!(&row[2], "Asha");
+        assert_eq!(&row[6], "");
+    }
+
+    #[test]
+    fn ndjson_has_one_object_per_line() {
+        let bytes = ndjson_chunk(&[test_herb("Tulsi", "Asha"), test_herb("Line\nbreak", "Asha")]);
+        let text = std::str::from_utf8(&bytes).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let second: Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(second["name"], "Line\nbreak");
+    }
+
+    #[test]
+    fn events_map_registration_and_verdicts() {
+        let herb = test_herb("Tulsi", "Asha");
+        let event = commissioning_event(&herb, None);
+        assert_eq!(event["bizStep"], "commissioning");
+        assert_eq!(event["action"], "ADD");
+        assert_eq!(event["epcList"][0], format!("urn:herb:{}", herb.id));
+
+        let event = inspection_event(&herb, &lab_result(&herb, Verdict::Fail), Some("https://example.com/"));
+        assert_eq!(event["bizStep"], "inspecting");
+        assert_eq!(event["disposition"], "non_conformant");
+        assert_eq!(event["epcList"][0], format!("https://example.com/p/{}", herb.id));
+        assert_eq!(event["herb:coaUrl"], "https://example.com/coa.pdf");
+        let event = inspection_event(&herb, &lab_result(&herb, Verdict::Pass), None);
+        assert_eq!(event["disposition"], "conformant");
+    }
+
+    #[test]
+    fn digital_links_are_classes_in_the_quantity_list() {
+        let mut herb = test_herb("Tulsi", "Asha");
+        herb.gtin = Some("08901234567890".to_string());
+        herb.lot = Some("L1".to_string());
+        herb.units = Some(40);
+        let event = commissioning_event(&herb, Some("https://example.com"));
+        assert!(event.get("epcList").is_none());
+        assert_eq!(event["quantityList"][0]["epcClass"], "https://example.com/01/08901234567890/10/L1");
+        assert_eq!(event["quantityList"][0]["quantity"], 40);
+        assert_eq!(event["type"], "ObjectEvent");
+    }
+
+    #[test]
+    fn releases_and_recalls_become_events() {
+        let mut herb = test_herb("Tulsi", "Asha");
+        assert!(status_event(&herb, None).is_none());
+        herb.status = HerbStatus::Released;
+        assert_eq!(status_event(&herb, None).unwrap()["disposition"], "sellable_not_accessible");
+        herb.status = HerbStatus::Recalled;
+        herb.recall_reason = Some("aflatoxin".to_string());
+        let event = status_event(&herb, None).unwrap();
+        assert_eq!(event["disposition"], "recalled");
+        assert_eq!(event["herb:recallReason"], "aflatoxin");
+        assert_eq!(event["epcList"][0], format!("urn:herb:{}", herb.id));
+    }
+
+    #[test]
+    fn csv_cells_cannot_start_a_formula() {
+        let mut herb = test_herb("=HYPERLINK(\"http://evil\")", "Asha");
+        herb.farmer = "@SUM(A1)".to_string();
+        herb.location = "-1+2".to_string();
+        let bytes = csv_chunk(std::slice::from_ref(&herb));
+        let mut reader = csv::ReaderBuilder::new().has_headers(false).from_reader(&bytes[..]);
+        let row = reader.records().next().unwrap().unwrap();
+        assert_eq!(&row[1], "'=HYPERLINK(\"http://evil\")");
+        assert_eq!(&row[2], "'@SUM(A1)");
+        assert_eq!(&row[3], "'-1+2");
+        assert_eq!(&row[0], herb.id);
+    }
+
+    #[tokio::test]
+    async fn a_failed_page_ends_with_the_marker_then_the_error() {
+        let chunks = stream::iter(vec![Ok(Bytes::from_static(b"row\n")), Err(StoreError::Backend("down".to_string()))]);
+        let out: Vec<_> = with_error_marker(chunks, CSV_ERROR_ROW).collect().await;
+        assert_eq!(out.len(), 3);
+        assert_eq!(out[0].as_ref().unwrap(), &Bytes::from_static(b"row\n"));
+        assert_eq!(out[1].as_ref().unwrap(), &Bytes::from_static(CSV_ERROR_ROW.as_bytes()));
+        assert!(out[2].is_err());
+    }
+
+    #[tokio::test]
+    async fn epcis_streams_a_complete_document() {
+        let state = AppState::for_tests();
+        let tested = test_herb("Tulsi", "Asha");
+        state.herbs.put(&tested).await.unwrap();
+        state.herbs.put(&test_herb("Ashwagandha", "Asha")).await.unwrap();
+        let result = lab_result(&tested, Verdict::Pass);
+        state.docs.put_doc(Collection::LabResults, &result.id, &result).await.unwrap();
+
+        let response = export_epcis(State(state), Query(HerbFilter::default())).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let doc: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(doc["type"], "EPCISDocument");
+        let events = doc["epcisBody"]["eventList"].as_array().unwrap();
+        let steps: Vec<&str> = events.iter().map(|e| e["bizStep"].as_str().unwrap()).collect();
+        assert_eq!(steps.iter().filter(|s| **s == "commissioning").count(), 2);
+        assert_eq!(steps.iter().filter(|s| **s == "inspecting").count(), 1);
+    }
+}
@@ -0,0 +1,631 @@
+use async_graphql::{
+    dataloader::{DataLoader, Loader},
+    futures_util::{stream, Stream, StreamExt},
+    http::{parse_query_string, GraphiQLSource},
+    ComplexObject, Context, EmptyMutation, Enum, Object, Schema, SimpleObject, Subscription,
+};
+use axum::{
+    extract::{Extension, Json, RawQuery},
+    http::{header, HeaderMap, StatusCode},
+    response::{sse::{Event, KeepAlive, Sse}, Html, IntoResponse, Response},
+};
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{BTreeMap, HashMap};
+use tokio::sync::broadcast;
+use crate::analytics::{ScanEvent, DEFAULT_WINDOW_DAYS};
+use crate::docs::{Collection, DocQuery};
+use crate::gs1;
+use crate::handlers::{AppState, Herb, HerbFilter, HerbStatus};
+use crate::lab::LabResult;
+use crate::store::{HerbQuery, StoreError};
+use crate::tenants::BasePath;
+
+pub type HerbSchema = Schema<QueryRoot, EmptyMutation, SubscriptionRoot>;
+
+// Dashboard queries nest a few levels (herb -> farmer -> batches -> lab results); anything
+// much deeper or wider is almost certainly a mistake or abuse
+const MAX_DEPTH: usize = 8;
+const MAX_COMPLEXITY: usize = 1000;
+// Live events buffered per subscriber before the slowest ones start skipping
+const LIVE_FEED_CAPACITY: usize = 256;
+
+// In-process fan-out of new herbs and scans to GraphQL subscriptions
+#[derive(Clone)]
+pub struct LiveFeed {
+    herbs: broadcast::Sender<Herb>,
+    scans: broadcast::Sender<ScanEvent>,
+}
+
+impl LiveFeed {
+    pub fn new() -> Self {
+        Self {
+            herbs: broadcast::channel(LIVE_FEED_CAPACITY).0,
+            scans: broadcast::channel(LIVE_FEED_CAPACITY).0,
+        }
+    }
+
+    // Sending only fails when nobody is subscribed, which is fine
+    pub fn herb_added(&self, herb: &Herb) {
+        let _ = self.herbs.send(herb.clone());
+    }
+
+    pub fn scan_recorded(&self, event: &ScanEvent) {
+        let _ = self.scans.send(event.clone());
+    }
+}
+
+// Receiver as a stream; a subscriber that falls behind skips what it missed
+fn subscribe<T: Clone + Send + 'static>(sender: &broadcast::Sender<T>) -> impl Stream<Item = T> {
+    stream::unfold(sender.subscribe(), |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(item) => return Some((item, rx)),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
+pub fn schema(state: AppState) -> HerbSchema {
+    Schema::build(QueryRoot, EmptyMutation, SubscriptionRoot)
+        .data(DataLoader::new(CouchLoader { state: state.clone() }, tokio::spawn))
+        .data(state)
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish()
+}
+
+// Storage errors are logged and reported to the client without store details
+fn storage_error(context: &str, err: impl std::fmt::Display) -> async_graphql::Error {
+    eprintln!("graphql {} failed: {}", context, err);
+    async_graphql::Error::new(format!("Failed to load {}", context))
+}
+
+// Batch loading
+
+// Keys for the per-request batch loads; each kind is fetched with one store query per batch.
+// Documents come through find_docs, which reads every page, so busy herbs are not cut short.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct HerbId(String);
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct LabResultsOf(String);
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct ScansOf(String);
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct BatchesOf(String);
+
+pub struct CouchLoader {
+    state: AppState,
+}
+
+// Groups documents by a key field, keeping every requested key (possibly empty)
+fn group_by<K: Clone + Eq + std::hash::Hash, T>(keys: &[K], docs: Vec<T>, key: impl Fn(&T) -> K) -> HashMap<K, Vec<T>> {
+    let mut grouped: HashMap<K, Vec<T>> = keys.iter().map(|k| (k.clone(), Vec::new())).collect();
+    for doc in docs {
+        if let Some(group) = grouped.get_mut(&key(&doc)) {
+            group.push(doc);
+        }
+    }
+    grouped
+}
+
+impl Loader<HerbId> for CouchLoader {
+    type Value = Herb;
+    type Error = StoreError;
+
+    // One get_many (POST _all_docs with keys on CouchDB)
+    async fn load(&self, keys: &[HerbId]) -> Result<HashMap<HerbId, Herb>, Self::Error> {
+        let ids: Vec<String> = keys.iter().map(|k| k.0.clone()).collect();
+        let herbs = self.state.herbs.get_many(&ids).await?;
+        Ok(herbs.into_iter().map(|h| (HerbId(h.id.clone()), h)).collect())
+    }
+}
+
+impl Loader<LabResultsOf> for CouchLoader {
+    type Value = Vec<LabResult>;
+    type Error = StoreError;
+
+    async fn load(&self, keys: &[LabResultsOf]) -> Result<HashMap<LabResultsOf, Vec<LabResult>>, Self::Error> {
+        let query = DocQuery { herb_ids: keys.iter().map(|k| k.0.clone()).collect(), ..DocQuery::default() };
+        let mut results = self.state.docs.find_docs::<LabResult>(Collection::LabResults, &query).await?;
+        // Newest first, as in GET /herbs/{id}/lab-results
+        results.sort_by(|a, b| b.tested_at.cmp(&a.tested_at).then(b.created_at.cmp(&a.created_at)));
+        Ok(group_by(keys, results, |r| LabResultsOf(r.herb_id.clone())))
+    }
+}
+
+impl Loader<ScansOf> for CouchLoader {
+    type Value = Vec<ScanEvent>;
+    type Error = StoreError;
+
+    // Scans from the default analytics window, newest first
+    async fn load(&self, keys: &[ScansOf]) -> Result<HashMap<ScansOf, Vec<ScanEvent>>, Self::Error> {
+        let query = DocQuery {
+            herb_ids: keys.iter().map(|k| k.0.clone()).collect(),
+            from: Some(Utc::now() - Duration::days(DEFAULT_WINDOW_DAYS)),
+            ..DocQuery::default()
+        };
+        let mut scans = self.state.docs.find_docs::<ScanEvent>(Collection::Scans, &query).await?;
+        scans.sort_by_key(|s| std::cmp::Reverse(s.scanned_at));
+        Ok(group_by(keys, scans, |s| ScansOf(s.herb_id.clone())))
+    }
+}
+
+impl Loader<BatchesOf> for CouchLoader {
+    type Value = Vec<Herb>;
+    type Error = StoreError;
+
+    async fn load(&self, keys: &[BatchesOf]) -> Result<HashMap<BatchesOf, Vec<Herb>>, Self::Error> {
+        let farmers = keys.iter().map(|k| k.0.clone()).collect();
+        let mut herbs = self.state.herbs.find(&HerbQuery { farmers, ..HerbQuery::default() }).await?;
+        herbs.sort_by_key(|h| std::cmp::Reverse(h.created_at));
+        Ok(group_by(keys, herbs, |h| BatchesOf(h.farmer.clone())))
+    }
+}
+
+fn loader<'a>(ctx: &Context<'a>) -> &'a DataLoader<CouchLoader> {
+    ctx.data_unchecked::<DataLoader<CouchLoader>>()
+}
+
+// Object types
+
+// A farmer, identified by the name recorded on their batches
+pub struct Farmer {
+    name: String,
+}
+
+#[Object]
+impl Farmer {
+    async fn name(&self) -> &str {
+        &self.name
+    }
+
+    // Every herb batch registered under this farmer, newest first
+    async fn batches(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Herb>> {
+        let batches = loader(ctx)
+            .load_one(BatchesOf(self.name.clone()))
+            .await
+            .map_err(|err| storage_error("batches", err))?;
+        Ok(batches.unwrap_or_default())
+    }
+
+    // Distinct growing locations across the farmer's batches
+    async fn locations(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<String>> {
+        let mut locations: Vec<String> = self.batches(ctx).await?.into_iter().map(|h| h.location).collect();
+        locations.sort();
+        locations.dedup();
+        Ok(locations)
+    }
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Registered,
+    LabTested,
+    Released,
+    Recalled,
+    Scanned,
+}
+
+// One step in a batch's history
+#[derive(SimpleObject)]
+pub struct HerbEvent {
+    pub kind: EventKind,
+    pub at: DateTime<Utc>,
+    // Location, lab name, recall reason or scan region, depending on the kind
+    pub detail: Option<String>,
+}
+
+#[ComplexObject]
+impl Herb {
+    #[graphql(name = "farmer")]
+    async fn farmer_profile(&self) -> Farmer {
+        Farmer { name: self.farmer.clone() }
+    }
+
+    // Newest first
+    async fn lab_results(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<LabResult>> {
+        let results = loader(ctx)
+            .load_one(LabResultsOf(self.id.clone()))
+            .await
+            .map_err(|err| storage_error("lab results", err))?;
+        Ok(results.unwrap_or_default())
+    }
+
+    // Scans in the last 30 days, newest first
+    async fn scans(&self, ctx: &Context<'_>, limit: Option<usize>) -> async_graphql::Result<Vec<ScanEvent>> {
+        let mut scans = loader(ctx)
+            .load_one(ScansOf(self.id.clone()))
+            .await
+            .map_err(|err| storage_error("scans", err))?
+            .unwrap_or_default();
+        if let Some(limit) = limit {
+            scans.truncate(limit);
+        }
+        Ok(scans)
+    }
+
+    // Registration, lab tests, release or recall, and recent scans, oldest first
+    async fn events(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<HerbEvent>> {
+        let mut events = vec![HerbEvent { kind: EventKind::Registered, at: self.created_at, detail: Some(self.location.clone()) }];
+        for result in self.lab_results(ctx).await? {
+            events.push(HerbEvent { kind: EventKind::LabTested, at: result.tested_at, detail: Some(result.lab_name) });
+        }
+        if let Some(at) = self.status_changed_at {
+            match self.status {
+                HerbStatus::Released => events.push(HerbEvent { kind: EventKind::Released, at, detail: None }),
+                HerbStatus::Recalled => events.push(HerbEvent { kind: EventKind::Recalled, at, detail: self.recall_reason.clone() }),
+                HerbStatus::Pending => {}
+            }
+        }
+        for scan in self.scans(ctx, None).await? {
+            events.push(HerbEvent { kind: EventKind::Scanned, at: scan.scanned_at, detail: scan.region.or(scan.country) });
+        }
+        events.sort_by_key(|e| e.at);
+        Ok(events)
+    }
+}
+
+// Roots
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    async fn herb(&self, ctx: &Context<'_>, id: String) -> async_graphql::Result<Option<Herb>> {
+        loader(ctx).load_one(HerbId(id)).await.map_err(|err| storage_error("herb", err))
+    }
+
+    // Herbs by id (one batched lookup), or all herbs matching the filters
+    #[allow(clippy::too_many_arguments)]
+    async fn herbs(
+        &self,
+        ctx: &Context<'_>,
+        ids: Option<Vec<String>>,
+        name: Option<String>,
+        farmer: Option<String>,
+        location: Option<String>,
+        status: Option<HerbStatus>,
+        created_after: Option<DateTime<Utc>>,
+        created_before: Option<DateTime<Utc>>,
+    ) -> async_graphql::Result<Vec<Herb>> {
+        let filter = HerbFilter { name, farmer, location, status, created_after, created_before };
+        let herbs = match ids {
+            Some(ids) => {
+                let found = loader(ctx)
+                    .load_many(ids.iter().cloned().map(HerbId))
+                    .await
+                    .map_err(|err| storage_error("herbs", err))?;
+                ids.into_iter().filter_map(|id| found.get(&HerbId(id)).cloned()).collect()
+            }
+            None => {
+                let state = ctx.data::<AppState>()?;
+                state.herbs.list().await.map_err(|err| storage_error("herbs", err))?
+            }
+        };
+        Ok(herbs.into_iter().filter(|h| filter.matches(h)).collect())
+    }
+
+    // A batch by GS1 GTIN and lot; the lot may be left out when the GTIN has a single batch
+    async fn batch(&self, ctx: &Context<'_>, gtin: String, lot: Option<String>) -> async_graphql::Result<Option<Herb>> {
+        let gtin = gs1::normalize_gtin(&gtin)?;
+        match gs1::find_herb(ctx.data::<AppState>()?, &gtin, lot.as_deref()).await {
+            Ok(herb) => Ok(Some(herb)),
+            Err((StatusCode::NOT_FOUND, "Product not found")) => Ok(None),
+            Err((_, msg)) => Err(async_graphql::Error::new(msg)),
+        }
+    }
+
+    async fn farmer(&self, ctx: &Context<'_>, name: String) -> async_graphql::Result<Option<Farmer>> {
+        let farmer = Farmer { name };
+        Ok(if farmer.batches(ctx).await?.is_empty() { None } else { Some(farmer) })
+    }
+
+    // Everyone with at least one batch, by name
+    async fn farmers(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Farmer>> {
+        let state = ctx.data::<AppState>()?;
+        let herbs = state.herbs.list().await.map_err(|err| storage_error("farmers", err))?;
+        let names: BTreeMap<String, ()> = herbs.into_iter().map(|h| (h.farmer, ())).collect();
+        Ok(names.into_keys().map(|name| Farmer { name }).collect())
+    }
+}
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    async fn herb_added(&self, ctx: &Context<'_>) -> async_graphql::Result<impl Stream<Item = Herb>> {
+        Ok(subscribe(&ctx.data::<AppState>()?.live.herbs))
+    }
+
+    // Every recorded scan, or only those of one herb
+    async fn scan_recorded(
+        &self,
+        ctx: &Context<'_>,
+        herb_id: Option<String>,
+    ) -> async_graphql::Result<impl Stream<Item = ScanEvent>> {
+        let scans = subscribe(&ctx.data::<AppState>()?.live.scans);
+        Ok(scans.filter(move |scan| {
+            let keep = herb_id.as_ref().is_none_or(|id| *id == scan.herb_id);
+            async move { keep }
+        }))
+    }
+}
+
+// HTTP
+
+fn wants_event_stream(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/event-stream"))
+}
+
+// GraphQL over server-sent events ("distinct connections" mode): one `next` event per
+// result, then `complete`. This is how subscriptions are delivered.
+fn event_stream(schema: HerbSchema, request: async_graphql::Request) -> Response {
+    let results = schema
+        .execute_stream(request)
+        .map(|response| Event::default().event("next").json_data(&response));
+    let complete = stream::once(async { Ok::<_, axum::Error>(Event::default().event("complete").data("")) });
+    Sse::new(results.chain(complete)).keep_alive(KeepAlive::default()).into_response()
+}
+
+// POST /graphql - queries; subscriptions too when the client accepts text/event-stream
+#[utoipa::path(
+    post,
+    path = "/graphql",
+    tag = "graphql",
+    request_body(content = Object, description = "GraphQL request: query, optional operationName and variables"),
+    responses(
+        (status = 200, description = "GraphQL response, or a text/event-stream of results for subscriptions", body = Object),
+    ),
+)]
+pub async fn graphql_post(
+    Extension(schema): Extension<HerbSchema>,
+    headers: HeaderMap,
+    Json(request): Json<async_graphql::Request>,
+) -> Response {
+    if wants_event_stream(&headers) {
+        return event_stream(schema, request);
+    }
+    Json(schema.execute(request).await).into_response()
+}
+
+// GET /graphql?query=... - queries and (with Accept: text/event-stream) subscriptions for
+// EventSource clients; without a query, the GraphiQL explorer
+#[utoipa::path(
+    get,
+    path = "/graphql",
+    tag = "graphql",
+    params(
+        ("query" = Option<String>, Query, description = "GraphQL document"),
+        ("variables" = Option<String>, Query, description = "JSON-encoded variables"),
+        ("operationName" = Option<String>, Query),
+    ),
+    responses(
+        (status = 200, description = "GraphQL response, event stream, or the GraphiQL page", body = Object),
+        (status = 400, description = "Malformed request"),
+    ),
+)]
+pub async fn graphql_get(
+    Extension(schema): Extension<HerbSchema>,
+    headers: HeaderMap,
+    RawQuery(query): RawQuery,
+    base: BasePath,
+) -> Response {
+    let Some(query) = query.filter(|q| !q.is_empty()) else {
+        return Html(GraphiQLSource::build().endpoint(&format!("{}/graphql", base.0)).finish()).into_response();
+    };
+    let request = match parse_query_string(&query) {
+        Ok(request) => request,
+        Err(err) => return (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+    };
+    if wants_event_stream(&headers) {
+        return event_stream(schema, request);
+    }
+    Json(schema.execute(request).await).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analytics::{new_event, ScanClient, ScanSource};
+    use crate::docs::{DocStore, FileContent, FileInfo, MemoryDocStore};
+    use crate::handlers::test_herb;
+    use crate::lab::Verdict;
+    use crate::store::{HerbStore, MemoryStore};
+    use async_trait::async_trait;
+    use serde_json::Value;
+    use std::sync::{Arc, Mutex};
+
+    // Memory stores that note each read the loaders make
+    #[derive(Default)]
+    struct Recorder {
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl Recorder {
+        fn note(&self, call: impl Into<String>) {
+            self.calls.lock().unwrap().push(call.into());
+        }
+
+        fn take(&self) -> Vec<String> {
+            let mut calls = std::mem::take(&mut *self.calls.lock().unwrap());
+            calls.sort();
+            calls
+        }
+    }
+
+    struct RecordedHerbs(MemoryStore, Arc<Recorder>);
+
+    #[async_trait]
+    impl HerbStore for RecordedHerbs {
+        async fn get(&self, id: &str) -> Result<(Herb, String), StoreError> {
+            self.1.note("get");
+            self.0.get(id).await
+        }
+        async fn rev(&self, id: &str) -> Result<Option<String>, StoreError> {
+            self.0.rev(id).await
+        }
+        async fn get_many(&self, ids: &[String]) -> Result<Vec<Herb>, StoreError> {
+            self.1.note("get_many");
+            self.0.get_many(ids).await
+        }
+        async fn put(&self, herb: &Herb) -> Result<(), StoreError> {
+            self.0.put(herb).await
+        }
+        async fn update(&self, herb: &Herb, rev: &str) -> Result<String, StoreError> {
+            self.0.update(herb, rev).await
+        }
+        async fn delete(&self, id: &str, rev: &str) -> Result<(), StoreError> {
+            self.0.delete(id, rev).await
+        }
+        async fn list(&self) -> Result<Vec<Herb>, StoreError> {
+            self.1.note("list");
+            self.0.list().await
+        }
+        async fn list_page(&self, after: Option<&str>, limit: usize) -> Result<(Vec<Herb>, Option<String>), StoreError> {
+            self.1.note("list_page");
+            self.0.list_page(after, limit).await
+        }
+        async fn find(&self, query: &HerbQuery) -> Result<Vec<Herb>, StoreError> {
+            self.1.note("find");
+            self.0.find(query).await
+        }
+        async fn put_many(&self, herbs: &[Herb]) -> Result<Vec<Result<(), StoreError>>, StoreError> {
+            self.0.put_many(herbs).await
+        }
+        async fn reset(&self) -> Result<(), StoreError> {
+            self.0.reset().await
+        }
+    }
+
+    struct RecordedDocs(MemoryDocStore, Arc<Recorder>);
+
+    #[async_trait]
+    impl DocStore for RecordedDocs {
+        async fn get(&self, collection: Collection, id: &str) -> Result<(Value, String), StoreError> {
+            self.0.get(collection, id).await
+        }
+        async fn put(&self, collection: Collection, id: &str, doc: &Value) -> Result<(), StoreError> {
+            self.0.put(collection, id, doc).await
+        }
+        async fn update(&self, collection: Collection, id: &str, rev: &str, doc: &Value) -> Result<(), StoreError> {
+            self.0.update(collection, id, rev, doc).await
+        }
+        async fn delete(&self, collection: Collection, id: &str) -> Result<(), StoreError> {
+            self.0.delete(collection, id).await
+        }
+        async fn find_page(
+            &self,
+            collection: Collection,
+            query: &DocQuery,
+            after: Option<&str>,
+            limit: usize,
+        ) -> Result<(Vec<Value>, Option<String>), StoreError> {
+            self.1.note(format!("find_page {}", collection.name()));
+            self.0.find_page(collection, query, after, limit).await
+        }
+        async fn put_file(&self, herb_id: &str, name: &str, content_type: &str, bytes: Vec<u8>) -> Result<(), StoreError> {
+            self.0.put_file(herb_id, name, content_type, bytes).await
+        }
+        async fn get_file(&self, herb_id: &str, name: &str) -> Result<FileContent, StoreError> {
+            self.0.get_file(herb_id, name).await
+        }
+        async fn list_files(&self, herb_id: &str) -> Result<Vec<FileInfo>, StoreError> {
+            self.0.list_files(herb_id).await
+        }
+        async fn delete_file(&self, herb_id: &str, name: &str) -> Result<(), StoreError> {
+            self.0.delete_file(herb_id, name).await
+        }
+        async fn delete_files(&self, herb_id: &str) -> Result<(), StoreError> {
+            self.0.delete_files(herb_id).await
+        }
+        async fn reset(&self) -> Result<(), StoreError> {
+            self.0.reset().await
+        }
+    }
+
+    fn lab_result(herb: &Herb) -> LabResult {
+        LabResult {
+            id: format!("lab_{}", herb.id),
+            herb_id: herb.id.clone(),
+            lab_name: format!("{} lab", herb.name),
+            tested_at: Utc::now(),
+            measurements: Default::default(),
+            coa_url: None,
+            verdict: Verdict::NoSpec,
+            checks: Vec::new(),
+            created_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn a_nested_query_makes_one_load_per_kind_and_level() {
+        let recorder = Arc::new(Recorder::default());
+        let state = AppState {
+            herbs: Arc::new(RecordedHerbs(MemoryStore::new(), recorder.clone())),
+            docs: Arc::new(RecordedDocs(MemoryDocStore::new(), recorder.clone())),
+            ..AppState::for_tests()
+        };
+        let herbs = [test_herb("Tulsi", "Asha"), test_herb("Brahmi", "Asha"), test_herb("Neem", "Ravi")];
+        for herb in &herbs {
+            state.herbs.put(herb).await.unwrap();
+            let result = lab_result(herb);
+            state.docs.put_doc(Collection::LabResults, &result.id, &result).await.unwrap();
+            let scan = new_event(&herb.id, true, ScanSource::Scan, &ScanClient::default(), None);
+            state.docs.put_doc(Collection::Scans, &scan.id, &scan).await.unwrap();
+        }
+        recorder.take();
+
+        let ids: Vec<String> = herbs.iter().map(|h| format!("{:?}", h.id)).collect();
+        let query = format!(
+            "{{ herbs(ids: [{}]) {{ name labResults {{ labName }} scans {{ herbId }} \
+             farmer {{ batches {{ name labResults {{ labName }} scans {{ herbId }} }} }} }} }}",
+            ids.join(", "),
+        );
+        let response = schema(state).execute(query).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let data = response.data.into_json().unwrap();
+        assert_eq!(data["herbs"][0]["labResults"][0]["labName"], "Tulsi lab");
+        assert_eq!(data["herbs"][2]["scans"].as_array().unwrap().len(), 1);
+        assert_eq!(data["herbs"][0]["farmer"]["batches"].as_array().unwrap().len(), 2);
+        // One load per kind at each level of the query, however many herbs the level has; the
+        // loader keeps no cache between levels
+        assert_eq!(
+            recorder.take(),
+            ["find", "find_page lab_results", "find_page lab_results", "find_page scans", "find_page scans", "get_many"],
+        );
+    }
+
+    #[tokio::test]
+    async fn scan_subscriptions_only_see_their_herb() {
+        let state = AppState::for_tests();
+        let schema = schema(state.clone());
+        let mut stream = schema.execute_stream("subscription { scanRecorded(herbId: \"herb_1\") { herbId found } }");
+        let live = state.live.clone();
+        tokio::spawn(async move {
+            // Wait until the subscription is listening
+            while live.scans.receiver_count() == 0 {
+                tokio::task::yield_now().await;
+            }
+            live.scan_recorded(&new_event("herb_2", false, ScanSource::Scan, &ScanClient::default(), None));
+            live.scan_recorded(&new_event("herb_1", true, ScanSource::Scan, &ScanClient::default(), None));
+        });
+        let response = stream.next().await.unwrap();
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let data = response.data.into_json().unwrap();
+        assert_eq!(data["scanRecorded"]["herbId"], "herb_1");
+        assert_eq!(data["scanRecorded"]["found"], true);
+    }
+
+    #[tokio::test]
+    async fn graphiql_posts_to_the_tenant_endpoint() {
+        let schema = schema(AppState::for_tests());
+        let response = graphql_get(Extension(schema), HeaderMap::new(), RawQuery(None), BasePath("/t/acme".to_string())).await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("/t/acme/graphql"));
+    }
+}
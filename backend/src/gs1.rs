@@ -0,0 +1,224 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+};
+use crate::analytics::{self, ScanClient, ScanSource};
+use crate::handlers::{self, AppState, Herb};
+use crate::i18n::{Lang, LangQuery};
+use crate::store::HerbQuery;
+use crate::tenants::BasePath;
+
+// GS1 Application Identifiers used in Digital Link paths
+pub const AI_GTIN: &str = "01";
+pub const AI_LOT: &str = "10";
+const MAX_LOT_LEN: usize = 20;
+
+// Validate a GTIN-8/12/13/14 (check digit included) and left-pad it to the 14 digits
+// that GS1 Digital Link requires
+pub fn normalize_gtin(raw: &str) -> Result<String, String> {
+    let digits = raw.trim();
+    if !matches!(digits.len(), 8 | 12 | 13 | 14) || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err("gtin must be 8, 12, 13 or 14 digits".to_string());
+    }
+    let gtin = format!("{:0>14}", digits);
+    let values: Vec<u32> = gtin.chars().filter_map(|c| c.to_digit(10)).collect();
+    // Weights alternate 3,1,3,... from the left for the first 13 digits of a GTIN-14
+    let sum: u32 = values[..13]
+        .iter()
+        .enumerate()
+        .map(|(i, d)| if i % 2 == 0 { d * 3 } else { *d })
+        .sum();
+    let check = (10 - sum % 10) % 10;
+    if check != values[13] {
+        return Err("gtin check digit is invalid".to_string());
+    }
+    Ok(gtin)
+}
+
+// Lots are kept to a URL-safe subset of the GS1 AI (10) character set
+pub fn validate_lot(lot: &str) -> Result<(), String> {
+    if lot.is_empty() || lot.len() > MAX_LOT_LEN {
+        return Err(format!("lot must be 1-{} characters", MAX_LOT_LEN));
+    }
+    if !lot.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_')) {
+        return Err("lot may only contain letters, digits, '-', '.' and '_'".to_string());
+    }
+    Ok(())
+}
+
+// Digital Link URI (/01/{gtin}/10/{lot}) for a herb, when it has both identifiers
+pub fn digital_link(base: &str, herb: &Herb) -> Option<String> {
+    let gtin = herb.gtin.as_ref()?;
+    let lot = herb.lot.as_ref()?;
+    Some(format!("{}/{}/{}/{}/{}", base.trim_end_matches('/'), AI_GTIN, gtin, AI_LOT, lot))
+}
+
+// Find "01/{gtin}" (optionally followed by "10/{lot}") anywhere in a path, so Digital Links
+// served under a prefix are understood too. Returns (gtin, lot).
+pub fn parse_digital_link_path(segments: &[&str]) -> Option<(String, Option<String>)> {
+    let start = segments.iter().position(|s| *s == AI_GTIN)?;
+    let gtin = normalize_gtin(segments.get(start + 1)?).ok()?;
+    let lot = match (segments.get(start + 2), segments.get(start + 3)) {
+        (Some(&AI_LOT), Some(lot)) => Some((*lot).to_string()),
+        _ => None,
+    };
+    Some((gtin, lot))
+}
+
+// Resolve a GTIN (and lot, if given) to a single herb. Several matches are a conflict: without a lot
+// the caller must pick one, and with a lot the data holds a duplicate someone has to clean up.
+pub async fn find_herb(state: &AppState, gtin: &str, lot: Option<&str>) -> Result<Herb, (StatusCode, &'static str)> {
+    let query = HerbQuery { gtin: Some(gtin.to_string()), lot: lot.map(str::to_string), ..HerbQuery::default() };
+    let mut herbs = state.herbs.find(&query).await.map_err(|err| {
+        eprintln!("gs1 find_herb failed for gtin {}: {}", gtin, err);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Failed to resolve product")
+    })?;
+    match herbs.len() {
+        0 => Err((StatusCode::NOT_FOUND, "Product not found")),
+        1 => Ok(herbs.remove(0)),
+        _ if lot.is_some() => Err((StatusCode::CONFLICT, "Multiple herbs share this GTIN and lot")),
+        _ => Err((StatusCode::CONFLICT, "Multiple lots share this GTIN; include the lot")),
+    }
+}
+
+async fn resolve(
+    state: AppState,
+    headers: HeaderMap,
+    client: ScanClient,
+    base: BasePath,
+    lang: Option<String>,
+    gtin: String,
+    lot: Option<String>,
+) -> axum::response::Response {
+    let Ok(gtin) = normalize_gtin(&gtin) else {
+        return (StatusCode::BAD_REQUEST, "invalid gtin").into_response();
+    };
+    match find_herb(&state, &gtin, lot.as_deref()).await {
+        Ok(herb) => {
+            analytics::record(&state, analytics::new_event(&herb.id, true, ScanSource::PublicPage, &client, None));
+            // Browsers opening the link get the landing page, apps get JSON
+            let wants_html = headers
+                .get(header::ACCEPT)
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|accept| accept.contains("text/html"));
+            if wants_html {
+                handlers::public_product_html(&state, herb, Lang::negotiate(lang.as_deref(), &headers), &base).await
+            } else {
+                handlers::public_product_json(&state, herb).await
+            }
+        }
+        Err(err) => err.into_response(),
+    }
+}
+
+// Handlers
+
+// GET /01/{gtin}/10/{lot}?lang= - GS1 Digital Link resolver
+#[utoipa::path(
+    get,
+    path = "/01/{gtin}/10/{lot}",
+    tag = "gs1",
+    params(
+        ("gtin" = String, Path, description = "GTIN-8/12/13/14"),
+        ("lot" = String, Path, description = "Batch/lot"),
+        LangQuery,
+    ),
+    responses(
+        (status = 200, description = "Product JSON, or the HTML page when Accept includes text/html", body = crate::handlers::PublicProduct),
+        (status = 400, description = "Invalid GTIN"),
+        (status = 404, description = "Product not found"),
+        (status = 409, description = "Several herbs share this GTIN and lot"),
+    ),
+)]
+pub async fn resolve_gtin_lot(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    client: ScanClient,
+    base: BasePath,
+    Path((gtin, lot)): Path<(String, String)>,
+    Query(query): Query<LangQuery>,
+) -> impl IntoResponse {
+    resolve(state, headers, client, base, query.lang, gtin, Some(lot)).await
+}
+
+// GET /01/{gtin} - Resolves only when a single lot carries the GTIN
+#[utoipa::path(
+    get,
+    path = "/01/{gtin}",
+    tag = "gs1",
+    params(("gtin" = String, Path, description = "GTIN-8/12/13/14"), LangQuery),
+    responses(
+        (status = 200, description = "Product JSON, or the HTML page when Accept includes text/html", body = crate::handlers::PublicProduct),
+        (status = 400, description = "Invalid GTIN"),
+        (status = 404, description = "Product not found"),
+        (status = 409, description = "Several lots share this GTIN"),
+    ),
+)]
+pub async fn resolve_gtin(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    client: ScanClient,
+    base: BasePath,
+    Path(gtin): Path<String>,
+    Query(query): Query<LangQuery>,
+) -> impl IntoResponse {
+    resolve(state, headers, client, base, query.lang, gtin, None).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::test_herb;
+
+    fn herb(name: &str, lot: &str) -> Herb {
+        Herb { gtin: Some("08901234567890".to_string()), lot: Some(lot.to_string()), ..test_herb(name, "Asha") }
+    }
+
+    #[test]
+    fn gtins_are_checked_and_padded() {
+        assert_eq!(normalize_gtin("8901234567890"), Ok("08901234567890".to_string()));
+        assert_eq!(normalize_gtin(" 96385074 "), Ok("00000096385074".to_string()));
+        assert!(normalize_gtin("8901234567891").is_err());
+        assert!(normalize_gtin("890123456789A").is_err());
+        assert!(normalize_gtin("12345").is_err());
+    }
+
+    #[test]
+    fn lots_stay_url_safe() {
+        assert!(validate_lot("L-2026.10_a").is_ok());
+        assert!(validate_lot("").is_err());
+        assert!(validate_lot(&"x".repeat(MAX_LOT_LEN + 1)).is_err());
+        assert!(validate_lot("L/1").is_err());
+        assert!(validate_lot("L 1").is_err());
+    }
+
+    #[test]
+    fn digital_link_paths_are_found_under_a_prefix() {
+        assert_eq!(
+            parse_digital_link_path(&["t", "acme", "01", "8901234567890", "10", "L1"]),
+            Some(("08901234567890".to_string(), Some("L1".to_string()))),
+        );
+        assert_eq!(parse_digital_link_path(&["01", "8901234567890"]), Some(("08901234567890".to_string(), None)));
+        assert_eq!(parse_digital_link_path(&["01", "8901234567890", "21", "S1"]), Some(("08901234567890".to_string(), None)));
+        assert_eq!(parse_digital_link_path(&["01", "123"]), None);
+        assert_eq!(parse_digital_link_path(&["p", "herb_1"]), None);
+    }
+
+    #[tokio::test]
+    async fn ambiguous_matches_are_conflicts() {
+        let state = AppState::for_tests();
+        let first = herb("Tulsi", "L1");
+        state.herbs.put(&first).await.unwrap();
+        state.herbs.put(&herb("Ashwagandha", "L2")).await.unwrap();
+
+        assert_eq!(find_herb(&state, "08901234567890", Some("L1")).await.unwrap().id, first.id);
+        assert_eq!(find_herb(&state, "08901234567890", None).await.err().unwrap().0, StatusCode::CONFLICT);
+        assert_eq!(find_herb(&state, "08901234567890", Some("L3")).await.err().unwrap().0, StatusCode::NOT_FOUND);
+
+        state.herbs.put(&herb("Brahmi", "L1")).await.unwrap();
+        let (status, message) = find_herb(&state, "08901234567890", Some("L1")).await.err().unwrap();
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(message, "Multiple herbs share this GTIN and lot");
+    }
+}
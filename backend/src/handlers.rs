@@ -1,43 +1,115 @@
 use axum::{
-    extract::{Path, Json, State},
+    extract::{Path, Json, Query, State},
     http::StatusCode,
     response::IntoResponse,
 };
-use axum::response::Html;
-use axum::http::header;
+use axum::http::{header, HeaderMap, HeaderValue};
 use crate::couchdb::CouchDb;
+use crate::lab::{self, LabSummary, Verdict};
+use crate::attachments::{self, AttachmentInfo};
+use crate::gs1;
+use crate::i18n::{Lang, LangQuery};
+use crate::map;
+use crate::pages::{self, ProductPage, ScanPage};
+use crate::alerts;
+use crate::graphql::LiveFeed;
+use crate::health::Workers;
+use crate::provision::Readiness;
+use crate::settings::Settings;
+use crate::tenants::{BasePath, Branding};
+use crate::store::{HerbStore, StoreError};
+use crate::docs::DocStore;
+use crate::analytics::{self, ScanClient, ScanSource};
+use crate::qr::{self, QrCache, QrOptions, QrQuery};
 use serde::{Deserialize, Serialize};
+use utoipa::{ToSchema, IntoParams};
+use async_graphql::{Enum, SimpleObject};
 use chrono::{DateTime, Utc};
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
-use qrcode::QrCode;
-use image::{Luma, ImageFormat};
-use std::io::Cursor;
+use std::sync::Arc;
 use base64::{engine::general_purpose, Engine as _};
-use std::env;
 use url::Url;
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, ToSchema, SimpleObject)]
+#[graphql(complex)]
 pub struct Herb {
     pub id: String,
     pub name: String,
+    // GraphQL resolves `farmer` to the farmer profile (see graphql.rs)
+    #[graphql(name = "farmerName")]
     pub farmer: String,
     pub location: String,
     pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub status: HerbStatus,
+    // GS1 trade item number (normalised to 14 digits) and batch/lot, used for Digital Link QR codes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gtin: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lot: Option<String>,
+    // Number of packed units carrying this QR code; used by the counterfeit scan rules
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub units: Option<u32>,
+    // Where the batch was grown, shown on the public origin map
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latitude: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub longitude: Option<f64>,
+    // When the status last changed (release or recall)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_changed_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recall_reason: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+// Batch lifecycle. A herb can only be released once its latest lab result passes spec;
+// any batch can be recalled.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, ToSchema, Enum)]
+#[serde(rename_all = "snake_case")]
+pub enum HerbStatus {
+    #[default]
+    Pending,
+    Released,
+    Recalled,
+}
+
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub struct HerbWithQr {
     #[serde(flatten)]
     pub herb: Herb,
     pub qr_code: String, // Base64 PNG
+    #[serde(default)]
+    pub attachments: Vec<AttachmentInfo>,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, ToSchema)]
+pub struct PublicProduct {
+    #[serde(flatten)]
+    pub herb: Herb,
+    pub lab_summary: Option<LabSummary>,
+}
+
+#[derive(Deserialize, ToSchema)]
 pub struct AddHerbRequest {
     pub name: String,
     pub farmer: String,
     pub location: String,
+    pub gtin: Option<String>,
+    pub lot: Option<String>,
+    pub units: Option<u32>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+// Origin coordinates must be given together and be on the globe
+fn validate_origin(latitude: Option<f64>, longitude: Option<f64>) -> Result<(), String> {
+    match (latitude, longitude) {
+        (None, None) => Ok(()),
+        (Some(lat), Some(lon)) if (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon) => Ok(()),
+        (Some(_), Some(_)) => Err("latitude must be within -90..90 and longitude within -180..180".to_string()),
+        _ => Err("latitude and longitude must be given together".to_string()),
+    }
 }
 
 impl AddHerbRequest {
@@ -49,31 +121,127 @@ impl AddHerbRequest {
         if self.name.len() > 100 { return Err("name too long (max 100)".to_string()); }
         if self.farmer.len() > 100 { return Err("farmer too long (max 100)".to_string()); }
         if self.location.len() > 200 { return Err("location too long (max 200)".to_string()); }
+        if let Some(gtin) = &self.gtin { gs1::normalize_gtin(gtin)?; }
+        if let Some(lot) = &self.lot { gs1::validate_lot(lot)?; }
+        if self.units == Some(0) { return Err("units must be at least 1".to_string()); }
+        validate_origin(self.latitude, self.longitude)?;
         Ok(())
     }
 }
 
-#[derive(Deserialize)]
+// Query filters shared by /listHerbs and the export endpoints
+#[derive(Deserialize, Default, Clone, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HerbFilter {
+    pub name: Option<String>,
+    pub farmer: Option<String>,
+    pub location: Option<String>,
+    pub status: Option<HerbStatus>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+}
+
+impl HerbFilter {
+    // Text filters are case-insensitive substring matches
+    pub fn matches(&self, herb: &Herb) -> bool {
+        fn contains(haystack: &str, needle: &Option<String>) -> bool {
+            needle
+                .as_ref()
+                .is_none_or(|n| haystack.to_lowercase().contains(&n.trim().to_lowercase()))
+        }
+        contains(&herb.name, &self.name)
+            && contains(&herb.farmer, &self.farmer)
+            && contains(&herb.location, &self.location)
+            && self.status.is_none_or(|s| herb.status == s)
+            && self.created_after.is_none_or(|t| herb.created_at >= t)
+            && self.created_before.is_none_or(|t| herb.created_at < t)
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
 pub struct ScanRequest {
     pub data: String,
+    // Optional device position; stored rounded to ~11 km
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+// Herb returned by /scan, with any counterfeit-rule warnings for this scan
+#[derive(Serialize, ToSchema)]
+pub struct ScanResponse {
+    #[serde(flatten)]
+    pub herb: Herb,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct UpdateHerbRequest {
     pub name: Option<String>,
     pub farmer: Option<String>,
     pub location: Option<String>,
+    pub status: Option<HerbStatus>,
+    pub gtin: Option<String>,
+    pub lot: Option<String>,
+    pub units: Option<u32>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    // Shown on the public page when status is set to recalled
+    pub recall_reason: Option<String>,
 }
 
-// AppState to hold CouchDB client and database name
+// AppState to hold the stores and database name
 #[derive(Clone)]
 pub struct AppState {
-    pub couch: CouchDb,
+    // Only set when HERB_STORE is couchdb; replication management needs it
+    pub couch: Option<CouchDb>,
     pub db_name: String,
+    pub qr_cache: QrCache,
+    pub herbs: Arc<dyn HerbStore>,
+    // Lab results, specs, scans, alerts and attachments, in the same backend as the herbs
+    pub docs: Arc<dyn DocStore>,
+    // New herbs and scans, for GraphQL subscriptions
+    pub live: LiveFeed,
+    // Base of the public product URLs encoded in QR codes and EPCs (PUBLIC_BASE_URL, or the
+    // tenant's own); without it QR codes carry the herb as JSON
+    pub public_base_url: Option<String>,
+    // Look of the public product pages
+    pub branding: Arc<Branding>,
+    // Startup settings, shared by every tenant
+    pub settings: Arc<Settings>,
+    // Whether startup provisioning has finished
+    pub readiness: Readiness,
+    // Outcomes of background work, for /health/ready
+    pub workers: Workers,
+}
+
+impl AppState {
+    // Replication settings managed through /admin/replications
+    pub fn replications_db(&self) -> String {
+        format!("{}_replications", self.db_name)
+    }
+
+    // Herbs and documents in memory and no CouchDB, so handlers can be exercised without a server
+    #[cfg(test)]
+    pub fn for_tests() -> Self {
+        Self {
+            couch: None,
+            db_name: "herbs_test".to_string(),
+            qr_cache: QrCache::new(16, &Settings::default().qr).expect("no logo to load"),
+            herbs: Arc::new(crate::store::MemoryStore::new()),
+            docs: Arc::new(crate::docs::MemoryDocStore::new()),
+            live: LiveFeed::new(),
+            public_base_url: None,
+            branding: Arc::new(Branding::default()),
+            settings: Arc::new(Settings::default()),
+            readiness: Readiness::default(),
+            workers: Workers::default(),
+        }
+    }
 }
 
 // Generate deterministic hash-based ID from name + farmer
-fn generate_id(name: &str, farmer: &str) -> String {
+pub fn generate_id(name: &str, farmer: &str) -> String {
     let mut hasher = DefaultHasher::new();
     name.hash(&mut hasher);
     farmer.hash(&mut hasher);
@@ -81,59 +249,81 @@ fn generate_id(name: &str, farmer: &str) -> String {
     format!("herb_{:x}", hash)
 }
 
-// Generate Base64 PNG QR code from full herb details (JSON string)
-fn generate_qr_base64(herb: &Herb) -> String {
-    // If PUBLIC_BASE_URL is set, encode a URL to the public product page; otherwise embed JSON
-    let payload = if let Ok(base) = env::var("PUBLIC_BASE_URL") {
-        format!("{}/p/{}", base.trim_end_matches('/'), herb.id)
-    } else {
-        serde_json::to_string(herb).unwrap()
-    };
-    let code = QrCode::new(payload).unwrap();
-    let image = code.render::<Luma<u8>>().build();
-    let mut buffer: Vec<u8> = Vec::new();
-    {
-        let mut cursor = Cursor::new(&mut buffer);
-        image::DynamicImage::ImageLuma8(image)
-            .write_to(&mut cursor, ImageFormat::Png)
-            .unwrap();
-    }
-    general_purpose::STANDARD.encode(&buffer)
-}
-
-fn generate_qr_png_bytes(herb: &Herb) -> Vec<u8> {
-    let payload = if let Ok(base) = env::var("PUBLIC_BASE_URL") {
-        format!("{}/p/{}", base.trim_end_matches('/'), herb.id)
-    } else {
-        serde_json::to_string(herb).unwrap()
-    };
-    let code = QrCode::new(payload).unwrap();
-    let image = code.render::<Luma<u8>>().build();
-    let mut buffer: Vec<u8> = Vec::new();
-    {
-        let mut cursor = Cursor::new(&mut buffer);
-        image::DynamicImage::ImageLuma8(image)
-            .write_to(&mut cursor, ImageFormat::Png)
-            .unwrap();
+// Build a new pending herb from a validated request
+pub fn new_herb(payload: AddHerbRequest) -> Herb {
+    Herb {
+        id: generate_id(&payload.name, &payload.farmer),
+        name: payload.name,
+        farmer: payload.farmer,
+        location: payload.location,
+        created_at: Utc::now(),
+        status: HerbStatus::Pending,
+        gtin: payload.gtin.and_then(|g| gs1::normalize_gtin(&g).ok()),
+        lot: payload.lot,
+        units: payload.units,
+        latitude: payload.latitude,
+        longitude: payload.longitude,
+        status_changed_at: None,
+        recall_reason: None,
     }
-    buffer
+}
+
+// A herb grown in Mysuru with no optional fields; tests set the ones they need
+#[cfg(test)]
+pub(crate) fn test_herb(name: &str, farmer: &str) -> Herb {
+    new_herb(AddHerbRequest {
+        name: name.to_string(),
+        farmer: farmer.to_string(),
+        location: "Mysuru".to_string(),
+        gtin: None,
+        lot: None,
+        units: None,
+        latitude: None,
+        longitude: None,
+    })
 }
 
 // Handlers
 
 // GET /
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "system",
+    responses((status = 200, description = "Greeting", body = String)),
+)]
 pub async fn root() -> &'static str {
     "Hello from Backend!"
 }
 
 // GET /health
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "system",
+    responses((status = 200, description = "Service is up", body = String)),
+)]
 pub async fn health_check() -> impl IntoResponse {
     (StatusCode::OK, "OK")
 }
 
-// POST /resetDb
+// POST /resetDb - The replication registry is kept: its _replicator jobs keep running and must
+// stay manageable through /admin/replications
+#[utoipa::path(
+    post,
+    path = "/resetDb",
+    tag = "system",
+    responses(
+        (status = 200, description = "Herbs and companion documents recreated"),
+        (status = 500, description = "Reset failed"),
+    ),
+)]
 pub async fn reset_db(State(state): State<AppState>) -> impl IntoResponse {
-    match state.couch.reset_db(&state.db_name).await {
+    if let Err(e) = state.docs.reset().await {
+        eprintln!("reset_db failed for companion documents: {}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to reset database").into_response();
+    }
+    match state.herbs.reset().await {
         Ok(_) => (StatusCode::OK, "Database reset successfully").into_response(),
         Err(e) => {
             eprintln!("reset_db failed: {}", e);
@@ -143,6 +333,17 @@ pub async fn reset_db(State(state): State<AppState>) -> impl IntoResponse {
 }
 
 // POST /addHerb
+#[utoipa::path(
+    post,
+    path = "/addHerb",
+    tag = "herbs",
+    request_body = AddHerbRequest,
+    responses(
+        (status = 201, description = "Herb created", body = Herb),
+        (status = 200, description = "A herb with this name and farmer already exists; it is returned unchanged", body = Herb),
+        (status = 400, description = "Validation failed"),
+    ),
+)]
 pub async fn add_herb(
     State(state): State<AppState>,
     Json(payload): Json<AddHerbRequest>,
@@ -150,22 +351,14 @@ pub async fn add_herb(
     if let Err(msg) = payload.validate() {
         return (StatusCode::BAD_REQUEST, msg).into_response();
     }
-    let id = generate_id(&payload.name, &payload.farmer);
-    let created_at = Utc::now();
+    let herb = new_herb(payload);
+    let id = herb.id.clone();
 
-    let herb = Herb {
-        id: id.clone(),
-        name: payload.name,
-        farmer: payload.farmer,
-        location: payload.location,
-        created_at,
-    };
-
-    // Save to CouchDB; if exists, fetch and return existing plain herb instead of erroring
-    if let Err(e) = state.couch.add_doc(&state.db_name, &id, &herb).await {
+    // Save to the store; if exists, fetch and return existing plain herb instead of erroring
+    if let Err(e) = state.herbs.put(&herb).await {
         eprintln!("add_doc failed for id {}: {}", id, e);
-        match state.couch.get_doc::<Herb>(&state.db_name, &id).await {
-            Ok(existing) => {
+        match state.herbs.get(&id).await {
+            Ok((existing, _)) => {
                 return (StatusCode::OK, Json(existing)).into_response();
             }
             Err(fetch_err) => {
@@ -175,24 +368,29 @@ pub async fn add_herb(
         }
     }
 
+    state.live.herb_added(&herb);
     // Return plain herb; QR generated only on demand via get
     (StatusCode::CREATED, Json(herb)).into_response()
 }
 
 // GET /getHerb/{id}
+#[utoipa::path(
+    get,
+    path = "/getHerb/{id}",
+    tag = "herbs",
+    params(("id" = String, Path, description = "Herb id")),
+    responses(
+        (status = 200, description = "Herb with QR code and attachments", body = HerbWithQr),
+        (status = 404, description = "Herb not found"),
+    ),
+)]
 pub async fn get_herb(
     State(state): State<AppState>,
     Path(id): Path<String>,
+    base: BasePath,
 ) -> impl IntoResponse {
-    match state.couch.get_doc::<Herb>(&state.db_name, &id).await {
-        Ok(herb) => {
-            let qr_base64 = generate_qr_base64(&herb);
-            let herb_with_qr = HerbWithQr {
-                herb,
-                qr_code: format!("data:image/png;base64,{}", qr_base64),
-            };
-            (StatusCode::OK, Json(herb_with_qr)).into_response()
-        },
+    match state.herbs.get(&id).await {
+        Ok((herb, rev)) => (StatusCode::OK, Json(with_qr(&state, herb, &rev, &base).await)).into_response(),
         Err(err) => {
             eprintln!("get_herb failed for id {}: {}", id, err);
             (StatusCode::NOT_FOUND, "Herb not found").into_response()
@@ -200,13 +398,69 @@ pub async fn get_herb(
     }
 }
 
+// Herb plus its default QR code as a data URL and its attachments, as returned by the read endpoints
+pub async fn with_qr(state: &AppState, herb: Herb, rev: &str, base: &BasePath) -> HerbWithQr {
+    let key = state.qr_cache.key(&herb.id, rev, state.public_base_url.as_deref(), &QrOptions::default());
+    let qr_code = match state.qr_cache.get_or_render(&herb, &key).await {
+        Ok(bytes) => format!("data:image/png;base64,{}", general_purpose::STANDARD.encode(bytes.as_slice())),
+        Err(err) => {
+            eprintln!("could not render QR for id {}: {}", herb.id, err);
+            String::new()
+        }
+    };
+    let attachments = attachments::list_for_herb(state, &herb.id, &base.0).await.unwrap_or_else(|err| {
+        eprintln!("could not list attachments for id {}: {}", herb.id, err);
+        Vec::new()
+    });
+    HerbWithQr { herb, qr_code, attachments }
+}
+
+// JSON body served at /p/{id} and by the GS1 Digital Link resolver
+pub async fn public_product_json(state: &AppState, mut herb: Herb) -> axum::response::Response {
+    herb.latitude = herb.latitude.map(map::public_coordinate);
+    herb.longitude = herb.longitude.map(map::public_coordinate);
+    let lab_summary = lab::latest_summary(state, &herb.id).await;
+    (StatusCode::OK, Json(PublicProduct { herb, lab_summary })).into_response()
+}
+
+// HTML landing page served at /p/{id}/html and by the GS1 Digital Link resolver
+pub async fn public_product_html(state: &AppState, herb: Herb, lang: Lang, base: &BasePath) -> axum::response::Response {
+    let journey = pages::load_journey(state, &herb).await;
+    let page = ProductPage::new(&herb, &journey, lang, &state.settings.map)
+        .with_brand(&state.branding)
+        .with_base(&base.0);
+    // Map tiles and the tenant's logo come from other origins
+    let image_origins: Vec<String> = map::tile_origin(&state.settings.map)
+        .into_iter()
+        .chain(state.branding.logo_url.as_deref().and_then(pages::image_origin))
+        .collect();
+    let mut response = pages::render_allowing_images(&page, &image_origins);
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_LANGUAGE, HeaderValue::from_static(lang.code()));
+    headers.insert(header::VARY, HeaderValue::from_static("Accept-Language"));
+    response
+}
+
 // GET /p/{id} - Public product endpoint (no QR data), suitable for QR landing page
+#[utoipa::path(
+    get,
+    path = "/p/{id}",
+    tag = "public",
+    params(("id" = String, Path, description = "Herb id")),
+    responses(
+        (status = 200, description = "Public product view", body = PublicProduct),
+        (status = 404, description = "Product not found"),
+    ),
+)]
 pub async fn get_public_product(
     State(state): State<AppState>,
     Path(id): Path<String>,
+    client: ScanClient,
 ) -> impl IntoResponse {
-    match state.couch.get_doc::<Herb>(&state.db_name, &id).await {
-        Ok(herb) => (StatusCode::OK, Json(herb)).into_response(),
+    let result = state.herbs.get(&id).await.map(|(herb, _)| herb);
+    analytics::record(&state, analytics::new_event(&id, result.is_ok(), ScanSource::PublicPage, &client, None));
+    match result {
+        Ok(herb) => public_product_json(&state, herb).await,
         Err(err) => {
             eprintln!("get_public_product failed for id {}: {}", id, err);
             (StatusCode::NOT_FOUND, "Product not found").into_response()
@@ -214,22 +468,30 @@ pub async fn get_public_product(
     }
 }
 
-// GET /p/{id}/html - Simple HTML landing page for a product
+// GET /p/{id}/html?lang=en|hi|kn|ta|mr - HTML landing page for a product; without ?lang the
+// language comes from Accept-Language
+#[utoipa::path(
+    get,
+    path = "/p/{id}/html",
+    tag = "public",
+    params(("id" = String, Path, description = "Herb id"), LangQuery),
+    responses(
+        (status = 200, description = "Traceability page", content_type = "text/html", body = String),
+        (status = 404, description = "Product not found"),
+    ),
+)]
 pub async fn get_public_product_html(
     State(state): State<AppState>,
     Path(id): Path<String>,
+    Query(query): Query<LangQuery>,
+    headers: HeaderMap,
+    client: ScanClient,
+    base: BasePath,
 ) -> impl IntoResponse {
-    match state.couch.get_doc::<Herb>(&state.db_name, &id).await {
-        Ok(herb) => {
-            let html = format!(
-                "<!doctype html><html lang=\"en\"><head><meta charset=\"utf-8\"><meta name=\"viewport\" content=\"width=device-width, initial-scale=1\"><title>{name}</title><style>body{{font-family:sans-serif;margin:24px;}}.card{{max-width:640px;border:1px solid #eee;border-radius:12px;padding:20px;box-shadow:0 2px 8px rgba(0,0,0,0.06);}}.row{{margin:6px 0}}code,a{{color:#0a6;word-break:break-all}}</style></head><body><div class=\"card\"><h1>{name}</h1><div class=\"row\"><strong>Farmer:</strong> {farmer}</div><div class=\"row\"><strong>Location:</strong> {location}</div><div class=\"row\"><strong>ID:</strong> <code>{id}</code></div><div class=\"row\"><img alt=\"QR\" src=\"/qr/{id}\" style=\"margin-top:12px;max-width:240px\"/></div><hr/><div class=\"row\"><a href=\"/p/{id}\">View JSON</a></div></div></body></html>",
-                name = herb.name,
-                farmer = herb.farmer,
-                location = herb.location,
-                id = herb.id
-            );
-            (StatusCode::OK, Html(html)).into_response()
-        },
+    let result = state.herbs.get(&id).await.map(|(herb, _)| herb);
+    analytics::record(&state, analytics::new_event(&id, result.is_ok(), ScanSource::PublicPage, &client, None));
+    match result {
+        Ok(herb) => public_product_html(&state, herb, Lang::negotiate(query.lang.as_deref(), &headers), &base).await,
         Err(err) => {
             eprintln!("get_public_product_html failed for id {}: {}", id, err);
             (StatusCode::NOT_FOUND, "Product not found").into_response()
@@ -237,33 +499,78 @@ pub async fn get_public_product_html(
     }
 }
 
-// GET /qr/{id} - Return QR as PNG image bytes
+// GET /qr/{id}?format=png|svg|pdf&size=&margin=&ec=L|M|Q|H&fg=&bg=&logo=true
+// Rendered images are cached per herb revision and options, and served with an ETag
+#[utoipa::path(
+    get,
+    path = "/qr/{id}",
+    tag = "public",
+    params(("id" = String, Path, description = "Herb id"), QrQuery),
+    responses(
+        (status = 200, description = "QR code image", content(("image/png"), ("image/svg+xml"), ("application/pdf"))),
+        (status = 304, description = "Not modified (If-None-Match)"),
+        (status = 400, description = "Invalid options"),
+        (status = 404, description = "Herb not found"),
+    ),
+)]
 pub async fn get_qr_png(
     State(state): State<AppState>,
     Path(id): Path<String>,
+    Query(query): Query<QrQuery>,
+    headers: HeaderMap,
 ) -> impl IntoResponse {
-    match state.couch.get_doc::<Herb>(&state.db_name, &id).await {
-        Ok(herb) => {
-            let bytes = generate_qr_png_bytes(&herb);
-            (
-                StatusCode::OK,
-                [(header::CONTENT_TYPE, "image/png")],
-                bytes,
-            )
-        }.into_response(),
+    let options = match QrOptions::from_query(&query, state.qr_cache.has_logo()) {
+        Ok(options) => options,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let (herb, rev) = match state.herbs.get(&id).await {
+        Ok(found) => found,
         Err(err) => {
             eprintln!("get_qr_png failed for id {}: {}", id, err);
-            (StatusCode::NOT_FOUND, "Herb not found").into_response()
-        },
+            return (StatusCode::NOT_FOUND, "Herb not found").into_response();
+        }
+    };
+    let key = state.qr_cache.key(&herb.id, &rev, state.public_base_url.as_deref(), &options);
+    let cache_headers = [
+        (header::ETAG, key.etag.clone()),
+        (header::CACHE_CONTROL, qr::CACHE_CONTROL.to_string()),
+    ];
+    // Answered from the key alone, without rendering
+    if headers.get(header::IF_NONE_MATCH).and_then(|v| v.to_str().ok()).is_some_and(|v| key.matches(v)) {
+        return (StatusCode::NOT_MODIFIED, cache_headers).into_response();
+    }
+    match state.qr_cache.get_or_render(&herb, &key).await {
+        Ok(bytes) => (
+            StatusCode::OK,
+            cache_headers,
+            [(header::CONTENT_TYPE, options.format.content_type())],
+            bytes.as_ref().clone(),
+        )
+            .into_response(),
+        Err(err) => {
+            eprintln!("get_qr_png could not render id {}: {}", id, err);
+            (StatusCode::UNPROCESSABLE_ENTITY, "QR payload does not fit the requested error correction level").into_response()
+        }
     }
 }
 
-// GET /listHerbs
+// GET /listHerbs?farmer=..&location=..&status=..
+#[utoipa::path(
+    get,
+    path = "/listHerbs",
+    tag = "herbs",
+    params(HerbFilter),
+    responses((status = 200, description = "Matching herbs", body = [Herb])),
+)]
 pub async fn list_herbs(
     State(state): State<AppState>,
+    Query(filter): Query<HerbFilter>,
 ) -> impl IntoResponse {
-    match state.couch.list_docs::<Herb>(&state.db_name).await {
-        Ok(herbs) => (StatusCode::OK, Json(herbs)).into_response(),
+    match state.herbs.list().await {
+        Ok(herbs) => {
+            let herbs: Vec<Herb> = herbs.into_iter().filter(|h| filter.matches(h)).collect();
+            (StatusCode::OK, Json(herbs)).into_response()
+        },
         Err(err) => {
             eprintln!("list_herbs failed: {}", err);
             (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch herbs").into_response()
@@ -272,11 +579,26 @@ pub async fn list_herbs(
 }
 
 // DELETE /deleteHerb/{id}
+#[utoipa::path(
+    delete,
+    path = "/deleteHerb/{id}",
+    tag = "herbs",
+    params(("id" = String, Path, description = "Herb id")),
+    responses(
+        (status = 200, description = "Herb deleted"),
+        (status = 404, description = "Herb not found"),
+    ),
+)]
 pub async fn delete_herb(
     State(state): State<AppState>,
     Path(id): Path<String>,
 ) -> impl IntoResponse {
-    match state.couch.delete_doc(&state.db_name, &id).await {
+    let result = match state.herbs.rev(&id).await {
+        Ok(Some(rev)) => remove_herb(&state, &id, &rev).await,
+        Ok(None) => Err(StoreError::NotFound),
+        Err(err) => Err(err),
+    };
+    match result {
         Ok(_) => (StatusCode::OK, format!("Herb {} deleted successfully", id)).into_response(),
         Err(err) => {
             eprintln!("delete_herb failed for id {}: {}", id, err);
@@ -285,14 +607,36 @@ pub async fn delete_herb(
     }
 }
 
+// Deletes a herb and, best-effort, its attachments
+pub async fn remove_herb(state: &AppState, id: &str, rev: &str) -> Result<(), StoreError> {
+    state.herbs.delete(id, rev).await?;
+    if let Err(err) = state.docs.delete_files(id).await {
+        eprintln!("could not remove attachments for id {}: {}", id, err);
+    }
+    Ok(())
+}
+
 // PUT /updateHerb/{id}
+#[utoipa::path(
+    put,
+    path = "/updateHerb/{id}",
+    tag = "herbs",
+    params(("id" = String, Path, description = "Herb id")),
+    request_body = UpdateHerbRequest,
+    responses(
+        (status = 200, description = "Updated herb", body = Herb),
+        (status = 400, description = "Validation failed"),
+        (status = 404, description = "Herb not found"),
+        (status = 409, description = "Release needs a passing lab result, or the herb was changed by another request"),
+    ),
+)]
 pub async fn update_herb(
     State(state): State<AppState>,
     Path(id): Path<String>,
     Json(payload): Json<UpdateHerbRequest>,
 ) -> impl IntoResponse {
     // Fetch current doc with revision
-    let (mut herb, rev) = match state.couch.get_doc_with_rev::<Herb>(&state.db_name, &id).await {
+    let (mut herb, rev) = match state.herbs.get(&id).await {
         Ok(pair) => pair,
         Err(err) => {
             eprintln!("update_herb get failed for id {}: {}", id, err);
@@ -300,84 +644,203 @@ pub async fn update_herb(
         }
     };
 
-    // Apply partial updates with basic validation
+    if let Err(response) = apply_update(&state, &mut herb, payload).await {
+        return response;
+    }
+
+    // Persist update with _rev; another write since the fetch makes it stale
+    match state.herbs.update(&herb, &rev).await {
+        Ok(_) => (StatusCode::OK, Json(herb)).into_response(),
+        Err(StoreError::Conflict) => {
+            (StatusCode::CONFLICT, "Herb was changed by another request; retry").into_response()
+        }
+        Err(err) => {
+            eprintln!("update_herb save failed for id {}: {}", id, err);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update herb").into_response()
+        }
+    }
+}
+
+// Applies the fields present in an update, with basic validation.
+// Shared by PUT /updateHerb/{id} and PATCH /api/v1/herbs/{id}.
+pub async fn apply_update(
+    state: &AppState,
+    herb: &mut Herb,
+    payload: UpdateHerbRequest,
+) -> Result<(), axum::response::Response> {
     if let Some(name) = payload.name {
         if name.trim().is_empty() || name.len() > 100 {
-            return (StatusCode::BAD_REQUEST, "invalid name").into_response();
+            return Err((StatusCode::BAD_REQUEST, "invalid name").into_response());
         }
         herb.name = name;
     }
     if let Some(farmer) = payload.farmer {
         if farmer.trim().is_empty() || farmer.len() > 100 {
-            return (StatusCode::BAD_REQUEST, "invalid farmer").into_response();
+            return Err((StatusCode::BAD_REQUEST, "invalid farmer").into_response());
         }
         herb.farmer = farmer;
     }
     if let Some(location) = payload.location {
         if location.trim().is_empty() || location.len() > 200 {
-            return (StatusCode::BAD_REQUEST, "invalid location").into_response();
+            return Err((StatusCode::BAD_REQUEST, "invalid location").into_response());
         }
         herb.location = location;
     }
-
-    // Persist update with _rev
-    if let Err(err) = state.couch.update_doc(&state.db_name, &id, &rev, &herb).await {
-        eprintln!("update_herb save failed for id {}: {}", id, err);
-        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update herb").into_response();
+    if let Some(gtin) = payload.gtin {
+        match gs1::normalize_gtin(&gtin) {
+            Ok(gtin) => herb.gtin = Some(gtin),
+            Err(msg) => return Err((StatusCode::BAD_REQUEST, msg).into_response()),
+        }
+    }
+    if let Some(lot) = payload.lot {
+        if let Err(msg) = gs1::validate_lot(&lot) {
+            return Err((StatusCode::BAD_REQUEST, msg).into_response());
+        }
+        herb.lot = Some(lot);
     }
+    if let Some(units) = payload.units {
+        if units == 0 {
+            return Err((StatusCode::BAD_REQUEST, "units must be at least 1").into_response());
+        }
+        herb.units = Some(units);
+    }
+    if payload.latitude.is_some() || payload.longitude.is_some() {
+        if let Err(msg) = validate_origin(payload.latitude, payload.longitude) {
+            return Err((StatusCode::BAD_REQUEST, msg).into_response());
+        }
+        herb.latitude = payload.latitude;
+        herb.longitude = payload.longitude;
+    }
+    if let Some(reason) = &payload.recall_reason {
+        if reason.len() > 500 {
+            return Err((StatusCode::BAD_REQUEST, "recall_reason too long (max 500)").into_response());
+        }
+    }
+    if let Some(status) = payload.status {
+        // Only batches whose most recent lab result passes spec may be released
+        if status == HerbStatus::Released && herb.status != HerbStatus::Released {
+            let passed = matches!(
+                lab::latest_summary(state, &herb.id).await,
+                Some(LabSummary { verdict: Verdict::Pass, .. })
+            );
+            if !passed {
+                return Err((StatusCode::CONFLICT, "Herb cannot be released without a passing lab result").into_response());
+            }
+        }
+        if status != herb.status {
+            herb.status_changed_at = Some(Utc::now());
+        }
+        herb.recall_reason = match status {
+            HerbStatus::Recalled => payload.recall_reason.or(herb.recall_reason.take()),
+            _ => None,
+        };
+        herb.status = status;
+    } else if herb.status == HerbStatus::Recalled && payload.recall_reason.is_some() {
+        herb.recall_reason = payload.recall_reason;
+    }
+    Ok(())
+}
 
-    (StatusCode::OK, Json(herb)).into_response()
+// What a scanned QR code points at: a herb id, or a GS1 GTIN (plus lot) to look up
+pub enum ScanTarget {
+    Id(String),
+    Gs1 { gtin: String, lot: Option<String> },
 }
 
-fn extract_id_from_scanned_text(input: &str) -> Option<String> {
-    // Try as URL like https://.../p/{id}
+fn extract_id_from_scanned_text(input: &str) -> Option<ScanTarget> {
+    // Try as URL like https://.../p/{id} or a GS1 Digital Link https://.../01/{gtin}/10/{lot}
     if let Ok(url) = Url::parse(input) {
         let path = url.path();
-        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let mut segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        // Tenant URLs look like https://.../t/{tenant}/p/{id}
+        if segments.len() > 2 && segments[0] == "t" {
+            segments.drain(..2);
+        }
         if segments.len() >= 2 && segments[0] == "p" {
-            return Some(segments[1].to_string());
+            return Some(ScanTarget::Id(segments[1].to_string()));
+        }
+        if let Some((gtin, lot)) = gs1::parse_digital_link_path(&segments) {
+            return Some(ScanTarget::Gs1 { gtin, lot });
         }
         // Fallback: last segment
         if let Some(last) = segments.last() {
-            return Some((*last).to_string());
+            return Some(ScanTarget::Id((*last).to_string()));
         }
     }
 
     // Try as JSON containing { "id": "..." } or full Herb
     if let Ok(v) = serde_json::from_str::<serde_json::Value>(input) {
         if let Some(id) = v.get("id").and_then(|x| x.as_str()) {
-            return Some(id.to_string());
+            return Some(ScanTarget::Id(id.to_string()));
         }
     }
 
     // Assume plain id
     let trimmed = input.trim();
     if !trimmed.is_empty() {
-        return Some(trimmed.to_string());
+        return Some(ScanTarget::Id(trimmed.to_string()));
     }
     None
 }
 
 // POST /scan - Accepts scanned QR text and resolves to product info
+#[utoipa::path(
+    post,
+    path = "/scan",
+    tag = "public",
+    request_body = ScanRequest,
+    responses(
+        (status = 200, description = "Scanned herb with counterfeit warnings", body = ScanResponse),
+        (status = 400, description = "No product id in the scanned text"),
+        (status = 404, description = "Product not found"),
+        (status = 409, description = "The GS1 code matches several herbs"),
+    ),
+)]
 pub async fn scan_product(
     State(state): State<AppState>,
+    client: ScanClient,
     Json(payload): Json<ScanRequest>,
 ) -> impl IntoResponse {
-    let Some(id) = extract_id_from_scanned_text(&payload.data) else {
-        return (StatusCode::BAD_REQUEST, "Unable to extract product id").into_response();
+    let coords = payload.latitude.zip(payload.longitude);
+    let (id, result) = match extract_id_from_scanned_text(&payload.data) {
+        Some(ScanTarget::Id(id)) => {
+            let result = state.herbs.get(&id).await.map(|(herb, _)| herb);
+            if let Err(err) = &result {
+                eprintln!("scan_product could not fetch id {}: {}", id, err);
+            }
+            (id, result.ok())
+        }
+        Some(ScanTarget::Gs1 { gtin, lot }) => match gs1::find_herb(&state, &gtin, lot.as_deref()).await {
+            Ok(herb) => (herb.id.clone(), Some(herb)),
+            // Unresolved GTINs are not recorded; there is no herb id to attach them to
+            Err(err) => return err.into_response(),
+        },
+        None => return (StatusCode::BAD_REQUEST, "Unable to extract product id").into_response(),
     };
 
-    match state.couch.get_doc::<Herb>(&state.db_name, &id).await {
-        Ok(herb) => (StatusCode::OK, Json(herb)).into_response(),
-        Err(err) => {
-            eprintln!("scan_product could not fetch id {}: {}", id, err);
-            (StatusCode::NOT_FOUND, "Product not found").into_response()
-        },
+    let event = analytics::new_event(&id, result.is_some(), ScanSource::Scan, &client, coords);
+    let warnings = alerts::check_scan(&state, result.as_ref(), &event).await;
+    analytics::record(&state, event);
+    match result {
+        Some(herb) => (StatusCode::OK, Json(ScanResponse { herb, warnings })).into_response(),
+        None if !warnings.is_empty() => (
+            StatusCode::NOT_FOUND,
+            format!("Product not found. Warning: {}", warnings.join("; ")),
+        )
+            .into_response(),
+        None => (StatusCode::NOT_FOUND, "Product not found").into_response(),
     }
 }
 
 // GET /scan-page - Minimal HTML scanner page (paste/scan input)
-pub async fn scan_page() -> impl IntoResponse {
-    let html = r#"<!doctype html><html lang=\"en\"><head><meta charset=\"utf-8\"><meta name=\"viewport\" content=\"width=device-width, initial-scale=1\"><title>Scan Product</title><style>body{font-family:sans-serif;margin:24px;} .card{max-width:640px;border:1px solid #eee;border-radius:12px;padding:20px;box-shadow:0 2px 8px rgba(0,0,0,0.06);} textarea{width:100%;height:120px} pre{background:#f7f7f7;padding:12px;border-radius:8px;white-space:pre-wrap;word-break:break-all}</style></head><body><div class=\"card\"><h1>Scan Product</h1><p>Paste scanned QR text (URL/JSON/id) below. The page will POST to /scan and show the product.</p><textarea id=\"scan\" placeholder=\"Paste scanned content here...\"></textarea><br/><button id=\"btn\">Submit</button><pre id=\"out\"></pre></div><script>const btn=document.getElementById('btn');const area=document.getElementById('scan');const out=document.getElementById('out');btn.onclick=async()=>{out.textContent='Loading...';try{const res=await fetch('/scan',{method:'POST',headers:{'Content-Type':'application/json'},body:JSON.stringify({data:area.value})});const text=await res.text();try{const json=JSON.parse(text);out.textContent=JSON.stringify(json,null,2);}catch(e){out.textContent=text}}catch(e){out.textContent=String(e)}};</script></body></html>"#;
-    (StatusCode::OK, Html(html)).into_response()
-}
\ No newline at end of file
+#[utoipa::path(
+    get,
+    path = "/scan-page",
+    tag = "public",
+    responses(
+        (status = 200, description = "Scanner page", content_type = "text/html", body = String),
+    ),
+)]
+pub async fn scan_page(base: BasePath) -> impl IntoResponse {
+    pages::render(&ScanPage { base: &base.0 })
+}
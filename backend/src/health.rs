@@ -0,0 +1,358 @@
+use axum::{
+    extract::{Json, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+use crate::handlers::AppState;
+use crate::replication::ReplicationConfig;
+
+// Liveness says the process is serving; readiness checks what it depends on. Only the store,
+// CouchDB and startup provisioning can make the server unready (503); replication lag, disk space
+// and background workers only degrade it, since requests still succeed without them.
+const COUCHDB_TIMEOUT: Duration = Duration::from_secs(2);
+const STORE_TIMEOUT: Duration = Duration::from_secs(2);
+const COUCHDB_SLOW: Duration = Duration::from_millis(500);
+// Pending changes on a replication before it counts as lagging
+const MAX_CHANGES_PENDING: u64 = 1_000;
+// Consecutive failures before a background worker counts as down
+const WORKER_DOWN_AFTER: u32 = 5;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    // Not checked, e.g. no disk path configured
+    Skipped,
+    Degraded,
+    Down,
+}
+
+#[derive(Serialize, Clone, ToSchema)]
+pub struct Component {
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub changes_pending: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub free_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub consecutive_failures: Option<u32>,
+}
+
+impl Component {
+    fn new(status: Status, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            detail: Some(detail.into()),
+            latency_ms: None,
+            changes_pending: None,
+            free_bytes: None,
+            consecutive_failures: None,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct HealthReport {
+    // The worst component status
+    pub status: Status,
+    pub checked_at: DateTime<Utc>,
+    pub components: BTreeMap<&'static str, Component>,
+}
+
+// Outcomes of background work (scan recording, alert raising), shared by every tenant
+#[derive(Clone, Default)]
+pub struct Workers(Arc<Mutex<HashMap<&'static str, WorkerStats>>>);
+
+#[derive(Clone, Default)]
+struct WorkerStats {
+    last_success: Option<DateTime<Utc>>,
+    last_error: Option<String>,
+    consecutive_failures: u32,
+}
+
+impl Workers {
+    pub fn succeeded(&self, worker: &'static str) {
+        let mut workers = self.0.lock().unwrap();
+        let stats = workers.entry(worker).or_default();
+        stats.last_success = Some(Utc::now());
+        stats.consecutive_failures = 0;
+    }
+
+    pub fn failed(&self, worker: &'static str, err: impl ToString) {
+        let mut workers = self.0.lock().unwrap();
+        let stats = workers.entry(worker).or_default();
+        stats.last_error = Some(err.to_string());
+        stats.consecutive_failures += 1;
+    }
+
+    fn components(&self) -> Vec<(&'static str, Component)> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, stats)| {
+                let (status, detail) = match stats.consecutive_failures {
+                    0 => (Status::Ok, match stats.last_success {
+                        Some(at) => format!("last succeeded at {}", at.to_rfc3339()),
+                        None => "no work yet".to_string(),
+                    }),
+                    n => (
+                        if n >= WORKER_DOWN_AFTER { Status::Down } else { Status::Degraded },
+                        stats.last_error.clone().unwrap_or_default(),
+                    ),
+                };
+                let mut component = Component::new(status, detail);
+                component.consecutive_failures = Some(stats.consecutive_failures);
+                (*name, component)
+            })
+            .collect()
+    }
+}
+
+// Skipped unless HERB_STORE=couchdb
+fn couch_not_used(state: &AppState) -> Component {
+    Component::new(Status::Skipped, format!("HERB_STORE is {}; CouchDB is not used", state.settings.store.kind.name()))
+}
+
+async fn check_couchdb(state: &AppState) -> Component {
+    let Some(couch) = &state.couch else {
+        return couch_not_used(state);
+    };
+    let started = Instant::now();
+    let result = tokio::time::timeout(COUCHDB_TIMEOUT, couch.session_user()).await;
+    let latency = started.elapsed();
+    let mut component = match result {
+        Err(_) => Component::new(Status::Down, format!("no answer within {}s", COUCHDB_TIMEOUT.as_secs())),
+        Ok(Err(e)) if matches!(e.status(), Some(reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN)) => {
+            Component::new(Status::Down, "credentials rejected")
+        }
+        Ok(Err(e)) => Component::new(Status::Down, e.to_string()),
+        Ok(Ok(None)) => Component::new(Status::Down, "not authenticated"),
+        Ok(Ok(Some(user))) if latency > COUCHDB_SLOW => Component::new(Status::Degraded, format!("slow; logged in as {}", user)),
+        Ok(Ok(Some(user))) => Component::new(Status::Ok, format!("logged in as {}", user)),
+    };
+    component.latency_ms = Some(latency.as_millis() as u64);
+    component
+}
+
+// Counts the herbs, so every backend (SQL included) is asked on each check
+async fn check_store(state: &AppState) -> Component {
+    let started = Instant::now();
+    let result = tokio::time::timeout(STORE_TIMEOUT, state.herbs.count()).await;
+    let latency = started.elapsed();
+    let mut component = match result {
+        Err(_) => Component::new(Status::Down, format!("no answer within {}s", STORE_TIMEOUT.as_secs())),
+        Ok(Err(e)) => Component::new(Status::Down, e.to_string()),
+        Ok(Ok(count)) => Component::new(Status::Ok, format!("{} herbs in {}", count, state.settings.store.kind.name())),
+    };
+    component.latency_ms = Some(latency.as_millis() as u64);
+    component
+}
+
+fn check_provisioning(state: &AppState) -> Component {
+    let report = state.readiness.report();
+    if report.ready {
+        Component::new(Status::Ok, "store provisioned")
+    } else {
+        let detail = report.last_error.unwrap_or_else(|| "not finished yet".to_string());
+        Component::new(Status::Down, format!("attempt {}: {}", report.attempts, detail))
+    }
+}
+
+// Changes-feed lag of the enabled replications; a crashed or failed one is degraded too
+async fn check_replications(state: &AppState) -> Component {
+    let Some(couch) = &state.couch else {
+        return couch_not_used(state);
+    };
+    let selector = serde_json::json!({ "enabled": true });
+    let configs = match couch.find_docs::<ReplicationConfig>(&state.replications_db(), selector).await {
+        Ok(configs) => configs,
+        Err(e) => return Component::new(Status::Degraded, format!("cannot list replications: {}", e)),
+    };
+    if configs.is_empty() {
+        return Component::new(Status::Ok, "no replications");
+    }
+    let mut pending = 0;
+    let mut problems = Vec::new();
+    for config in &configs {
+        match couch.replication_status(&config.id).await {
+            Ok(Some(status)) => {
+                pending = pending.max(status.changes_pending.unwrap_or(0));
+                if matches!(status.state.as_str(), "crashing" | "failed") {
+                    problems.push(format!("{} is {}", config.id, status.state));
+                }
+            }
+            Ok(None) => problems.push(format!("{} is not scheduled", config.id)),
+            Err(e) => problems.push(format!("{}: {}", config.id, e)),
+        }
+    }
+    if pending > MAX_CHANGES_PENDING {
+        problems.push(format!("{} changes pending", pending));
+    }
+    let mut component = if problems.is_empty() {
+        Component::new(Status::Ok, format!("{} replications", configs.len()))
+    } else {
+        Component::new(Status::Degraded, problems.join("; "))
+    };
+    component.changes_pending = Some(pending);
+    component
+}
+
+// Free space on the disk holding `path`: the mount point with the longest matching prefix
+fn free_space(path: &Path) -> Option<u64> {
+    let path = path.canonicalize().ok()?;
+    let disks = sysinfo::Disks::new_with_refreshed_list();
+    disks
+        .list()
+        .iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| disk.available_space())
+}
+
+// Attachments live in the selected store, so this needs its data directory (CouchDB's or the
+// SQL database's) as seen from here
+fn check_disk(state: &AppState) -> Component {
+    let Some(path) = state.settings.attachments_disk_path.as_deref() else {
+        return Component::new(Status::Skipped, "set ATTACHMENTS_DISK_PATH to the store's data directory");
+    };
+    let Some(free) = free_space(Path::new(path)) else {
+        return Component::new(Status::Degraded, format!("cannot read free space of {}", path));
+    };
+    let minimum = state.settings.min_free_disk_mb * 1024 * 1024;
+    let status = if free < crate::attachments::MAX_ATTACHMENT_BYTES as u64 {
+        Status::Down
+    } else if free < minimum {
+        Status::Degraded
+    } else {
+        Status::Ok
+    };
+    let mut component = Component::new(status, format!("{} MiB free", free / (1024 * 1024)));
+    component.free_bytes = Some(free);
+    component
+}
+
+async fn report(state: &AppState) -> HealthReport {
+    let (store, couchdb, replication) = tokio::join!(check_store(state), check_couchdb(state), check_replications(state));
+    let mut components = BTreeMap::from([
+        ("store", store),
+        ("couchdb", couchdb),
+        ("provisioning", check_provisioning(state)),
+        ("replication", replication),
+        ("disk", check_disk(state)),
+    ]);
+    components.extend(state.workers.components());
+    let status = components.values().map(|c| c.status).max().unwrap_or(Status::Ok);
+    HealthReport { status, checked_at: Utc::now(), components }
+}
+
+// Components whose failure makes the server unready
+fn critical(name: &str) -> bool {
+    matches!(name, "store" | "couchdb" | "provisioning")
+}
+
+// GET /health/live
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    tag = "system",
+    responses((status = 200, description = "The process is serving requests")),
+)]
+pub async fn live() -> impl IntoResponse {
+    (StatusCode::OK, Json(serde_json::json!({ "status": Status::Ok })))
+}
+
+// GET /health/ready - Only the status is public; the components (hosts, users, errors) need the
+// admin token
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "system",
+    responses(
+        (status = 200, description = "Ready; status may still be degraded", body = HealthReport),
+        (status = 503, description = "The store or CouchDB is down, or not yet provisioned", body = HealthReport),
+    ),
+)]
+pub async fn ready(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    let report = report(&state).await;
+    let down = report.components.iter().any(|(name, c)| critical(name) && c.status == Status::Down);
+    let status = if down { StatusCode::SERVICE_UNAVAILABLE } else { StatusCode::OK };
+    if crate::admin::is_admin(&headers, state.settings.admin_token.as_deref()) {
+        (status, Json(report)).into_response()
+    } else {
+        (status, Json(serde_json::json!({ "status": report.status }))).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::couchdb::CouchDb;
+
+    #[tokio::test]
+    async fn unreachable_couchdb_makes_the_server_unready() {
+        let state = AppState {
+            couch: Some(CouchDb::new("http://127.0.0.1:9", "admin", "secret")),
+            ..AppState::for_tests()
+        };
+        state.workers.succeeded("scan-recorder");
+        let report = report(&state).await;
+        assert_eq!(report.status, Status::Down);
+        assert_eq!(report.components["couchdb"].status, Status::Down);
+        assert_eq!(report.components["provisioning"].status, Status::Down);
+        assert_eq!(report.components["disk"].status, Status::Skipped);
+        assert_eq!(report.components["scan-recorder"].status, Status::Ok);
+
+        let response = ready(State(state), HeaderMap::new()).await.into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    async fn ready_body(state: &AppState, token: Option<&str>) -> serde_json::Value {
+        let mut headers = HeaderMap::new();
+        if let Some(token) = token {
+            headers.insert(http::header::AUTHORIZATION, format!("Bearer {}", token).parse().unwrap());
+        }
+        let response = ready(State(state.clone()), headers).await.into_response();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn components_need_the_admin_token() {
+        let mut state = AppState::for_tests();
+        let mut settings = (*state.settings).clone();
+        settings.admin_token = Some("operator-token-123".to_string());
+        state.settings = Arc::new(settings);
+        let public = ready_body(&state, None).await;
+        assert!(public.get("status").is_some());
+        assert!(public.get("components").is_none());
+        assert!(ready_body(&state, Some("wrong")).await.get("components").is_none());
+        let full = ready_body(&state, Some("operator-token-123")).await;
+        assert_eq!(full["components"]["store"]["status"], "ok");
+    }
+
+    #[test]
+    fn failing_workers_degrade_then_go_down() {
+        let workers = Workers::default();
+        let status = |w: &Workers| w.components()[0].1.status;
+        workers.failed("alert-raiser", "boom");
+        assert_eq!(status(&workers), Status::Degraded);
+        for _ in 1..WORKER_DOWN_AFTER {
+            workers.failed("alert-raiser", "boom");
+        }
+        assert_eq!(status(&workers), Status::Down);
+        workers.succeeded("alert-raiser");
+        assert_eq!(status(&workers), Status::Ok);
+    }
+}
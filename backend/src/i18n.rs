@@ -0,0 +1,193 @@
+use axum::http::{header, HeaderMap};
+use serde::Deserialize;
+use utoipa::IntoParams;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+// Catalogues are plain "key = value" files compiled into the binary
+const CATALOGUES: [(Lang, &str); 5] = [
+    (Lang::En, include_str!("../locales/en.txt")),
+    (Lang::Hi, include_str!("../locales/hi.txt")),
+    (Lang::Kn, include_str!("../locales/kn.txt")),
+    (Lang::Ta, include_str!("../locales/ta.txt")),
+    (Lang::Mr, include_str!("../locales/mr.txt")),
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum Lang {
+    #[default]
+    En,
+    Hi,
+    Kn,
+    Ta,
+    Mr,
+}
+
+impl Lang {
+    pub const ALL: [Lang; 5] = [Lang::En, Lang::Hi, Lang::Kn, Lang::Ta, Lang::Mr];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Lang::En => "en",
+            Lang::Hi => "hi",
+            Lang::Kn => "kn",
+            Lang::Ta => "ta",
+            Lang::Mr => "mr",
+        }
+    }
+
+    // Name of the language in that language, for the language switcher
+    pub fn native_name(self) -> &'static str {
+        match self {
+            Lang::En => "English",
+            Lang::Hi => "हिन्दी",
+            Lang::Kn => "ಕನ್ನಡ",
+            Lang::Ta => "தமிழ்",
+            Lang::Mr => "मराठी",
+        }
+    }
+
+    // Accepts "hi", "hi-IN", "HI_in" and so on
+    pub fn parse(tag: &str) -> Option<Self> {
+        let primary = tag.trim().split(['-', '_']).next()?.to_ascii_lowercase();
+        Lang::ALL.into_iter().find(|l| l.code() == primary)
+    }
+
+    // ?lang= wins; otherwise the highest-weighted supported Accept-Language entry; otherwise English
+    pub fn negotiate(query: Option<&str>, headers: &HeaderMap) -> Self {
+        if let Some(lang) = query.and_then(Lang::parse) {
+            return lang;
+        }
+        let Some(accept) = headers.get(header::ACCEPT_LANGUAGE).and_then(|v| v.to_str().ok()) else {
+            return Lang::default();
+        };
+        let mut best: Option<(Lang, f32)> = None;
+        for entry in accept.split(',') {
+            let mut parts = entry.split(';');
+            let Some(lang) = parts.next().and_then(Lang::parse) else { continue };
+            let weight = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if weight > 0.0 && best.is_none_or(|(_, w)| weight > w) {
+                best = Some((lang, weight));
+            }
+        }
+        best.map(|(lang, _)| lang).unwrap_or_default()
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LangQuery {
+    pub lang: Option<String>,
+}
+
+fn parse_catalogue(source: &str) -> HashMap<&str, &str> {
+    source
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .collect()
+}
+
+fn catalogues() -> &'static HashMap<Lang, HashMap<&'static str, &'static str>> {
+    static CATALOGUES_BY_LANG: OnceLock<HashMap<Lang, HashMap<&'static str, &'static str>>> = OnceLock::new();
+    CATALOGUES_BY_LANG.get_or_init(|| CATALOGUES.iter().map(|(lang, src)| (*lang, parse_catalogue(src))).collect())
+}
+
+fn lookup_in(all: &HashMap<Lang, HashMap<&'static str, &'static str>>, lang: Lang, key: &str) -> Option<&'static str> {
+    all.get(&lang)
+        .and_then(|c| c.get(key))
+        .or_else(|| all.get(&Lang::En).and_then(|c| c.get(key)))
+        .copied()
+}
+
+// Translation lookups for one language, with English as the fallback
+#[derive(Clone, Copy)]
+pub struct Translator {
+    pub lang: Lang,
+}
+
+impl Translator {
+    pub fn new(lang: Lang) -> Self {
+        Self { lang }
+    }
+
+    fn lookup(&self, key: &str) -> Option<&'static str> {
+        lookup_in(catalogues(), self.lang, key)
+    }
+
+    // Missing keys render as the key itself so gaps are visible rather than blank
+    pub fn get<'a>(&self, key: &'a str) -> &'a str {
+        self.lookup(key).unwrap_or(key)
+    }
+
+    // Known species are translated; anything else is shown exactly as the farmer entered it
+    pub fn species(&self, name: &str) -> Option<&'static str> {
+        self.lookup(&format!("species.{}", name.trim().to_lowercase()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn accept(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_LANGUAGE, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn regional_tags_match_their_language() {
+        assert_eq!(Lang::parse("hi-IN"), Some(Lang::Hi));
+        assert_eq!(Lang::parse(" KN_in "), Some(Lang::Kn));
+        assert_eq!(Lang::parse("fr-FR"), None);
+        assert_eq!(Lang::negotiate(None, &accept("hi-IN")), Lang::Hi);
+    }
+
+    #[test]
+    fn the_highest_weight_wins() {
+        assert_eq!(Lang::negotiate(None, &accept("en;q=0.5, ta;q=0.9, hi;q=0.7")), Lang::Ta);
+        // No q means 1.0, and a tie keeps the earlier entry
+        assert_eq!(Lang::negotiate(None, &accept("mr, kn;q=1.0")), Lang::Mr);
+        // Unsupported and refused languages are passed over
+        assert_eq!(Lang::negotiate(None, &accept("fr, de;q=0.9, hi;q=0, kn;q=0.1")), Lang::Kn);
+        assert_eq!(Lang::negotiate(None, &accept("fr, de")), Lang::En);
+        assert_eq!(Lang::negotiate(None, &HeaderMap::new()), Lang::En);
+    }
+
+    #[test]
+    fn the_query_overrides_the_header_unless_unsupported() {
+        assert_eq!(Lang::negotiate(Some("mr"), &accept("hi")), Lang::Mr);
+        assert_eq!(Lang::negotiate(Some("xx"), &accept("hi")), Lang::Hi);
+    }
+
+    #[test]
+    fn missing_keys_fall_back_to_english_then_the_key() {
+        let all: HashMap<Lang, HashMap<&'static str, &'static str>> = [
+            (Lang::En, parse_catalogue("# comment\ngreeting = Hello\nfarewell = Bye")),
+            (Lang::Hi, parse_catalogue("greeting = नमस्ते")),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(lookup_in(&all, Lang::Hi, "greeting"), Some("नमस्ते"));
+        assert_eq!(lookup_in(&all, Lang::Hi, "farewell"), Some("Bye"));
+        assert_eq!(lookup_in(&all, Lang::Ta, "farewell"), Some("Bye"));
+        assert_eq!(lookup_in(&all, Lang::Hi, "unknown"), None);
+        assert_eq!(Translator::new(Lang::Hi).get("no.such.key"), "no.such.key");
+    }
+
+    #[test]
+    fn every_catalogue_has_every_english_key() {
+        let all = catalogues();
+        for lang in Lang::ALL {
+            let missing: Vec<&&str> = all[&Lang::En].keys().filter(|k| !all[&lang].contains_key(*k)).collect();
+            assert!(missing.is_empty(), "{} lacks {:?}", lang.code(), missing);
+        }
+    }
+}
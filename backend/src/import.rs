@@ -0,0 +1,360 @@
+use axum::{
+    body::Bytes,
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use calamine::{Reader, Xlsx};
+use serde::{Deserialize, Serialize};
+use utoipa::{ToSchema, IntoParams};
+use std::collections::HashMap;
+use std::io::Cursor;
+use crate::handlers::{generate_id, new_herb, AddHerbRequest, AppState};
+
+pub const MAX_IMPORT_BYTES: usize = 10 * 1024 * 1024;
+const MAX_IMPORT_ROWS: usize = 5000;
+const XLSX_CONTENT_TYPE: &str = "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet";
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportQuery {
+    #[serde(default)]
+    pub dry_run: bool,
+    // "csv" or "xlsx"; defaults to the request Content-Type
+    pub format: Option<String>,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RowStatus {
+    Valid,
+    Invalid,
+    // Skipped because the id repeats an earlier row or an existing herb
+    Duplicate,
+    Created,
+    Failed,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ImportRowReport {
+    // 1-based spreadsheet row number (the header is row 1)
+    pub row: usize,
+    pub id: Option<String>,
+    pub status: RowStatus,
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub total_rows: usize,
+    pub valid_rows: usize,
+    pub invalid_rows: usize,
+    pub duplicate_rows: usize,
+    pub created: usize,
+    pub rows: Vec<ImportRowReport>,
+}
+
+// Raw cell values of one data row, keyed by lower-cased header
+type RawRow = HashMap<String, String>;
+
+// Lower-cased header row and the data rows under it
+struct Sheet {
+    headers: Vec<String>,
+    rows: Vec<RawRow>,
+}
+
+const REQUIRED_COLUMNS: [&str; 3] = ["name", "farmer", "location"];
+
+fn parse_csv(bytes: &[u8]) -> Result<Sheet, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(bytes);
+    let headers: Vec<String> = reader
+        .headers()
+        .map_err(|e| format!("invalid CSV header: {}", e))?
+        .iter()
+        .map(|h| h.trim_start_matches('\u{feff}').to_lowercase())
+        .collect();
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| format!("invalid CSV: {}", e))?;
+        rows.push(headers.iter().cloned().zip(record.iter().map(str::to_string)).collect());
+    }
+    Ok(Sheet { headers, rows })
+}
+
+fn parse_xlsx(bytes: &[u8]) -> Result<Sheet, String> {
+    let mut workbook: Xlsx<_> = Xlsx::new(Cursor::new(bytes)).map_err(|e| format!("invalid XLSX: {}", e))?;
+    let range = workbook
+        .worksheet_range_at(0)
+        .ok_or_else(|| "XLSX has no worksheets".to_string())?
+        .map_err(|e| format!("invalid XLSX: {}", e))?;
+
+    let mut iter = range.rows();
+    let headers: Vec<String> = match iter.next() {
+        Some(cells) => cells.iter().map(|c| c.to_string().trim().to_lowercase()).collect(),
+        None => return Ok(Sheet { headers: Vec::new(), rows: Vec::new() }),
+    };
+    let rows = iter
+        .map(|cells| {
+            headers
+                .iter()
+                .cloned()
+                .zip(cells.iter().map(|c| c.to_string().trim().to_string()))
+                .collect()
+        })
+        .collect();
+    Ok(Sheet { headers, rows })
+}
+
+// The request for one row, plus an error for each number that could not be read
+fn row_to_request(raw: &RawRow) -> (AddHerbRequest, Vec<String>) {
+    let field = |key: &str| raw.get(key).cloned().unwrap_or_default();
+    // Optional columns; blank cells are treated as absent
+    let optional = |key: &str| raw.get(key).filter(|v| !v.is_empty()).cloned();
+    fn number<T: std::str::FromStr>(value: Option<String>, key: &str, errors: &mut Vec<String>) -> Option<T> {
+        let value = value?;
+        let parsed = value.parse().ok();
+        if parsed.is_none() {
+            errors.push(format!("{} is not a number: {}", key, value));
+        }
+        parsed
+    }
+    let mut errors = Vec::new();
+    let units = number(optional("units"), "units", &mut errors);
+    let latitude = number(optional("latitude"), "latitude", &mut errors);
+    let longitude = number(optional("longitude"), "longitude", &mut errors);
+    let request = AddHerbRequest {
+        name: field("name"),
+        farmer: field("farmer"),
+        location: field("location"),
+        gtin: optional("gtin"),
+        lot: optional("lot"),
+        units,
+        latitude,
+        longitude,
+    };
+    (request, errors)
+}
+
+// Handlers
+
+// POST /herbs/import?dry_run=true - CSV or XLSX with name, farmer and location columns
+// (gtin, lot, units, latitude and longitude are optional)
+#[utoipa::path(
+    post,
+    path = "/herbs/import",
+    tag = "herbs",
+    params(ImportQuery),
+    request_body(content(("text/csv"), ("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet")), description = "CSV or XLSX with a header row"),
+    responses(
+        (status = 200, description = "Dry-run or no-op import report", body = ImportReport),
+        (status = 201, description = "Per-row import report", body = ImportReport),
+        (status = 400, description = "Unreadable file"),
+        (status = 413, description = "Too many rows"),
+    ),
+)]
+pub async fn import_herbs(
+    State(state): State<AppState>,
+    Query(query): Query<ImportQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let is_xlsx = match query.format.as_deref() {
+        Some("xlsx") => true,
+        Some("csv") => false,
+        Some(other) => return (StatusCode::BAD_REQUEST, format!("unsupported format: {}", other)).into_response(),
+        None => content_type.starts_with(XLSX_CONTENT_TYPE),
+    };
+
+    let parsed = if is_xlsx { parse_xlsx(&body) } else { parse_csv(&body) };
+    let Sheet { headers, rows: raw_rows } = match parsed {
+        Ok(sheet) => sheet,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    if REQUIRED_COLUMNS.iter().any(|col| !headers.iter().any(|h| h == col)) {
+        return (StatusCode::BAD_REQUEST, "header must contain name, farmer and location columns").into_response();
+    }
+    if raw_rows.is_empty() {
+        return (StatusCode::BAD_REQUEST, "no data rows").into_response();
+    }
+    if raw_rows.len() > MAX_IMPORT_ROWS {
+        return (StatusCode::PAYLOAD_TOO_LARGE, format!("too many rows (max {})", MAX_IMPORT_ROWS)).into_response();
+    }
+
+    // Validate every row and flag ids repeated within the file
+    let mut rows = Vec::with_capacity(raw_rows.len());
+    let mut requests = Vec::with_capacity(raw_rows.len());
+    let mut first_seen: HashMap<String, usize> = HashMap::new();
+    for (index, raw) in raw_rows.iter().enumerate() {
+        let row = index + 2;
+        let (request, errors) = row_to_request(raw);
+        let mut report = ImportRowReport { row, id: None, status: RowStatus::Valid, errors, warnings: Vec::new() };
+        if let Err(msg) = request.validate() {
+            report.errors.push(msg);
+        }
+        if !report.errors.is_empty() {
+            report.status = RowStatus::Invalid;
+        } else {
+            let id = generate_id(&request.name, &request.farmer);
+            if let Some(first) = first_seen.get(&id) {
+                report.status = RowStatus::Duplicate;
+                report.warnings.push(format!("same name and farmer as row {}", first));
+            } else {
+                first_seen.insert(id.clone(), row);
+            }
+            report.id = Some(id);
+        }
+        rows.push(report);
+        requests.push(request);
+    }
+
+    // Flag rows whose herb already exists in the database
+    let candidate_ids: Vec<String> = first_seen.keys().cloned().collect();
+    let existing = if candidate_ids.is_empty() {
+        Vec::new()
+    } else {
+        match state.herbs.existing_ids(&candidate_ids).await {
+            Ok(ids) => ids,
+            Err(err) => {
+                eprintln!("import_herbs existing id lookup failed: {}", err);
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to check existing herbs").into_response();
+            }
+        }
+    };
+    for report in rows.iter_mut().filter(|r| r.status == RowStatus::Valid) {
+        if report.id.as_ref().is_some_and(|id| existing.contains(id)) {
+            report.status = RowStatus::Duplicate;
+            report.warnings.push("herb already exists".to_string());
+        }
+    }
+
+    let mut created = 0;
+    if !query.dry_run {
+        let (indices, herbs): (Vec<usize>, Vec<_>) = rows
+            .iter()
+            .zip(requests)
+            .enumerate()
+            .filter(|(_, (report, _))| report.status == RowStatus::Valid)
+            .map(|(i, (_, request))| (i, new_herb(request)))
+            .unzip();
+        if !herbs.is_empty() {
+            let results = match state.herbs.put_many(&herbs).await {
+                Ok(results) => results,
+                Err(err) => {
+                    eprintln!("import_herbs bulk write failed: {}", err);
+                    return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to import herbs").into_response();
+                }
+            };
+            // put_many returns one result per submitted herb, in order
+            for ((i, herb), result) in indices.into_iter().zip(&herbs).zip(results) {
+                match result {
+                    Ok(()) => {
+                        state.live.herb_added(herb);
+                        rows[i].status = RowStatus::Created;
+                        created += 1;
+                    }
+                    Err(err) => {
+                        rows[i].status = RowStatus::Failed;
+                        rows[i].errors.push(err.to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    let count = |status: RowStatus| rows.iter().filter(|r| r.status == status).count();
+    let report = ImportReport {
+        dry_run: query.dry_run,
+        total_rows: rows.len(),
+        valid_rows: count(RowStatus::Valid) + count(RowStatus::Created) + count(RowStatus::Failed),
+        invalid_rows: count(RowStatus::Invalid),
+        duplicate_rows: count(RowStatus::Duplicate),
+        created,
+        rows,
+    };
+    let status = if query.dry_run || created == 0 { StatusCode::OK } else { StatusCode::CREATED };
+    (status, Json(report)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::to_bytes;
+    use serde_json::Value;
+
+    async fn import(state: &AppState, dry_run: bool, csv: &str) -> (StatusCode, Value) {
+        let query = ImportQuery { dry_run, format: Some("csv".to_string()) };
+        let response = import_herbs(State(state.clone()), Query(query), HeaderMap::new(), Bytes::from(csv.to_string()))
+            .await
+            .into_response();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+    }
+
+    fn statuses(report: &Value) -> Vec<&str> {
+        report["rows"].as_array().unwrap().iter().map(|r| r["status"].as_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn unreadable_numbers_are_row_errors() {
+        let raw: RawRow = [("name", "Tulsi"), ("units", "ten"), ("latitude", "12.3"), ("longitude", "east")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let (request, errors) = row_to_request(&raw);
+        assert_eq!(request.latitude, Some(12.3));
+        assert_eq!(errors, ["units is not a number: ten", "longitude is not a number: east"]);
+    }
+
+    #[tokio::test]
+    async fn the_header_is_checked_even_when_the_first_row_is_short() {
+        let state = AppState::for_tests();
+        let (status, report) = import(&state, true, "name,farmer,location\nTulsi,Asha\n").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(statuses(&report), ["invalid"]);
+
+        let (status, _) = import(&state, true, "name,farmer\nTulsi,Asha\n").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn a_dry_run_reports_without_writing() {
+        let state = AppState::for_tests();
+        let csv = "Name,Farmer,Location,Units\nTulsi,Asha,Mysuru,10\nBrahmi,Ravi,Hassan,lots\n";
+        let (status, report) = import(&state, true, csv).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(statuses(&report), ["valid", "invalid"]);
+        assert_eq!(report["rows"][1]["row"], 3);
+        assert_eq!((report["valid_rows"].as_u64(), report["invalid_rows"].as_u64()), (Some(1), Some(1)));
+        assert_eq!(report["created"], 0);
+        let id = report["rows"][0]["id"].as_str().unwrap();
+        assert!(state.herbs.get(id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn repeats_and_existing_herbs_are_duplicates() {
+        let state = AppState::for_tests();
+        let (status, report) = import(&state, false, "name,farmer,location\nTulsi,Asha,Mysuru\n").await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(statuses(&report), ["created"]);
+
+        let csv = "name,farmer,location\nTulsi,Asha,Mysuru\nBrahmi,Ravi,Hassan\nBrahmi,Ravi,Udupi\n";
+        let (status, report) = import(&state, false, csv).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(statuses(&report), ["duplicate", "created", "duplicate"]);
+        assert_eq!(report["rows"][0]["warnings"][0], "herb already exists");
+        assert_eq!(report["rows"][2]["warnings"][0], "same name and farmer as row 3");
+        assert_eq!(report["created"], 1);
+    }
+}
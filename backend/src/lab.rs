@@ -0,0 +1,452 @@
+use axum::{
+    extract::{Path, Json, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use async_graphql::{Enum, SimpleObject};
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use url::Url;
+use crate::docs::{Collection, DocQuery};
+use crate::handlers::AppState;
+use crate::store::StoreError;
+
+// Measured quality parameters from a certificate of analysis (CoA).
+// Every value is optional because labs rarely test the full panel on every batch.
+#[derive(Serialize, Deserialize, Clone, Default, ToSchema, SimpleObject)]
+pub struct LabMeasurements {
+    pub moisture_pct: Option<f64>,
+    pub heavy_metals_ppm: Option<f64>,
+    pub pesticide_residue_ppm: Option<f64>,
+    pub aflatoxin_ppb: Option<f64>,
+    pub microbial_load_cfu_g: Option<f64>,
+    pub active_marker_pct: Option<f64>,
+}
+
+// Per-species specification limits. All contaminants are upper bounds,
+// the active marker is a lower bound (minimum potency).
+#[derive(Serialize, Deserialize, Clone, Default, ToSchema)]
+pub struct SpecLimits {
+    pub species: String,
+    pub moisture_max_pct: Option<f64>,
+    pub heavy_metals_max_ppm: Option<f64>,
+    pub pesticide_residue_max_ppm: Option<f64>,
+    pub aflatoxin_max_ppb: Option<f64>,
+    pub microbial_load_max_cfu_g: Option<f64>,
+    pub active_marker_min_pct: Option<f64>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SpecLimitsRequest {
+    pub moisture_max_pct: Option<f64>,
+    pub heavy_metals_max_ppm: Option<f64>,
+    pub pesticide_residue_max_ppm: Option<f64>,
+    pub aflatoxin_max_ppb: Option<f64>,
+    pub microbial_load_max_cfu_g: Option<f64>,
+    pub active_marker_min_pct: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema, Enum)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Pass,
+    Fail,
+    // No specification is registered for the species, or none of its limits
+    // were measured, so nothing can be released
+    NoSpec,
+}
+
+#[derive(Serialize, Deserialize, Clone, ToSchema, SimpleObject)]
+pub struct ParameterCheck {
+    pub parameter: String,
+    pub value: f64,
+    pub limit: f64,
+    // "max" or "min"
+    pub bound: String,
+    pub passed: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, ToSchema, SimpleObject)]
+pub struct LabResult {
+    pub id: String,
+    pub herb_id: String,
+    pub lab_name: String,
+    pub tested_at: DateTime<Utc>,
+    pub measurements: LabMeasurements,
+    // http(s) URL of the CoA document
+    pub coa_url: Option<String>,
+    pub verdict: Verdict,
+    pub checks: Vec<ParameterCheck>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct AddLabResultRequest {
+    pub lab_name: String,
+    pub tested_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub measurements: LabMeasurements,
+    pub coa_url: Option<String>,
+}
+
+impl AddLabResultRequest {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.lab_name.trim().is_empty() { return Err("lab_name is required".to_string()); }
+        if self.lab_name.len() > 100 { return Err("lab_name too long (max 100)".to_string()); }
+        let m = &self.measurements;
+        let values = [
+            m.moisture_pct,
+            m.heavy_metals_ppm,
+            m.pesticide_residue_ppm,
+            m.aflatoxin_ppb,
+            m.microbial_load_cfu_g,
+            m.active_marker_pct,
+        ];
+        if values.iter().flatten().any(|v| !v.is_finite() || *v < 0.0) {
+            return Err("measurements must be non-negative numbers".to_string());
+        }
+        if values.iter().all(|v| v.is_none()) {
+            return Err("at least one measurement is required".to_string());
+        }
+        if let Some(url) = &self.coa_url {
+            if url.len() > 500 { return Err("coa_url too long (max 500)".to_string()); }
+            let scheme_ok = Url::parse(url).map(|u| matches!(u.scheme(), "http" | "https")).unwrap_or(false);
+            if !scheme_ok { return Err("coa_url must be an http(s) URL".to_string()); }
+        }
+        Ok(())
+    }
+}
+
+// Compact view of the latest lab result, shown on the public product page
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub struct LabSummary {
+    pub lab_name: String,
+    pub tested_at: DateTime<Utc>,
+    pub verdict: Verdict,
+    pub failed_parameters: Vec<String>,
+    pub coa_url: Option<String>,
+}
+
+impl From<&LabResult> for LabSummary {
+    fn from(result: &LabResult) -> Self {
+        Self {
+            lab_name: result.lab_name.clone(),
+            tested_at: result.tested_at,
+            verdict: result.verdict,
+            failed_parameters: result
+                .checks
+                .iter()
+                .filter(|c| !c.passed)
+                .map(|c| c.parameter.clone())
+                .collect(),
+            coa_url: result.coa_url.clone(),
+        }
+    }
+}
+
+// Specs are keyed by species; herbs are matched on their (case-insensitive) name. Names in other
+// scripts would all slug to dashes, so they also get a hash of the name to keep them apart.
+pub fn spec_id(species: &str) -> String {
+    let name = species.trim().to_lowercase();
+    let slug: String = name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '-' }).collect();
+    if name.is_ascii() {
+        format!("spec_{}", slug)
+    } else {
+        let hash = hex::encode(Sha256::digest(name.as_bytes()));
+        format!("spec_{}_{}", slug, &hash[..16])
+    }
+}
+
+// Compare measurements against spec limits. Parameters that were not measured,
+// or that have no limit in the spec, are not checked.
+pub fn evaluate(measurements: &LabMeasurements, spec: Option<&SpecLimits>) -> (Verdict, Vec<ParameterCheck>) {
+    let Some(spec) = spec else {
+        return (Verdict::NoSpec, Vec::new());
+    };
+
+    let max_checks = [
+        ("moisture_pct", measurements.moisture_pct, spec.moisture_max_pct),
+        ("heavy_metals_ppm", measurements.heavy_metals_ppm, spec.heavy_metals_max_ppm),
+        ("pesticide_residue_ppm", measurements.pesticide_residue_ppm, spec.pesticide_residue_max_ppm),
+        ("aflatoxin_ppb", measurements.aflatoxin_ppb, spec.aflatoxin_max_ppb),
+        ("microbial_load_cfu_g", measurements.microbial_load_cfu_g, spec.microbial_load_max_cfu_g),
+    ];
+
+    let mut checks = Vec::new();
+    for (parameter, value, limit) in max_checks {
+        if let (Some(value), Some(limit)) = (value, limit) {
+            checks.push(ParameterCheck {
+                parameter: parameter.to_string(),
+                value,
+                limit,
+                bound: "max".to_string(),
+                passed: value <= limit,
+            });
+        }
+    }
+    if let (Some(value), Some(limit)) = (measurements.active_marker_pct, spec.active_marker_min_pct) {
+        checks.push(ParameterCheck {
+            parameter: "active_marker_pct".to_string(),
+            value,
+            limit,
+            bound: "min".to_string(),
+            passed: value >= limit,
+        });
+    }
+
+    let verdict = if checks.is_empty() {
+        Verdict::NoSpec
+    } else if checks.iter().all(|c| c.passed) {
+        Verdict::Pass
+    } else {
+        Verdict::Fail
+    };
+    (verdict, checks)
+}
+
+pub async fn fetch_spec(state: &AppState, species: &str) -> Option<SpecLimits> {
+    state.docs.get_doc::<SpecLimits>(Collection::Specs, &spec_id(species)).await.ok().map(|(spec, _)| spec)
+}
+
+pub async fn fetch_lab_results(state: &AppState, herb_id: &str) -> Result<Vec<LabResult>, StoreError> {
+    let mut results = state.docs.find_docs::<LabResult>(Collection::LabResults, &DocQuery::herb(herb_id)).await?;
+    results.sort_by(|a, b| b.tested_at.cmp(&a.tested_at).then(b.created_at.cmp(&a.created_at)));
+    Ok(results)
+}
+
+// Summary of the most recent lab result, if any
+pub async fn latest_summary(state: &AppState, herb_id: &str) -> Option<LabSummary> {
+    match fetch_lab_results(state, herb_id).await {
+        Ok(results) => results.first().map(LabSummary::from),
+        Err(err) => {
+            eprintln!("latest_summary failed for herb {}: {}", herb_id, err);
+            None
+        }
+    }
+}
+
+// Handlers
+
+// PUT /specs/{species}
+#[utoipa::path(
+    put,
+    path = "/specs/{species}",
+    tag = "lab",
+    params(("species" = String, Path)),
+    request_body = SpecLimitsRequest,
+    responses(
+        (status = 200, description = "Stored specification", body = SpecLimits),
+        (status = 400, description = "Validation failed"),
+    ),
+)]
+pub async fn put_spec(
+    State(state): State<AppState>,
+    Path(species): Path<String>,
+    Json(payload): Json<SpecLimitsRequest>,
+) -> impl IntoResponse {
+    if species.trim().is_empty() || species.len() > 100 {
+        return (StatusCode::BAD_REQUEST, "invalid species").into_response();
+    }
+    let limits = [
+        payload.moisture_max_pct,
+        payload.heavy_metals_max_ppm,
+        payload.pesticide_residue_max_ppm,
+        payload.aflatoxin_max_ppb,
+        payload.microbial_load_max_cfu_g,
+        payload.active_marker_min_pct,
+    ];
+    if limits.iter().flatten().any(|v| !v.is_finite() || *v < 0.0) {
+        return (StatusCode::BAD_REQUEST, "limits must be non-negative numbers").into_response();
+    }
+
+    let spec = SpecLimits {
+        species: species.trim().to_string(),
+        moisture_max_pct: payload.moisture_max_pct,
+        heavy_metals_max_ppm: payload.heavy_metals_max_ppm,
+        pesticide_residue_max_ppm: payload.pesticide_residue_max_ppm,
+        aflatoxin_max_ppb: payload.aflatoxin_max_ppb,
+        microbial_load_max_cfu_g: payload.microbial_load_max_cfu_g,
+        active_marker_min_pct: payload.active_marker_min_pct,
+    };
+    match state.docs.upsert_doc(Collection::Specs, &spec_id(&species), &spec).await {
+        Ok(_) => (StatusCode::OK, Json(spec)).into_response(),
+        Err(err) => {
+            eprintln!("put_spec failed for species {}: {}", species, err);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to save specification").into_response()
+        }
+    }
+}
+
+// GET /specs/{species}
+#[utoipa::path(
+    get,
+    path = "/specs/{species}",
+    tag = "lab",
+    params(("species" = String, Path)),
+    responses(
+        (status = 200, description = "Specification", body = SpecLimits),
+        (status = 404, description = "No specification for the species"),
+    ),
+)]
+pub async fn get_spec(
+    State(state): State<AppState>,
+    Path(species): Path<String>,
+) -> impl IntoResponse {
+    match fetch_spec(&state, &species).await {
+        Some(spec) => (StatusCode::OK, Json(spec)).into_response(),
+        None => (StatusCode::NOT_FOUND, "Specification not found").into_response(),
+    }
+}
+
+// GET /specs
+#[utoipa::path(
+    get,
+    path = "/specs",
+    tag = "lab",
+    responses((status = 200, description = "All specifications", body = [SpecLimits])),
+)]
+pub async fn list_specs(State(state): State<AppState>) -> impl IntoResponse {
+    match state.docs.find_docs::<SpecLimits>(Collection::Specs, &DocQuery::default()).await {
+        Ok(specs) => (StatusCode::OK, Json(specs)).into_response(),
+        Err(err) => {
+            eprintln!("list_specs failed: {}", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch specifications").into_response()
+        }
+    }
+}
+
+// POST /herbs/{id}/lab-results - Record a CoA and evaluate it against the species spec. A failing
+// result does not change the herb's status: release is checked when it happens, and recalling a
+// batch already on the market is left to the operator. The public page shows the latest result.
+#[utoipa::path(
+    post,
+    path = "/herbs/{id}/lab-results",
+    tag = "lab",
+    params(("id" = String, Path, description = "Herb id")),
+    request_body = AddLabResultRequest,
+    responses(
+        (status = 201, description = "Evaluated lab result", body = LabResult),
+        (status = 400, description = "Validation failed"),
+        (status = 404, description = "Herb not found"),
+    ),
+)]
+pub async fn add_lab_result(
+    State(state): State<AppState>,
+    Path(herb_id): Path<String>,
+    Json(payload): Json<AddLabResultRequest>,
+) -> impl IntoResponse {
+    if let Err(msg) = payload.validate() {
+        return (StatusCode::BAD_REQUEST, msg).into_response();
+    }
+    let herb = match state.herbs.get(&herb_id).await {
+        Ok((herb, _)) => herb,
+        Err(err) => {
+            eprintln!("add_lab_result could not fetch herb {}: {}", herb_id, err);
+            return (StatusCode::NOT_FOUND, "Herb not found").into_response();
+        }
+    };
+
+    let spec = fetch_spec(&state, &herb.name).await;
+    let (verdict, checks) = evaluate(&payload.measurements, spec.as_ref());
+    let created_at = Utc::now();
+    let result = LabResult {
+        // Two CoAs for a herb can arrive in the same millisecond
+        id: format!("lab_{}_{}_{:08x}", herb_id, created_at.timestamp_millis(), rand::thread_rng().gen::<u32>()),
+        herb_id,
+        lab_name: payload.lab_name.trim().to_string(),
+        tested_at: payload.tested_at.unwrap_or(created_at),
+        measurements: payload.measurements,
+        coa_url: payload.coa_url,
+        verdict,
+        checks,
+        created_at,
+    };
+
+    match state.docs.put_doc(Collection::LabResults, &result.id, &result).await {
+        Ok(_) => (StatusCode::CREATED, Json(result)).into_response(),
+        Err(err) => {
+            eprintln!("add_lab_result save failed for id {}: {}", result.id, err);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to save lab result").into_response()
+        }
+    }
+}
+
+// GET /herbs/{id}/lab-results - Newest first
+#[utoipa::path(
+    get,
+    path = "/herbs/{id}/lab-results",
+    tag = "lab",
+    params(("id" = String, Path, description = "Herb id")),
+    responses((status = 200, description = "Lab results, newest first", body = [LabResult])),
+)]
+pub async fn list_lab_results(
+    State(state): State<AppState>,
+    Path(herb_id): Path<String>,
+) -> impl IntoResponse {
+    match fetch_lab_results(&state, &herb_id).await {
+        Ok(results) => (StatusCode::OK, Json(results)).into_response(),
+        Err(err) => {
+            eprintln!("list_lab_results failed for herb {}: {}", herb_id, err);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch lab results").into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec() -> SpecLimits {
+        SpecLimits {
+            species: "Tulsi".to_string(),
+            moisture_max_pct: Some(10.0),
+            heavy_metals_max_ppm: Some(5.0),
+            active_marker_min_pct: Some(0.5),
+            ..SpecLimits::default()
+        }
+    }
+
+    #[test]
+    fn limits_are_inclusive_and_unmeasured_parameters_are_skipped() {
+        let measurements = LabMeasurements { moisture_pct: Some(10.0), active_marker_pct: Some(0.5), ..LabMeasurements::default() };
+        let (verdict, checks) = evaluate(&measurements, Some(&spec()));
+        assert!(verdict == Verdict::Pass);
+        let parameters: Vec<&str> = checks.iter().map(|c| c.parameter.as_str()).collect();
+        assert_eq!(parameters, ["moisture_pct", "active_marker_pct"]);
+        assert_eq!(checks[1].bound, "min");
+    }
+
+    #[test]
+    fn any_failed_check_fails_the_result() {
+        let measurements = LabMeasurements { heavy_metals_ppm: Some(5.1), active_marker_pct: Some(0.9), ..LabMeasurements::default() };
+        let (verdict, checks) = evaluate(&measurements, Some(&spec()));
+        assert!(verdict == Verdict::Fail);
+        assert!(!checks[0].passed && checks[1].passed);
+
+        let measurements = LabMeasurements { active_marker_pct: Some(0.4), ..LabMeasurements::default() };
+        assert!(evaluate(&measurements, Some(&spec())).0 == Verdict::Fail);
+    }
+
+    #[test]
+    fn nothing_to_check_is_no_spec() {
+        let measurements = LabMeasurements { moisture_pct: Some(3.0), ..LabMeasurements::default() };
+        assert!(evaluate(&measurements, None).0 == Verdict::NoSpec);
+        let measurements = LabMeasurements { aflatoxin_ppb: Some(3.0), ..LabMeasurements::default() };
+        let (verdict, checks) = evaluate(&measurements, Some(&spec()));
+        assert!(verdict == Verdict::NoSpec);
+        assert!(checks.is_empty());
+    }
+
+    #[test]
+    fn spec_ids_ignore_case_and_keep_scripts_apart() {
+        assert_eq!(spec_id(" Holy Basil "), "spec_holy-basil");
+        assert_eq!(spec_id("HOLY BASIL"), spec_id("holy basil"));
+        let tulsi = spec_id("तुलसी");
+        assert!(tulsi.starts_with("spec_") && tulsi.is_ascii());
+        assert_ne!(tulsi, spec_id("अश्वगंधा"));
+        assert_eq!(spec_id("Ärztekraut"), spec_id("ärztekraut"));
+    }
+}
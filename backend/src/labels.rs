@@ -0,0 +1,372 @@
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use qrcode::EcLevel;
+use serde::Deserialize;
+use utoipa::ToSchema;
+use crate::handlers::{AppState, Herb, HerbFilter};
+use crate::pdf::{self, PdfDocument, PdfPage};
+use crate::qr::{self, QrMatrix};
+use crate::settings::LinkFormat;
+
+const MAX_LABELS: usize = 500;
+const MM_TO_PT: f64 = 72.0 / 25.4;
+// Inner padding of each label and quiet zone around its QR code
+const LABEL_PADDING_MM: f64 = 2.0;
+const QR_MARGIN_MODULES: u32 = 2;
+
+// Physical layout of a label sheet, in millimetres
+pub struct LabelTemplate {
+    pub page_width: f64,
+    pub page_height: f64,
+    pub columns: usize,
+    pub rows: usize,
+    pub label_width: f64,
+    pub label_height: f64,
+    pub margin_left: f64,
+    pub margin_top: f64,
+    // Distance between the left/top edges of neighbouring labels
+    pub pitch_x: f64,
+    pub pitch_y: f64,
+    pub font_size_pt: f64,
+}
+
+impl LabelTemplate {
+    pub fn by_name(name: &str) -> Option<Self> {
+        match name {
+            // Avery L7160-style A4 sheet: 3 x 7 labels of 63.5 x 38.1 mm
+            "a4_3x7" => Some(Self {
+                page_width: 210.0,
+                page_height: 297.0,
+                columns: 3,
+                rows: 7,
+                label_width: 63.5,
+                label_height: 38.1,
+                margin_left: 7.2,
+                margin_top: 15.15,
+                pitch_x: 66.04,
+                pitch_y: 38.1,
+                font_size_pt: 7.0,
+            }),
+            // Thermal roll, one 50 x 30 mm label per page
+            "thermal_50x30" => Some(Self {
+                page_width: 50.0,
+                page_height: 30.0,
+                columns: 1,
+                rows: 1,
+                label_width: 50.0,
+                label_height: 30.0,
+                margin_left: 0.0,
+                margin_top: 0.0,
+                pitch_x: 50.0,
+                pitch_y: 30.0,
+                font_size_pt: 5.5,
+            }),
+            _ => None,
+        }
+    }
+
+    fn per_page(&self) -> usize {
+        self.columns * self.rows
+    }
+
+    // Top-left corner (mm, from the top-left of the page) of the n-th label on a page
+    fn origin(&self, slot: usize) -> (f64, f64) {
+        let col = slot % self.columns;
+        let row = slot / self.columns;
+        (self.margin_left + col as f64 * self.pitch_x, self.margin_top + row as f64 * self.pitch_y)
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct LabelsRequest {
+    // Either explicit ids (printed in the given order) or a filter over all herbs
+    pub ids: Option<Vec<String>>,
+    pub filter: Option<HerbFilter>,
+    pub template: String,
+    // "pdf" (default) or "svg"
+    pub format: Option<String>,
+}
+
+// Text lines printed next to the QR code
+fn label_lines(herb: &Herb) -> Vec<String> {
+    vec![
+        herb.name.clone(),
+        format!("Farmer: {}", herb.farmer),
+        // The herb id stands in for a missing lot, labelled as what it is
+        match herb.lot.as_deref() {
+            Some(lot) => format!("Lot: {}", lot),
+            None => format!("ID: {}", herb.id),
+        },
+        // Registration is the only date a herb records
+        format!("Registered: {}", herb.created_at.format("%Y-%m-%d")),
+    ]
+}
+
+// Cut text so it fits `width_pt` at `size`, marking the cut with "..."
+fn fit_text(text: &str, width_pt: f64, size: f64) -> String {
+    if pdf::text_width(text, size) <= width_pt {
+        return text.to_string();
+    }
+    let mut out: String = String::new();
+    for c in text.chars() {
+        if pdf::text_width(&format!("{}{}...", out, c), size) > width_pt {
+            break;
+        }
+        out.push(c);
+    }
+    format!("{}...", out.trim_end())
+}
+
+struct PlacedLabel {
+    matrix: QrMatrix,
+    lines: Vec<String>,
+}
+
+fn place_labels(herbs: &[Herb], base: Option<&str>, format: LinkFormat) -> Result<Vec<PlacedLabel>, String> {
+    herbs
+        .iter()
+        .map(|herb| {
+            let matrix = qr::encode_herb(herb, base, format, EcLevel::M, QR_MARGIN_MODULES)
+                .map_err(|err| format!("QR for {} could not be encoded: {}", herb.id, err))?;
+            Ok(PlacedLabel { matrix, lines: label_lines(herb) })
+        })
+        .collect()
+}
+
+fn render_pdf(template: &LabelTemplate, labels: &[PlacedLabel]) -> Vec<u8> {
+    let mut doc = PdfDocument::new();
+    let page_height = template.page_height * MM_TO_PT;
+    for chunk in labels.chunks(template.per_page()) {
+        let mut page = PdfPage::new(template.page_width * MM_TO_PT, page_height);
+        for (slot, label) in chunk.iter().enumerate() {
+            let (left, top) = template.origin(slot);
+            let qr_side = (template.label_height - 2.0 * LABEL_PADDING_MM) * MM_TO_PT;
+            let x = (left + LABEL_PADDING_MM) * MM_TO_PT;
+            let y = page_height - (top + LABEL_PADDING_MM) * MM_TO_PT - qr_side;
+            label.matrix.draw_pdf(&mut page, x, y, qr_side, [0, 0, 0], [255, 255, 255]);
+
+            let text_x = x + qr_side + LABEL_PADDING_MM * MM_TO_PT;
+            let text_width = (left + template.label_width - LABEL_PADDING_MM) * MM_TO_PT - text_x;
+            let size = template.font_size_pt;
+            page.fill_color([0, 0, 0]);
+            for (i, line) in label.lines.iter().enumerate() {
+                // First line (herb name) slightly larger
+                let line_size = if i == 0 { size * 1.25 } else { size };
+                let baseline = page_height - (top + LABEL_PADDING_MM) * MM_TO_PT - line_size - i as f64 * size * 1.5;
+                page.text(text_x, baseline, line_size, &fit_text(line, text_width, line_size));
+            }
+        }
+        doc.add_page(page);
+    }
+    doc.to_bytes()
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// All pages stacked vertically in one SVG, in millimetre units
+fn render_svg(template: &LabelTemplate, labels: &[PlacedLabel]) -> Vec<u8> {
+    let pages = labels.len().div_ceil(template.per_page()).max(1);
+    let total_height = template.page_height * pages as f64;
+    let mut body = String::new();
+    for (page_index, chunk) in labels.chunks(template.per_page()).enumerate() {
+        let page_top = page_index as f64 * template.page_height;
+        for (slot, label) in chunk.iter().enumerate() {
+            let (left, top) = template.origin(slot);
+            let top = top + page_top;
+            let qr_side = template.label_height - 2.0 * LABEL_PADDING_MM;
+            let x = left + LABEL_PADDING_MM;
+            let y = top + LABEL_PADDING_MM;
+            let module = qr_side / label.matrix.width as f64;
+            let mut path = String::new();
+            for row in 0..label.matrix.width {
+                for col in 0..label.matrix.width {
+                    if label.matrix.is_dark(col, row) {
+                        path.push_str(&format!(
+                            "M{:.3} {:.3}h{m:.3}v{m:.3}h-{m:.3}z",
+                            x + col as f64 * module,
+                            y + row as f64 * module,
+                            m = module
+                        ));
+                    }
+                }
+            }
+            body.push_str(&format!("<path d=\"{}\" fill=\"#000\"/>", path));
+
+            let text_x = x + qr_side + LABEL_PADDING_MM;
+            let text_width_pt = (left + template.label_width - LABEL_PADDING_MM - text_x) * MM_TO_PT;
+            let size = template.font_size_pt;
+            for (i, line) in label.lines.iter().enumerate() {
+                let line_size = if i == 0 { size * 1.25 } else { size };
+                let baseline = y + (line_size + i as f64 * size * 1.5) / MM_TO_PT;
+                body.push_str(&format!(
+                    "<text x=\"{:.3}\" y=\"{:.3}\" font-size=\"{:.3}\">{}</text>",
+                    text_x,
+                    baseline,
+                    line_size / MM_TO_PT,
+                    xml_escape(&fit_text(line, text_width_pt, line_size))
+                ));
+            }
+        }
+    }
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?><svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}mm\" height=\"{h}mm\" viewBox=\"0 0 {w} {h}\" font-family=\"Helvetica, Arial, sans-serif\" shape-rendering=\"crispEdges\"><rect width=\"{w}\" height=\"{h}\" fill=\"#fff\"/>{body}</svg>",
+        w = template.page_width,
+        h = total_height,
+        body = body
+    )
+    .into_bytes()
+}
+
+// Handlers
+
+// POST /labels - Print-ready label sheet for a list of herb ids or a filter
+#[utoipa::path(
+    post,
+    path = "/labels",
+    tag = "labels",
+    request_body = LabelsRequest,
+    responses(
+        (status = 200, description = "Label sheet", content(("application/pdf"), ("image/svg+xml"))),
+        (status = 400, description = "Invalid request"),
+        (status = 404, description = "No matching herbs"),
+        (status = 422, description = "A QR code does not fit, or label text is not Latin (PDF only)"),
+    ),
+)]
+pub async fn print_labels(
+    State(state): State<AppState>,
+    Json(payload): Json<LabelsRequest>,
+) -> impl IntoResponse {
+    let Some(template) = LabelTemplate::by_name(&payload.template) else {
+        return (StatusCode::BAD_REQUEST, "unknown template (use a4_3x7 or thermal_50x30)").into_response();
+    };
+    let svg = match payload.format.as_deref() {
+        None | Some("pdf") => false,
+        Some("svg") => true,
+        Some(other) => return (StatusCode::BAD_REQUEST, format!("unsupported format: {}", other)).into_response(),
+    };
+
+    let herbs = match (&payload.ids, &payload.filter) {
+        (Some(ids), _) => {
+            if ids.len() > MAX_LABELS {
+                return (StatusCode::BAD_REQUEST, format!("too many labels (max {})", MAX_LABELS)).into_response();
+            }
+            state.herbs.get_many(ids).await
+        }
+        (None, Some(filter)) => state
+            .herbs
+            .list()
+            .await
+            .map(|herbs| herbs.into_iter().filter(|h| filter.matches(h)).collect()),
+        (None, None) => return (StatusCode::BAD_REQUEST, "ids or filter is required").into_response(),
+    };
+    let herbs = match herbs {
+        Ok(herbs) => herbs,
+        Err(err) => {
+            eprintln!("print_labels could not fetch herbs: {}", err);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch herbs").into_response();
+        }
+    };
+    if herbs.is_empty() {
+        return (StatusCode::NOT_FOUND, "No matching herbs").into_response();
+    }
+    if herbs.len() > MAX_LABELS {
+        return (StatusCode::BAD_REQUEST, format!("too many labels (max {})", MAX_LABELS)).into_response();
+    }
+
+    // The PDF font only covers Latin text; SVG leaves fonts to the viewer
+    if !svg {
+        if let Some(herb) = herbs.iter().find(|h| !label_lines(h).iter().all(|line| pdf::is_encodable(line))) {
+            let msg = format!("label text for {} has characters the PDF font lacks; use format svg", herb.id);
+            return (StatusCode::UNPROCESSABLE_ENTITY, msg).into_response();
+        }
+    }
+
+    // Encoding hundreds of QR codes is CPU-bound, so it runs on the blocking pool
+    let (base, link_format) = (state.public_base_url.clone(), state.settings.qr.link_format);
+    let rendered = tokio::task::spawn_blocking(move || {
+        let labels = place_labels(&herbs, base.as_deref(), link_format)?;
+        Ok::<_, String>(if svg {
+            ("image/svg+xml", render_svg(&template, &labels))
+        } else {
+            ("application/pdf", render_pdf(&template, &labels))
+        })
+    })
+    .await
+    .expect("label rendering panicked");
+    match rendered {
+        Ok((content_type, bytes)) => (StatusCode::OK, [(header::CONTENT_TYPE, content_type)], bytes).into_response(),
+        Err(msg) => (StatusCode::UNPROCESSABLE_ENTITY, msg).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::test_herb;
+
+    fn herb(name: &str) -> Herb {
+        Herb { lot: Some("L-1".to_string()), ..test_herb(name, "Asha") }
+    }
+
+    #[test]
+    fn templates_fit_their_labels_on_the_page() {
+        for name in ["a4_3x7", "thermal_50x30"] {
+            let template = LabelTemplate::by_name(name).unwrap();
+            let (left, top) = template.origin(template.per_page() - 1);
+            assert!(left + template.label_width <= template.page_width + 1e-9, "{}", name);
+            assert!(top + template.label_height <= template.page_height + 1e-9, "{}", name);
+            // The QR code leaves room for text beside it
+            assert!(template.label_height < template.label_width, "{}", name);
+        }
+        let a4 = LabelTemplate::by_name("a4_3x7").unwrap();
+        assert_eq!(a4.per_page(), 21);
+        assert_eq!(a4.origin(0), (7.2, 15.15));
+        assert_eq!(a4.origin(4), (7.2 + 66.04, 15.15 + 38.1));
+        assert!(LabelTemplate::by_name("letter").is_none());
+    }
+
+    #[test]
+    fn lines_name_the_lot_or_else_the_id() {
+        let mut herb = herb("Tulsi");
+        let lines = label_lines(&herb);
+        assert_eq!(lines[2], "Lot: L-1");
+        assert!(lines[3].starts_with("Registered: "));
+        herb.lot = None;
+        assert_eq!(label_lines(&herb)[2], format!("ID: {}", herb.id));
+    }
+
+    #[test]
+    fn long_text_is_cut_with_an_ellipsis() {
+        assert_eq!(fit_text("Tulsi", 100.0, 7.0), "Tulsi");
+        let cut = fit_text("Ashwagandha root, organic, sun dried", 40.0, 7.0);
+        assert!(cut.ends_with("..."));
+        assert!(pdf::text_width(&cut, 7.0) <= 40.0);
+        assert!("Ashwagandha root, organic, sun dried".starts_with(cut.trim_end_matches("...")));
+        assert_eq!(fit_text("Tulsi", 1.0, 7.0), "...");
+    }
+
+    #[tokio::test]
+    async fn pdf_labels_refuse_text_the_font_lacks() {
+        let state = AppState::for_tests();
+        let tulsi = herb("तुलसी");
+        state.herbs.put(&tulsi).await.unwrap();
+        let request = |format: &str| LabelsRequest {
+            ids: Some(vec![tulsi.id.clone()]),
+            filter: None,
+            template: "a4_3x7".to_string(),
+            format: Some(format.to_string()),
+        };
+        let response = print_labels(State(state.clone()), Json(request("pdf"))).await.into_response();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let response = print_labels(State(state.clone()), Json(request("svg"))).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8(body.to_vec()).unwrap().contains("तुलसी"));
+    }
+}
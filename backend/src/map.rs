@@ -0,0 +1,113 @@
+use std::f64::consts::PI;
+use url::Url;
+use crate::settings::MapSettings;
+
+// Slippy-map tiles drawn server-side into an SVG, so the origin map needs no JavaScript.
+// The tile server is MapSettings::tile_url.
+const TILE_SIZE: f64 = 256.0;
+// Roughly district level; with the marker at public_coordinate it shows the growing region
+// without pinpointing a farm
+const ZOOM: u32 = 9;
+const VIEW_WIDTH: f64 = 512.0;
+const VIEW_HEIGHT: f64 = 320.0;
+
+// Origin (scheme://host[:port]) of the tile server, for the Content-Security-Policy
+pub fn tile_origin(settings: &MapSettings) -> Option<String> {
+    let url = Url::parse(&settings.tile_url.replace(['{', '}'], "")).ok()?;
+    Some(url.origin().ascii_serialization()).filter(|o| o != "null")
+}
+
+// Coordinates shown publicly (map, link, public JSON) are rounded to two decimals, about 1 km
+pub fn public_coordinate(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+// Web Mercator position in pixels at ZOOM
+fn project(latitude: f64, longitude: f64) -> (f64, f64) {
+    let world = TILE_SIZE * f64::from(1u32 << ZOOM);
+    // Mercator is undefined at the poles
+    let lat = latitude.clamp(-85.0511, 85.0511).to_radians();
+    let x = (longitude + 180.0) / 360.0 * world;
+    let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0 * world;
+    (x, y)
+}
+
+pub struct MapTile {
+    pub href: String,
+    pub x: f64,
+    pub y: f64,
+}
+
+pub struct OriginMap {
+    pub view_box: String,
+    pub tiles: Vec<MapTile>,
+    pub marker_x: f64,
+    pub marker_y: f64,
+    pub latitude: String,
+    pub longitude: String,
+    // Full interactive map, for readers who want more than the static view
+    pub link: String,
+    pub attribution: String,
+}
+
+impl OriginMap {
+    pub fn new(latitude: f64, longitude: f64, settings: &MapSettings) -> Self {
+        let (latitude, longitude) = (public_coordinate(latitude), public_coordinate(longitude));
+        let (px, py) = project(latitude, longitude);
+        let left = px - VIEW_WIDTH / 2.0;
+        let top = py - VIEW_HEIGHT / 2.0;
+        let tiles_per_side = 1i64 << ZOOM;
+        let template = &settings.tile_url;
+        let mut tiles = Vec::new();
+        let first_row = (top / TILE_SIZE).floor() as i64;
+        let last_row = ((top + VIEW_HEIGHT) / TILE_SIZE).floor() as i64;
+        let first_col = (left / TILE_SIZE).floor() as i64;
+        let last_col = ((left + VIEW_WIDTH) / TILE_SIZE).floor() as i64;
+        for row in first_row..=last_row {
+            if row < 0 || row >= tiles_per_side {
+                continue;
+            }
+            for col in first_col..=last_col {
+                // Wrap around the antimeridian
+                let wrapped = col.rem_euclid(tiles_per_side);
+                tiles.push(MapTile {
+                    href: template
+                        .replace("{z}", &ZOOM.to_string())
+                        .replace("{x}", &wrapped.to_string())
+                        .replace("{y}", &row.to_string()),
+                    x: col as f64 * TILE_SIZE,
+                    y: row as f64 * TILE_SIZE,
+                });
+            }
+        }
+        Self {
+            view_box: format!("{:.1} {:.1} {} {}", left, top, VIEW_WIDTH, VIEW_HEIGHT),
+            tiles,
+            marker_x: px,
+            marker_y: py,
+            latitude: format!("{:.2}", latitude),
+            longitude: format!("{:.2}", longitude),
+            link: format!(
+                "https://www.openstreetmap.org/?mlat={lat:.2}&mlon={lon:.2}#map=11/{lat:.2}/{lon:.2}",
+                lat = latitude,
+                lon = longitude
+            ),
+            attribution: settings.attribution.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_origin_is_shown_to_two_decimals() {
+        let map = OriginMap::new(12.971_6, 77.594_6, &MapSettings::default());
+        assert_eq!((map.latitude.as_str(), map.longitude.as_str()), ("12.97", "77.59"));
+        assert!(map.link.contains("mlat=12.97&mlon=77.59"));
+        assert!(!map.link.contains("12.971"));
+        // The marker sits on the rounded point, not the farm
+        assert_eq!((map.marker_x, map.marker_y), project(12.97, 77.59));
+    }
+}
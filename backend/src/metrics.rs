@@ -0,0 +1,195 @@
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{Duration as ChronoDuration, Utc};
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use crate::docs::{Collection, DocQuery};
+use crate::handlers::AppState;
+
+// Prometheus metrics, served at /metrics. Routes are labelled with their matched path
+// (/api/v1/herbs/{id}), never the raw URI, so herb ids do not multiply the series.
+// Business gauges are recomputed at most once a minute, on scrape.
+const BUSINESS_REFRESH: Duration = Duration::from_secs(60);
+
+struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+    couch_duration: HistogramVec,
+    qr_render: Histogram,
+    qr_cache: IntCounterVec,
+    herbs_total: IntGauge,
+    scans_today: IntGauge,
+    // When the business gauges were last computed
+    refreshed: Mutex<Option<Instant>>,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by matched route and status"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "HTTP request latency by matched route"),
+            &["method", "route"],
+        )
+        .unwrap();
+        let couch_duration = HistogramVec::new(
+            HistogramOpts::new("couchdb_request_duration_seconds", "CouchDB request latency by client method")
+                .buckets(exponential_buckets(0.001, 2.0, 14).unwrap()),
+            &["method", "outcome"],
+        )
+        .unwrap();
+        let qr_render = Histogram::with_opts(
+            HistogramOpts::new("qr_render_duration_seconds", "Time to encode and render one QR code")
+                .buckets(exponential_buckets(0.0005, 2.0, 12).unwrap()),
+        )
+        .unwrap();
+        let qr_cache = IntCounterVec::new(
+            Opts::new("qr_cache_requests_total", "QR cache lookups; hit rate is hit / (hit + miss)"),
+            &["result"],
+        )
+        .unwrap();
+        let herbs_total = IntGauge::new("herbs_total", "Herbs in the default store").unwrap();
+        let scans_today = IntGauge::new("herb_scans_today", "Product scans since midnight UTC").unwrap();
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry.register(Box::new(http_duration.clone())).unwrap();
+        registry.register(Box::new(couch_duration.clone())).unwrap();
+        registry.register(Box::new(qr_render.clone())).unwrap();
+        registry.register(Box::new(qr_cache.clone())).unwrap();
+        registry.register(Box::new(herbs_total.clone())).unwrap();
+        registry.register(Box::new(scans_today.clone())).unwrap();
+        Self {
+            registry,
+            http_requests,
+            http_duration,
+            couch_duration,
+            qr_render,
+            qr_cache,
+            herbs_total,
+            scans_today,
+            refreshed: Mutex::new(None),
+        }
+    }
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+// Middleware for route_layer, which runs after routing so MatchedPath is known
+pub async fn track(request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let started = Instant::now();
+    let response = next.run(request).await;
+    let elapsed = started.elapsed().as_secs_f64();
+    let status = response.status().as_u16().to_string();
+    METRICS.http_requests.with_label_values(&[&method, &route, &status]).inc();
+    METRICS.http_duration.with_label_values(&[&method, &route]).observe(elapsed);
+    response
+}
+
+// One CouchDB request made by CouchDb::`method`; outcome is "2xx".."5xx" or "error" when no
+// response arrived
+pub fn couch_request(method: &str, outcome: &str, elapsed: Duration) {
+    METRICS.couch_duration.with_label_values(&[method, outcome]).observe(elapsed.as_secs_f64());
+}
+
+pub fn qr_rendered(elapsed: Duration) {
+    METRICS.qr_render.observe(elapsed.as_secs_f64());
+}
+
+pub fn qr_cache_lookup(hit: bool) {
+    METRICS.qr_cache.with_label_values(&[if hit { "hit" } else { "miss" }]).inc();
+}
+
+// Recomputes herbs_total and herb_scans_today unless that happened within BUSINESS_REFRESH;
+// on failure the gauges keep their last values
+async fn refresh_business(state: &AppState) {
+    {
+        let mut refreshed = METRICS.refreshed.lock().unwrap();
+        if refreshed.is_some_and(|at| at.elapsed() < BUSINESS_REFRESH) {
+            return;
+        }
+        *refreshed = Some(Instant::now());
+    }
+    match state.herbs.count().await {
+        Ok(count) => METRICS.herbs_total.set(count as i64),
+        Err(err) => eprintln!("metrics could not count herbs: {}", err),
+    }
+    let now = Utc::now();
+    let midnight = now.date_naive().and_hms_opt(0, 0, 0).unwrap().and_utc();
+    let today = DocQuery { from: Some(midnight), to: Some(now + ChronoDuration::seconds(1)), ..DocQuery::default() };
+    match state.docs.count(Collection::Scans, &today).await {
+        Ok(count) => METRICS.scans_today.set(count as i64),
+        Err(err) => eprintln!("metrics could not count scans: {}", err),
+    }
+}
+
+// GET /metrics
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "system",
+    responses((status = 200, description = "Prometheus text exposition format", content_type = "text/plain")),
+)]
+pub async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    refresh_business(&state).await;
+    let mut body = Vec::new();
+    let encoder = TextEncoder::new();
+    if let Err(err) = encoder.encode(&METRICS.registry.gather(), &mut body) {
+        eprintln!("encoding metrics failed: {}", err);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to encode metrics").into_response();
+    }
+    (StatusCode::OK, [(header::CONTENT_TYPE, encoder.format_type().to_string())], body).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    async fn scrape() -> String {
+        let response = metrics(State(AppState::for_tests())).await.into_response();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn routes_are_labelled_by_their_pattern() {
+        let app = Router::new()
+            .route("/herbs/{id}/labels", get(|| async { "ok" }))
+            .route_layer(middleware::from_fn(track));
+        let request = axum::http::Request::get("/herbs/herb_secret42/labels").body(Body::empty()).unwrap();
+        assert_eq!(app.oneshot(request).await.unwrap().status(), StatusCode::OK);
+
+        let text = scrape().await;
+        assert!(text.contains(r#"http_requests_total{method="GET",route="/herbs/{id}/labels",status="200"}"#));
+        assert!(!text.contains("herb_secret42"));
+    }
+
+    #[tokio::test]
+    async fn qr_cache_hits_and_misses_are_counted() {
+        qr_cache_lookup(true);
+        qr_cache_lookup(false);
+        let text = scrape().await;
+        assert!(text.contains(r#"qr_cache_requests_total{result="hit"}"#));
+        assert!(text.contains(r#"qr_cache_requests_total{result="miss"}"#));
+        assert!(text.contains("herbs_total"));
+    }
+}
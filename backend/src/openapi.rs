@@ -0,0 +1,243 @@
+use utoipa::openapi::Deprecated;
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
+use crate::{alerts, analytics, api_v1, graphql, attachments, export, gs1, handlers, import, lab, labels, health, metrics, pages, replication, settings, sync, tenants};
+
+// OpenAPI 3.1 description of the REST API, generated from the handler annotations
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Herb traceability API", description = "Herb batches, lab results, attachments, public product pages and scan analytics."),
+    paths(
+        handlers::root,
+        handlers::health_check,
+        api_v1::list_herbs,
+        api_v1::create_herb,
+        api_v1::get_herb,
+        api_v1::patch_herb,
+        api_v1::delete_herb,
+        sync::sync_herbs,
+        graphql::graphql_get,
+        graphql::graphql_post,
+        handlers::reset_db,
+        handlers::add_herb,
+        handlers::get_herb,
+        handlers::list_herbs,
+        handlers::update_herb,
+        handlers::delete_herb,
+        handlers::get_public_product,
+        handlers::get_public_product_html,
+        handlers::get_qr_png,
+        handlers::scan_product,
+        handlers::scan_page,
+        gs1::resolve_gtin,
+        gs1::resolve_gtin_lot,
+        labels::print_labels,
+        analytics::scans_by_herb,
+        analytics::scans_by_day,
+        analytics::scans_by_region,
+        alerts::list_alerts,
+        alerts::dismiss_alert,
+        pages::static_asset,
+        pages::brand_css,
+        export::export_csv,
+        export::export_ndjson,
+        export::export_epcis,
+        import::import_herbs,
+        lab::list_specs,
+        lab::get_spec,
+        lab::put_spec,
+        lab::list_lab_results,
+        lab::add_lab_result,
+        attachments::list_attachments,
+        attachments::upload_attachments,
+        attachments::download_attachment,
+        attachments::delete_attachment,
+        attachments::download_thumbnail,
+        replication::list_replications,
+        replication::put_replication,
+        replication::get_replication,
+        replication::start_replication,
+        replication::stop_replication,
+        replication::delete_replication,
+        settings::get_config,
+        health::live,
+        health::ready,
+        metrics::metrics,
+        tenants::list_tenants,
+        tenants::create_tenant,
+        tenants::get_tenant,
+        tenants::update_tenant,
+        tenants::delete_tenant,
+        tenants::issue_key,
+        tenants::revoke_keys,
+    ),
+    components(schemas(
+        handlers::Herb,
+        handlers::HerbStatus,
+        handlers::HerbWithQr,
+        handlers::PublicProduct,
+        handlers::AddHerbRequest,
+        handlers::UpdateHerbRequest,
+        handlers::ScanRequest,
+        handlers::ScanResponse,
+        api_v1::HerbPatch,
+        sync::SyncRequest,
+        sync::ClientChange,
+        sync::SyncOutcome,
+        sync::SyncResult,
+        sync::SyncReport,
+        replication::ReplicationRequest,
+        replication::ReplicationInfo,
+        crate::couchdb::ReplicationStatus,
+        health::HealthReport,
+        health::Component,
+        health::Status,
+        settings::Settings,
+        settings::CouchDbSettings,
+        settings::StoreSettings,
+        settings::Mode,
+        settings::StoreKind,
+        tenants::Branding,
+        tenants::CreateTenantRequest,
+        tenants::UpdateTenantRequest,
+        tenants::TenantInfo,
+        tenants::IssuedKey,
+        lab::LabMeasurements,
+        lab::SpecLimits,
+        lab::SpecLimitsRequest,
+        lab::Verdict,
+        lab::ParameterCheck,
+        lab::LabResult,
+        lab::AddLabResultRequest,
+        lab::LabSummary,
+        attachments::AttachmentInfo,
+        import::RowStatus,
+        import::ImportRowReport,
+        import::ImportReport,
+        labels::LabelsRequest,
+        analytics::HerbScanStats,
+        analytics::DayScanStats,
+        analytics::RegionScanStats,
+        alerts::AlertRule,
+        alerts::AlertStatus,
+        alerts::Alert,
+    )),
+    tags(
+        (name = "herbs", description = "Herb batch records"),
+        (name = "sync", description = "Offline sync for the field app"),
+        (name = "public", description = "Consumer-facing product pages, QR codes and scanning"),
+        (name = "gs1", description = "GS1 Digital Link resolver"),
+        (name = "lab", description = "Specifications and lab results"),
+        (name = "attachments", description = "Photos and certificates"),
+        (name = "labels", description = "Printable label sheets"),
+        (name = "export", description = "Bulk exports"),
+        (name = "analytics", description = "Scan analytics"),
+        (name = "alerts", description = "Counterfeit alerts"),
+        (name = "graphql", description = "GraphQL over herbs, farmers, batches and events"),
+        (name = "admin", description = "Operator endpoints; require \"Authorization: Bearer <ADMIN_TOKEN>\""),
+        (name = "system", description = "Service status and maintenance"),
+    )
+)]
+pub struct ApiDoc;
+
+// RPC-style herb routes superseded by /api/v1/herbs
+const LEGACY_PATHS: [&str; 5] = ["/addHerb", "/getHerb/{id}", "/listHerbs", "/deleteHerb/{id}", "/updateHerb/{id}"];
+
+// The generated document, with the legacy aliases flagged as deprecated
+pub fn spec() -> utoipa::openapi::OpenApi {
+    let mut spec = ApiDoc::openapi();
+    for path in LEGACY_PATHS {
+        if let Some(item) = spec.paths.paths.get_mut(path) {
+            for operation in [&mut item.get, &mut item.post, &mut item.put, &mut item.delete].into_iter().flatten() {
+                operation.deprecated = Some(Deprecated::True);
+            }
+        }
+    }
+    spec
+}
+
+// GET /openapi.json and the Swagger UI at /docs
+pub fn docs() -> SwaggerUi {
+    SwaggerUi::new("/docs").url("/openapi.json", spec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METHODS: [&str; 5] = ["get", "post", "put", "delete", "patch"];
+
+    // (path, method) pairs registered with .route() in main.rs
+    fn routes_in_main() -> Vec<(String, String)> {
+        let source = include_str!("main.rs");
+        let mut routes = Vec::new();
+        for chunk in source.split(".route(").skip(1) {
+            let chunk = chunk.split(".with_state(").next().unwrap_or(chunk);
+            let path = chunk.trim_start().trim_start_matches('"');
+            let path = &path[..path.find('"').expect("route path literal")];
+            let handlers = &chunk[chunk.find(',').expect("route handler")..];
+            for method in METHODS {
+                let call = format!("{}(", method);
+                let found = handlers.match_indices(&call).any(|(at, _)| {
+                    // Only method routers, not names like lab::get_spec or attachments::delete_attachment
+                    at == 0 || !handlers[..at].ends_with(|c: char| c.is_alphanumeric() || c == '_' || c == ':')
+                });
+                if found {
+                    routes.push((path.to_string(), method.to_string()));
+                }
+            }
+        }
+        routes
+    }
+
+    #[test]
+    fn every_route_in_main_is_documented() {
+        let spec = spec();
+        let routes = routes_in_main();
+        assert!(routes.len() > 30, "expected to find the routes in main.rs, found {:?}", routes);
+
+        let missing: Vec<String> = routes
+            .iter()
+            .filter(|(path, method)| {
+                let Some(item) = spec.paths.paths.get(path) else { return true };
+                let operation = match method.as_str() {
+                    "get" => &item.get,
+                    "post" => &item.post,
+                    "put" => &item.put,
+                    "delete" => &item.delete,
+                    _ => &item.patch,
+                };
+                operation.is_none()
+            })
+            .map(|(path, method)| format!("{} {}", method.to_uppercase(), path))
+            .collect();
+        assert!(missing.is_empty(), "routes missing from the OpenAPI spec: {:?}", missing);
+    }
+
+    #[test]
+    fn spec_is_openapi_3_1_with_core_schemas() {
+        let json = serde_json::to_value(spec()).unwrap();
+        assert!(json["openapi"].as_str().unwrap().starts_with("3.1"));
+        for schema in ["Herb", "HerbWithQr", "AddHerbRequest", "UpdateHerbRequest", "ScanRequest"] {
+            assert!(json["components"]["schemas"][schema].is_object(), "missing schema {}", schema);
+        }
+    }
+
+    #[test]
+    fn add_herb_documents_what_it_returns() {
+        let json = serde_json::to_value(spec()).unwrap();
+        let responses = &json["paths"]["/addHerb"]["post"]["responses"];
+        for status in ["200", "201"] {
+            assert_eq!(responses[status]["content"]["application/json"]["schema"]["$ref"], "#/components/schemas/Herb");
+        }
+        assert!(responses["409"].is_null());
+    }
+
+    #[test]
+    fn legacy_routes_are_marked_deprecated() {
+        let json = serde_json::to_value(spec()).unwrap();
+        assert_eq!(json["paths"]["/addHerb"]["post"]["deprecated"], true);
+        assert_eq!(json["paths"]["/updateHerb/{id}"]["put"]["deprecated"], true);
+        assert!(json["paths"]["/api/v1/herbs/{id}"]["patch"]["deprecated"].is_null());
+    }
+}
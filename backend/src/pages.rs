@@ -0,0 +1,589 @@
+use askama::Template;
+use axum::{
+    extract::{Path, Query},
+    http::{header, HeaderValue, StatusCode},
+    response::{Html, IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use utoipa::IntoParams;
+use crate::attachments::{self, AttachmentInfo};
+use crate::handlers::{AppState, Herb, HerbStatus};
+use crate::i18n::{Lang, Translator};
+use crate::lab::{self, LabResult, Verdict};
+use crate::map::OriginMap;
+use crate::settings::MapSettings;
+use crate::store::HerbQuery;
+use crate::tenants::{Branding, NO_BRANDING};
+
+// Everything a page needs is served from this origin (plus images from `image_origins`: map
+// tiles, a tenant's logo); no inline script or style is allowed, so injected markup cannot run
+// even if it slipped past escaping
+pub fn content_security_policy(image_origins: &[String]) -> String {
+    let images: String = image_origins.iter().map(|origin| format!(" {}", origin)).collect();
+    format!(
+        "default-src 'none'; script-src 'self'; style-src 'self'; img-src 'self' data:{}; connect-src 'self'; form-action 'self'; base-uri 'none'; frame-ancestors 'none'",
+        images
+    )
+}
+
+// Origin (scheme://host[:port]) of an http(s) image URL such as a tenant's logo
+pub fn image_origin(url: &str) -> Option<String> {
+    url::Url::parse(url)
+        .ok()
+        .filter(|u| matches!(u.scheme(), "http" | "https"))
+        .map(|u| u.origin().ascii_serialization())
+}
+
+const STATIC_CACHE_CONTROL: &str = "public, max-age=86400";
+// Most recent other batches listed in the farmer profile
+const MAX_OTHER_BATCHES: usize = 5;
+
+// Stylesheets and scripts compiled into the binary, served under /static/{name}
+const STATIC_ASSETS: [(&str, &str, &str); 3] = [
+    ("site.css", "text/css; charset=utf-8", include_str!("../static/site.css")),
+    ("product.css", "text/css; charset=utf-8", include_str!("../static/product.css")),
+    ("scan.js", "text/javascript; charset=utf-8", include_str!("../static/scan.js")),
+];
+
+// Render an HTML template with the security headers every page carries
+pub fn render<T: Template>(page: &T) -> Response {
+    render_allowing_images(page, &[])
+}
+
+// Like render, but the page may also load images from `image_origins`
+pub fn render_allowing_images<T: Template>(page: &T, image_origins: &[String]) -> Response {
+    // Serialized origins are ASCII, so only the strict policy is a fallback in theory
+    let policy = HeaderValue::from_str(&content_security_policy(image_origins))
+        .unwrap_or_else(|_| HeaderValue::from_str(&content_security_policy(&[])).expect("ASCII policy"));
+    match page.render() {
+        Ok(html) => {
+            let mut response = (StatusCode::OK, Html(html)).into_response();
+            let headers = response.headers_mut();
+            headers.insert(header::CONTENT_SECURITY_POLICY, policy);
+            headers.insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+            headers.insert(header::REFERRER_POLICY, HeaderValue::from_static("no-referrer"));
+            response
+        }
+        Err(err) => {
+            eprintln!("template render failed: {}", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to render page").into_response()
+        }
+    }
+}
+
+// Everything the public page shows besides the herb itself
+#[derive(Default)]
+pub struct Journey {
+    pub lab_results: Vec<LabResult>,
+    pub attachments: Vec<AttachmentInfo>,
+    // All herbs registered by the same farmer, including this one
+    pub farmer_batches: Vec<Herb>,
+}
+
+// Load the journey; sources that fail to load are logged and left empty so the page still renders
+pub async fn load_journey(state: &AppState, herb: &Herb) -> Journey {
+    let lab_results = lab::fetch_lab_results(state, &herb.id).await.unwrap_or_else(|err| {
+        eprintln!("load_journey lab results failed for {}: {}", herb.id, err);
+        Vec::new()
+    });
+    // The page prefixes its links with the base path itself
+    let attachments = attachments::list_for_herb(state, &herb.id, "").await.unwrap_or_else(|err| {
+        eprintln!("load_journey attachments failed for {}: {}", herb.id, err);
+        Vec::new()
+    });
+    let farmer_batches = state
+        .herbs
+        .find(&HerbQuery::farmer(&herb.farmer))
+        .await
+        .unwrap_or_else(|err| {
+            eprintln!("load_journey farmer batches failed for {}: {}", herb.id, err);
+            Vec::new()
+        });
+    Journey { lab_results, attachments, farmer_batches }
+}
+
+fn format_date(at: DateTime<Utc>) -> String {
+    at.format("%Y-%m-%d").to_string()
+}
+
+fn verdict_key(verdict: Verdict) -> &'static str {
+    match verdict {
+        Verdict::Pass => "lab.pass",
+        Verdict::Fail => "lab.fail",
+        Verdict::NoSpec => "lab.no_spec",
+    }
+}
+
+fn status_key(status: HerbStatus) -> &'static str {
+    match status {
+        HerbStatus::Pending => "status.pending",
+        HerbStatus::Released => "status.released",
+        HerbStatus::Recalled => "status.recalled",
+    }
+}
+
+pub struct TimelineEntry {
+    at: DateTime<Utc>,
+    pub date: String,
+    pub title_key: &'static str,
+    // Free text (lab name, location, recall reason) shown as entered
+    pub detail: Option<String>,
+    pub verdict_key: Option<&'static str>,
+}
+
+fn timeline(herb: &Herb, lab_results: &[LabResult]) -> Vec<TimelineEntry> {
+    let entry = |at: DateTime<Utc>, title_key, detail, verdict_key| TimelineEntry {
+        at,
+        date: format_date(at),
+        title_key,
+        detail,
+        verdict_key,
+    };
+    let mut entries = vec![entry(herb.created_at, "timeline.registered", Some(herb.location.clone()), None)];
+    for result in lab_results {
+        entries.push(entry(
+            result.tested_at,
+            "timeline.lab_tested",
+            Some(result.lab_name.clone()),
+            Some(verdict_key(result.verdict)),
+        ));
+    }
+    if let Some(at) = herb.status_changed_at {
+        match herb.status {
+            HerbStatus::Released => entries.push(entry(at, "timeline.released", None, None)),
+            HerbStatus::Recalled => entries.push(entry(at, "timeline.recalled", herb.recall_reason.clone(), None)),
+            HerbStatus::Pending => {}
+        }
+    }
+    entries.sort_by_key(|e| e.at);
+    entries
+}
+
+pub struct CheckRow {
+    pub parameter_key: String,
+    pub value: String,
+    pub bound_key: &'static str,
+    pub limit: String,
+    pub passed: bool,
+}
+
+// Catalogue keys double as CSS class names ("lab.pass" -> "lab-pass")
+fn css_class(key: &str) -> String {
+    key.replace('.', "-")
+}
+
+pub struct LabRow {
+    pub verdict_key: &'static str,
+    pub verdict_class: String,
+    pub lab_name: String,
+    pub tested_at: String,
+    pub coa_url: Option<String>,
+    pub checks: Vec<CheckRow>,
+}
+
+impl From<&LabResult> for LabRow {
+    fn from(result: &LabResult) -> Self {
+        Self {
+            verdict_key: verdict_key(result.verdict),
+            verdict_class: css_class(verdict_key(result.verdict)),
+            lab_name: result.lab_name.clone(),
+            tested_at: format_date(result.tested_at),
+            coa_url: result.coa_url.clone(),
+            checks: result
+                .checks
+                .iter()
+                .map(|c| CheckRow {
+                    parameter_key: format!("param.{}", c.parameter),
+                    value: c.value.to_string(),
+                    bound_key: if c.bound == "min" { "bound.min" } else { "bound.max" },
+                    limit: c.limit.to_string(),
+                    passed: c.passed,
+                })
+                .collect(),
+        }
+    }
+}
+
+pub struct BatchLink {
+    pub id: String,
+    pub name: String,
+    pub date: String,
+}
+
+pub struct FarmerProfile {
+    pub batches: usize,
+    pub since: String,
+    pub other_batches: Vec<BatchLink>,
+}
+
+fn farmer_profile(herb: &Herb, batches: &[Herb]) -> FarmerProfile {
+    let mut others: Vec<&Herb> = batches.iter().filter(|b| b.id != herb.id).collect();
+    others.sort_by_key(|b| std::cmp::Reverse(b.created_at));
+    let since = batches.iter().map(|b| b.created_at).chain([herb.created_at]).min().unwrap_or(herb.created_at);
+    FarmerProfile {
+        batches: others.len() + 1,
+        since: format_date(since),
+        other_batches: others
+            .into_iter()
+            .take(MAX_OTHER_BATCHES)
+            .map(|b| BatchLink { id: b.id.clone(), name: b.name.clone(), date: format_date(b.created_at) })
+            .collect(),
+    }
+}
+
+pub struct LanguageOption {
+    pub code: &'static str,
+    pub name: &'static str,
+    pub current: bool,
+}
+
+// Consumer traceability page; labels come from the translation catalogues, farmer-entered
+// fields are shown as entered
+#[derive(Template)]
+#[template(path = "product.html")]
+pub struct ProductPage<'a> {
+    pub t: Translator,
+    pub title: &'a str,
+    // The species was translated, so also show the name the farmer entered
+    pub show_entered_name: bool,
+    pub herb: &'a Herb,
+    pub status_key: &'static str,
+    pub status_class: String,
+    pub recalled: bool,
+    pub timeline: Vec<TimelineEntry>,
+    pub map: Option<OriginMap>,
+    pub farmer: FarmerProfile,
+    pub lab_results: Vec<LabRow>,
+    pub documents: Vec<&'a AttachmentInfo>,
+    pub photos: Vec<&'a AttachmentInfo>,
+    pub languages: Vec<LanguageOption>,
+    pub brand: &'a Branding,
+    // Accent colour as hex digits, for /static/brand.css
+    pub accent: Option<&'a str>,
+    // Tenant base path (see tenants::BasePath), prefixed to every root-absolute link
+    pub base: &'a str,
+}
+
+impl<'a> ProductPage<'a> {
+    pub fn new(herb: &'a Herb, journey: &'a Journey, lang: Lang, map: &MapSettings) -> Self {
+        let t = Translator::new(lang);
+        let species = t.species(&herb.name);
+        let (photos, documents) = journey.attachments.iter().partition(|a| a.content_type.starts_with("image/"));
+        Self {
+            t,
+            title: species.unwrap_or(&herb.name),
+            show_entered_name: species.is_some_and(|s| !s.eq_ignore_ascii_case(herb.name.trim())),
+            herb,
+            status_key: status_key(herb.status),
+            status_class: css_class(status_key(herb.status)),
+            recalled: herb.status == HerbStatus::Recalled,
+            timeline: timeline(herb, &journey.lab_results),
+            map: herb.latitude.zip(herb.longitude).map(|(lat, lon)| OriginMap::new(lat, lon, map)),
+            farmer: farmer_profile(herb, &journey.farmer_batches),
+            lab_results: journey.lab_results.iter().map(LabRow::from).collect(),
+            documents,
+            photos,
+            languages: Lang::ALL
+                .into_iter()
+                .map(|l| LanguageOption { code: l.code(), name: l.native_name(), current: l == lang })
+                .collect(),
+            brand: &NO_BRANDING,
+            accent: None,
+            base: "",
+        }
+    }
+
+    // The tenant's name, logo and accent colour
+    pub fn with_brand(mut self, brand: &'a Branding) -> Self {
+        self.brand = brand;
+        self.accent = brand.accent_color.as_deref().map(|c| c.trim_start_matches('#'));
+        self
+    }
+
+    // Links for a page served under /t/{tenant}
+    pub fn with_base(mut self, base: &'a str) -> Self {
+        self.base = base;
+        self
+    }
+}
+
+#[derive(Template)]
+#[template(path = "scan.html")]
+pub struct ScanPage<'a> {
+    // Tenant base path, prefixed to the asset links and the /scan endpoint
+    pub base: &'a str,
+}
+
+// Handlers
+
+// GET /static/{name} - Embedded stylesheets and scripts
+#[utoipa::path(
+    get,
+    path = "/static/{name}",
+    tag = "public",
+    params(("name" = String, Path, description = "Asset file name")),
+    responses(
+        (status = 200, description = "Stylesheet or script", content(("text/css"), ("text/javascript"))),
+        (status = 404, description = "Unknown asset"),
+    ),
+)]
+pub async fn static_asset(Path(name): Path<String>) -> impl IntoResponse {
+    match STATIC_ASSETS.iter().find(|(asset, _, _)| *asset == name) {
+        Some((_, content_type, body)) => (
+            StatusCode::OK,
+            [
+                (header::CONTENT_TYPE, *content_type),
+                (header::CACHE_CONTROL, STATIC_CACHE_CONTROL),
+                (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+            ],
+            *body,
+        )
+            .into_response(),
+        None => (StatusCode::NOT_FOUND, "Not found").into_response(),
+    }
+}
+
+// GET /static/brand.css?accent=0a6 - Accent colour of a tenant's pages, as a stylesheet so
+// the pages need no inline style
+#[utoipa::path(
+    get,
+    path = "/static/brand.css",
+    tag = "public",
+    params(BrandQuery),
+    responses(
+        (status = 200, description = "Stylesheet setting the accent colour", content_type = "text/css"),
+        (status = 400, description = "accent is not 3 or 6 hex digits"),
+    ),
+)]
+pub async fn brand_css(Query(query): Query<BrandQuery>) -> impl IntoResponse {
+    let accent = query.accent.unwrap_or_default();
+    if !matches!(accent.len(), 3 | 6) || !accent.chars().all(|c| c.is_ascii_hexdigit()) {
+        return (StatusCode::BAD_REQUEST, "accent must be 3 or 6 hex digits").into_response();
+    }
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "text/css; charset=utf-8"),
+            (header::CACHE_CONTROL, STATIC_CACHE_CONTROL),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+        ],
+        format!(":root{{--accent:#{}}}\n", accent),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BrandQuery {
+    // Hex digits without the '#'
+    pub accent: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::test_herb;
+    use chrono::Duration;
+
+    const PAYLOADS: [&str; 4] = [
+        "<script>alert(1)</script>",
+        "\"><img src=x onerror=alert(1)>",
+        "' onmouseover='alert(1)",
+        "</title><svg onload=alert(1)>",
+    ];
+
+    // An attribute like ` onclick=` anywhere in the markup
+    fn has_event_handler(html: &str) -> bool {
+        html.match_indices(" on").any(|(i, _)| {
+            let rest = &html[i + 3..];
+            let name_len = rest.chars().take_while(|c| c.is_ascii_alphabetic()).count();
+            name_len > 0 && rest[name_len..].starts_with('=')
+        })
+    }
+
+    fn herb_with(value: &str) -> Herb {
+        Herb { id: "herb_1a2b".to_string(), location: value.to_string(), ..test_herb(value, value) }
+    }
+
+    // A recalled, mapped herb with every free-text field set to `value`
+    fn journey_with(value: &str) -> (Herb, Journey) {
+        let mut herb = herb_with(value);
+        herb.status = HerbStatus::Recalled;
+        herb.status_changed_at = Some(Utc::now());
+        herb.recall_reason = Some(value.to_string());
+        herb.latitude = Some(12.97);
+        herb.longitude = Some(77.59);
+        let mut other = herb_with(value);
+        other.id = "herb_ffff".to_string();
+        let journey = Journey {
+            lab_results: vec![LabResult {
+                id: "lab_1".to_string(),
+                herb_id: herb.id.clone(),
+                lab_name: value.to_string(),
+                tested_at: Utc::now(),
+                measurements: Default::default(),
+                coa_url: Some(format!("https://example.com/coa?x={}", value)),
+                verdict: Verdict::Fail,
+                checks: vec![lab::ParameterCheck {
+                    parameter: "moisture_pct".to_string(),
+                    value: 14.0,
+                    limit: 12.0,
+                    bound: "max".to_string(),
+                    passed: false,
+                }],
+                created_at: Utc::now(),
+            }],
+            attachments: vec![AttachmentInfo {
+                name: "coa.pdf".to_string(),
+                content_type: "application/pdf".to_string(),
+                length: 10,
+                url: "/herbs/herb_1a2b/attachments/coa.pdf".to_string(),
+                thumbnail_url: None,
+            }],
+            farmer_batches: vec![herb.clone(), other],
+        };
+        (herb, journey)
+    }
+
+    // Characters that open markup or end an attribute value
+    fn markup_chars(html: &str) -> [usize; 4] {
+        ['<', '>', '"', '\''].map(|c| html.matches(c).count())
+    }
+
+    #[test]
+    fn malicious_fields_are_escaped_on_the_product_page() {
+        for lang in Lang::ALL {
+            let (benign_herb, benign_journey) = journey_with("x");
+            let benign = ProductPage::new(&benign_herb, &benign_journey, lang, &MapSettings::default()).render().unwrap();
+            for payload in PAYLOADS {
+                let (herb, journey) = journey_with(payload);
+                let html = ProductPage::new(&herb, &journey, lang, &MapSettings::default()).render().unwrap();
+                assert!(!html.contains(payload), "payload {:?} rendered raw in {:?}", payload, lang);
+                assert!(!html.contains("<script"), "script tag in {:?}", lang);
+                assert!(!html.contains("<img src=x"));
+                assert!(!html.contains("<svg onload"));
+                // The payload added no tags and broke out of no attribute
+                assert_eq!(markup_chars(&html), markup_chars(&benign), "payload {:?} changed the markup", payload);
+            }
+        }
+    }
+
+    #[test]
+    fn escaped_values_are_still_shown() {
+        let herb = herb_with("<b>Tulsi & Neem</b>");
+        let html = ProductPage::new(&herb, &Journey::default(), Lang::En, &MapSettings::default()).render().unwrap();
+        assert!(html.contains("&#60;b&#62;Tulsi &#38; Neem&#60;/b&#62;"));
+    }
+
+    #[test]
+    fn pages_have_no_inline_script_or_style() {
+        let (herb, journey) = journey_with("Tulsi");
+        for html in [
+            ProductPage::new(&herb, &journey, Lang::Hi, &MapSettings::default()).render().unwrap(),
+            ScanPage { base: "" }.render().unwrap(),
+        ] {
+            assert!(!html.contains("<script>"));
+            assert!(!html.contains("<style"));
+            assert!(!html.contains(" style="));
+            assert!(!has_event_handler(&html));
+        }
+    }
+
+    #[test]
+    fn branding_is_escaped_and_needs_no_inline_style() {
+        let (herb, journey) = journey_with("Tulsi");
+        let brand = Branding {
+            display_name: Some(PAYLOADS[0].to_string()),
+            logo_url: Some("https://cdn.example.org/logo.png".to_string()),
+            accent_color: Some("#0a6".to_string()),
+        };
+        let page = ProductPage::new(&herb, &journey, Lang::En, &MapSettings::default()).with_brand(&brand);
+        let html = page.render().unwrap();
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("<style"));
+        assert!(html.contains("href=\"/static/brand.css?accent=0a6\""));
+        let response = render_allowing_images(&page, &[image_origin(brand.logo_url.as_deref().unwrap()).unwrap()]);
+        let csp = response.headers().get(header::CONTENT_SECURITY_POLICY).unwrap().to_str().unwrap();
+        assert!(csp.contains("img-src 'self' data: https://cdn.example.org"));
+    }
+
+    #[test]
+    fn links_carry_the_tenant_base_path() {
+        let (herb, journey) = journey_with("Tulsi");
+        let html = ProductPage::new(&herb, &journey, Lang::En, &MapSettings::default()).with_base("/t/acme").render().unwrap();
+        assert!(html.contains("href=\"/t/acme/static/site.css\""));
+        assert!(html.contains(&format!("src=\"/t/acme/qr/{}\"", herb.id)));
+        assert!(html.contains(&format!("href=\"/t/acme/p/{}\"", herb.id)));
+        assert!(html.contains("href=\"/t/acme/p/herb_ffff/html"));
+        assert!(html.contains("href=\"/t/acme/herbs/herb_1a2b/attachments/coa.pdf\""));
+        let scan = ScanPage { base: "/t/acme" }.render().unwrap();
+        assert!(scan.contains("src=\"/t/acme/static/scan.js\""));
+        assert!(scan.contains("data-action=\"/t/acme/scan\""));
+    }
+
+    #[test]
+    fn rendered_pages_carry_a_content_security_policy() {
+        let response = render(&ScanPage { base: "" });
+        let csp = response.headers().get(header::CONTENT_SECURITY_POLICY).unwrap();
+        assert_eq!(csp, &content_security_policy(&[]));
+        assert!(content_security_policy(&[]).contains("script-src 'self'"));
+        assert!(!content_security_policy(&[]).contains("unsafe-inline"));
+        assert_eq!(image_origin("https://cdn.example.org:8443/logo.png").as_deref(), Some("https://cdn.example.org:8443"));
+        assert_eq!(image_origin("javascript:alert(1)"), None);
+    }
+
+    #[test]
+    fn the_timeline_runs_oldest_first() {
+        let (mut herb, journey) = journey_with("Tulsi");
+        let registered = herb.created_at;
+        let mut result = journey.lab_results[0].clone();
+        result.tested_at = registered + Duration::days(2);
+        herb.status_changed_at = Some(registered + Duration::days(3));
+        herb.recall_reason = Some("mould".to_string());
+        let entries = timeline(&herb, &[result]);
+        let titles: Vec<&str> = entries.iter().map(|e| e.title_key).collect();
+        assert_eq!(titles, ["timeline.registered", "timeline.lab_tested", "timeline.recalled"]);
+        assert_eq!(entries[1].verdict_key, Some("lab.fail"));
+        assert_eq!(entries[2].detail.as_deref(), Some("mould"));
+        assert_eq!(entries[2].date, format_date(registered + Duration::days(3)));
+
+        // A pending herb has no status entry, even with a change recorded
+        herb.status = HerbStatus::Pending;
+        assert_eq!(timeline(&herb, &[]).len(), 1);
+    }
+
+    #[test]
+    fn the_farmer_profile_lists_other_batches_newest_first() {
+        let herb = herb_with("Tulsi");
+        let mut batches = vec![herb.clone()];
+        for days in 1..=7 {
+            let mut other = herb_with("Brahmi");
+            other.id = format!("herb_{}", days);
+            other.created_at = herb.created_at - Duration::days(days);
+            batches.push(other);
+        }
+        let profile = farmer_profile(&herb, &batches);
+        assert_eq!(profile.batches, 8);
+        assert_eq!(profile.since, format_date(herb.created_at - Duration::days(7)));
+        let ids: Vec<&str> = profile.other_batches.iter().map(|b| b.id.as_str()).collect();
+        assert_eq!(ids, ["herb_1", "herb_2", "herb_3", "herb_4", "herb_5"]);
+
+        let html = ProductPage::new(&herb, &Journey { farmer_batches: batches, ..Journey::default() }, Lang::En, &MapSettings::default())
+            .with_base("/t/acme")
+            .render()
+            .unwrap();
+        assert!(html.contains("href=\"/t/acme/p/herb_1/html?lang=en\""));
+        assert!(!html.contains("/p/herb_6/"));
+    }
+
+    #[test]
+    fn the_page_shows_the_origin_only_to_two_decimals() {
+        let (mut herb, journey) = journey_with("Tulsi");
+        herb.latitude = Some(12.971_6);
+        herb.longitude = Some(77.594_6);
+        let html = ProductPage::new(&herb, &journey, Lang::En, &MapSettings::default()).render().unwrap();
+        assert!(html.contains("12.97, 77.59"));
+        assert!(!html.contains("12.971") && !html.contains("77.594"));
+    }
+}
@@ -0,0 +1,204 @@
+// Minimal PDF writer: filled rectangles, Helvetica text and RGB images.
+// Coordinates are PDF points (1/72 inch) with the origin at the bottom-left of the page.
+
+pub struct PdfImage {
+    pub width: u32,
+    pub height: u32,
+    // Packed 8-bit RGB samples, row by row from the top
+    pub rgb: Vec<u8>,
+}
+
+pub struct PdfPage {
+    width: f64,
+    height: f64,
+    content: Vec<u8>,
+    images: Vec<PdfImage>,
+}
+
+impl PdfPage {
+    pub fn new(width: f64, height: f64) -> Self {
+        Self { width, height, content: Vec::new(), images: Vec::new() }
+    }
+
+    pub fn fill_color(&mut self, rgb: [u8; 3]) {
+        let [r, g, b] = rgb.map(|c| c as f64 / 255.0);
+        self.content.extend_from_slice(format!("{:.3} {:.3} {:.3} rg\n", r, g, b).as_bytes());
+    }
+
+    pub fn rect(&mut self, x: f64, y: f64, width: f64, height: f64) {
+        self.content
+            .extend_from_slice(format!("{:.3} {:.3} {:.3} {:.3} re f\n", x, y, width, height).as_bytes());
+    }
+
+    // Single line of Helvetica text with its baseline at y
+    pub fn text(&mut self, x: f64, y: f64, size: f64, text: &str) {
+        self.content
+            .extend_from_slice(format!("BT /F1 {:.2} Tf {:.3} {:.3} Td (", size, x, y).as_bytes());
+        self.content.extend(encode_text(text));
+        self.content.extend_from_slice(b") Tj ET\n");
+    }
+
+    pub fn image(&mut self, image: PdfImage, x: f64, y: f64, width: f64, height: f64) {
+        let name = format!("Im{}", self.images.len());
+        self.images.push(image);
+        self.content.extend_from_slice(
+            format!("q {:.3} 0 0 {:.3} {:.3} {:.3} cm /{} Do Q\n", width, height, x, y, name).as_bytes(),
+        );
+    }
+}
+
+// Approximate Helvetica advance width, good enough for truncating label text
+pub fn text_width(text: &str, size: f64) -> f64 {
+    text.chars().count() as f64 * size * 0.55
+}
+
+// WinAnsiEncoding byte for a character: Latin-1 plus the typographic punctuation and euro sign
+// WinAnsi places in 0x80-0x9f. Helvetica has no glyphs for anything else (e.g. Indic scripts).
+fn win_ansi(c: char) -> Option<u8> {
+    match c {
+        ' '..='~' | '\u{a0}'..='\u{ff}' => Some(c as u32 as u8),
+        '€' => Some(0x80),
+        '‚' => Some(0x82),
+        '„' => Some(0x84),
+        '…' => Some(0x85),
+        '‘' => Some(0x91),
+        '’' => Some(0x92),
+        '“' => Some(0x93),
+        '”' => Some(0x94),
+        '•' => Some(0x95),
+        '–' => Some(0x96),
+        '—' => Some(0x97),
+        _ => None,
+    }
+}
+
+// Whether `text` can be drawn with PdfPage::text; callers reject anything else up front
+pub fn is_encodable(text: &str) -> bool {
+    text.chars().all(|c| win_ansi(c).is_some())
+}
+
+// String literal body; characters that fail is_encodable become '?'
+fn encode_text(text: &str) -> Vec<u8> {
+    let mut out = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                out.push(b'\\');
+                out.push(c as u8);
+            }
+            _ => out.push(win_ansi(c).unwrap_or(b'?')),
+        }
+    }
+    out
+}
+
+#[derive(Default)]
+pub struct PdfDocument {
+    pages: Vec<PdfPage>,
+}
+
+impl PdfDocument {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_page(&mut self, page: PdfPage) {
+        self.pages.push(page);
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // Object numbers: 1 catalog, 2 page tree, 3 font, then per page: page, content, images
+        let mut objects: Vec<Vec<u8>> = Vec::new();
+        let mut kids = Vec::new();
+        let mut next_id = 4;
+        let mut page_objects = Vec::new();
+        for page in &self.pages {
+            let page_id = next_id;
+            let content_id = next_id + 1;
+            let image_ids: Vec<usize> = (0..page.images.len()).map(|i| next_id + 2 + i).collect();
+            next_id += 2 + page.images.len();
+            kids.push(format!("{} 0 R", page_id));
+
+            let xobjects: String = image_ids
+                .iter()
+                .enumerate()
+                .map(|(i, id)| format!("/Im{} {} 0 R ", i, id))
+                .collect();
+            let page_obj = format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.3} {:.3}] /Resources << /Font << /F1 3 0 R >> /XObject << {}>> >> /Contents {} 0 R >>",
+                page.width, page.height, xobjects, content_id
+            );
+            let mut content_obj = format!("<< /Length {} >>\nstream\n", page.content.len()).into_bytes();
+            content_obj.extend_from_slice(&page.content);
+            content_obj.extend_from_slice(b"\nendstream");
+
+            let mut image_objs = Vec::new();
+            for image in &page.images {
+                let mut obj = format!(
+                    "<< /Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceRGB /BitsPerComponent 8 /Length {} >>\nstream\n",
+                    image.width, image.height, image.rgb.len()
+                )
+                .into_bytes();
+                obj.extend_from_slice(&image.rgb);
+                obj.extend_from_slice(b"\nendstream");
+                image_objs.push(obj);
+            }
+            page_objects.push((page_obj.into_bytes(), content_obj, image_objs));
+        }
+
+        objects.push(b"<< /Type /Catalog /Pages 2 0 R >>".to_vec());
+        objects.push(format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids.join(" "), self.pages.len()).into_bytes());
+        objects.push(b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>".to_vec());
+        for (page_obj, content_obj, image_objs) in page_objects {
+            objects.push(page_obj);
+            objects.push(content_obj);
+            objects.extend(image_objs);
+        }
+
+        let mut out = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+        let mut offsets = Vec::with_capacity(objects.len());
+        for (i, obj) in objects.iter().enumerate() {
+            offsets.push(out.len());
+            out.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+            out.extend_from_slice(obj);
+            out.extend_from_slice(b"\nendobj\n");
+        }
+        let xref_offset = out.len();
+        out.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes());
+        for offset in offsets {
+            out.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+        }
+        out.extend_from_slice(
+            format!("trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", objects.len() + 1, xref_offset)
+                .as_bytes(),
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_is_win_ansi_encoded_and_escaped() {
+        assert_eq!(encode_text("Tulsi (Holy Basil)"), b"Tulsi \\(Holy Basil\\)");
+        assert_eq!(encode_text("Café – “Neem”"), b"Caf\xe9 \x96 \x93Neem\x94");
+        assert!(is_encodable("Müller’s Ashwagandha €5"));
+        assert!(!is_encodable("तुलसी"));
+        assert!(!is_encodable("Tulsi ✓"));
+    }
+
+    #[test]
+    fn documents_list_every_object_in_the_xref_table() {
+        let mut page = PdfPage::new(100.0, 50.0);
+        page.text(10.0, 10.0, 8.0, "Tulsi");
+        let mut doc = PdfDocument::new();
+        doc.add_page(page);
+        let bytes = doc.to_bytes();
+        let text = String::from_utf8_lossy(&bytes);
+        assert!(text.starts_with("%PDF-1.4"));
+        assert!(text.contains("xref\n0 6\n"));
+        assert!(text.ends_with("%%EOF\n"));
+    }
+}
@@ -0,0 +1,128 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use crate::handlers::AppState;
+
+// Startup provisioning: prepare the selected store (tables, or CouchDB databases and Mango
+// indexes) and, with HERB_STORE=couchdb, check CouchDB accepts our credentials first. Until that
+// has succeeded the server is alive (it answers /health/live) but not ready (see health.rs).
+const FIRST_RETRY: Duration = Duration::from_secs(1);
+const MAX_RETRY: Duration = Duration::from_secs(60);
+
+// Shared by every tenant's state, set by the startup task
+#[derive(Clone, Default)]
+pub struct Readiness(Arc<RwLock<ReadinessReport>>);
+
+#[derive(Serialize, Clone, Default)]
+pub struct ReadinessReport {
+    pub ready: bool,
+    // Provisioning attempts so far
+    pub attempts: u32,
+    // Why the last attempt failed; cleared once ready
+    pub last_error: Option<String>,
+    pub ready_since: Option<DateTime<Utc>>,
+}
+
+impl Readiness {
+    pub fn report(&self) -> ReadinessReport {
+        self.0.read().unwrap().clone()
+    }
+
+    fn record(&self, result: Result<(), String>) {
+        let mut report = self.0.write().unwrap();
+        report.attempts += 1;
+        match result {
+            Ok(()) => {
+                report.ready = true;
+                report.last_error = None;
+                report.ready_since = Some(Utc::now());
+            }
+            Err(e) => report.last_error = Some(e),
+        }
+    }
+}
+
+// Turns a failed step into something an operator can act on
+fn explain(step: &str, err: &reqwest::Error) -> String {
+    match err.status() {
+        Some(reqwest::StatusCode::UNAUTHORIZED) | Some(reqwest::StatusCode::FORBIDDEN) => {
+            format!("{}: CouchDB rejected the credentials (check COUCHDB_USER and COUCHDB_PASS)", step)
+        }
+        Some(status) => format!("{}: CouchDB answered {}", step, status),
+        None if err.is_connect() || err.is_timeout() => format!("{}: CouchDB is unreachable ({})", step, err),
+        None => format!("{}: {}", step, err),
+    }
+}
+
+async fn attempt(state: &AppState, extra_dbs: &[String]) -> Result<(), String> {
+    if let Some(couch) = &state.couch {
+        match couch.session_user().await {
+            Ok(Some(_)) => {}
+            Ok(None) => return Err("CouchDB accepted the request anonymously; check COUCHDB_USER".to_string()),
+            Err(e) => return Err(explain("checking credentials", &e)),
+        }
+        for db in std::iter::once(&state.replications_db()).chain(extra_dbs) {
+            couch.ensure_db(db).await.map_err(|e| explain(&format!("creating {}", db), &e))?;
+        }
+    }
+    state.herbs.provision().await.map_err(|e| format!("provisioning the herb store: {}", e))?;
+    state.docs.provision().await.map_err(|e| format!("provisioning the document store: {}", e))?;
+    Ok(())
+}
+
+// 1s, 2s, 4s, ... capped at a minute
+fn backoff(failures: u32) -> Duration {
+    FIRST_RETRY.saturating_mul(2u32.saturating_pow(failures.saturating_sub(1))).min(MAX_RETRY)
+}
+
+// Retries until provisioning succeeds, recording each attempt in `state.readiness`
+pub async fn run(state: AppState, extra_dbs: Vec<String>) {
+    loop {
+        let result = attempt(&state, &extra_dbs).await;
+        let failed = result.clone().err();
+        state.readiness.record(result);
+        let Some(err) = failed else {
+            println!("Store is ready");
+            return;
+        };
+        let wait = backoff(state.readiness.report().attempts);
+        eprintln!("Startup check failed, retrying in {}s: {}", wait.as_secs(), err);
+        tokio::time::sleep(wait).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::couchdb::CouchDb;
+
+    #[test]
+    fn retries_back_off_to_a_minute() {
+        let waits: Vec<u64> = (1..=8).map(|n| backoff(n).as_secs()).collect();
+        assert_eq!(waits, vec![1, 2, 4, 8, 16, 32, 60, 60]);
+        assert_eq!(backoff(u32::MAX), MAX_RETRY);
+    }
+
+    #[tokio::test]
+    async fn memory_store_is_ready_without_couchdb() {
+        assert_eq!(attempt(&AppState::for_tests(), &[]).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn unreachable_couchdb_leaves_the_server_unready() {
+        let state = AppState {
+            couch: Some(CouchDb::new("http://127.0.0.1:9", "admin", "secret")),
+            ..AppState::for_tests()
+        };
+        let result = attempt(&state, &[]).await;
+        state.readiness.record(result);
+        let report = state.readiness.report();
+        assert!(!report.ready);
+        assert!(report.last_error.unwrap().contains("unreachable"));
+
+        state.readiness.record(Ok(()));
+        let report = state.readiness.report();
+        assert!(report.ready && report.last_error.is_none() && report.attempts == 2);
+    }
+}
@@ -0,0 +1,587 @@
+use base64::{engine::general_purpose, Engine as _};
+use image::{imageops, DynamicImage, ImageFormat, Rgba, RgbaImage};
+use lru::LruCache;
+use qrcode::{types::QrError, Color, EcLevel, QrCode};
+use serde::Deserialize;
+use utoipa::IntoParams;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Cursor;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use crate::gs1;
+use crate::handlers::Herb;
+use crate::metrics;
+use crate::pdf::{PdfDocument, PdfImage, PdfPage};
+use crate::settings::{LinkFormat, QrSettings};
+
+const DEFAULT_SIZE: u32 = 512;
+const MIN_SIZE: u32 = 64;
+const MAX_SIZE: u32 = 4096;
+const DEFAULT_MARGIN: u32 = 4;
+const MAX_MARGIN: u32 = 16;
+// The logo may cover at most this fraction of the code's width; level H recovers ~30% damage
+const LOGO_WIDTH_FRACTION: f64 = 0.22;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum QrFormat {
+    Png,
+    Svg,
+    Pdf,
+}
+
+impl QrFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            QrFormat::Png => "image/png",
+            QrFormat::Svg => "image/svg+xml",
+            QrFormat::Pdf => "application/pdf",
+        }
+    }
+}
+
+// Query parameters accepted by /qr/{id}
+#[derive(Deserialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QrQuery {
+    pub format: Option<String>,
+    pub size: Option<u32>,
+    pub margin: Option<u32>,
+    pub ec: Option<String>,
+    pub fg: Option<String>,
+    pub bg: Option<String>,
+    #[serde(default)]
+    pub logo: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct QrOptions {
+    pub format: QrFormat,
+    // Target width/height in pixels (points for PDF)
+    pub size: u32,
+    // Quiet zone in modules
+    pub margin: u32,
+    pub ec: EcLevel,
+    pub fg: [u8; 3],
+    pub bg: [u8; 3],
+    pub logo: bool,
+}
+
+impl Default for QrOptions {
+    fn default() -> Self {
+        Self {
+            format: QrFormat::Png,
+            size: DEFAULT_SIZE,
+            margin: DEFAULT_MARGIN,
+            ec: EcLevel::M,
+            fg: [0, 0, 0],
+            bg: [255, 255, 255],
+            logo: false,
+        }
+    }
+}
+
+// Accepts "0a6", "00aa66" or "#00aa66"
+fn parse_color(raw: &str) -> Result<[u8; 3], String> {
+    let hex = raw.trim().trim_start_matches('#');
+    let expanded: String = match hex.len() {
+        3 => hex.chars().flat_map(|c| [c, c]).collect(),
+        6 => hex.to_string(),
+        _ => return Err(format!("invalid colour: {}", raw)),
+    };
+    let channel = |i: usize| u8::from_str_radix(&expanded[i..i + 2], 16).map_err(|_| format!("invalid colour: {}", raw));
+    Ok([channel(0)?, channel(2)?, channel(4)?])
+}
+
+impl QrOptions {
+    // `has_logo`: whether a brand logo is configured (QrCache::has_logo)
+    pub fn from_query(query: &QrQuery, has_logo: bool) -> Result<Self, String> {
+        let mut options = QrOptions::default();
+        if let Some(format) = &query.format {
+            options.format = match format.to_lowercase().as_str() {
+                "png" => QrFormat::Png,
+                "svg" => QrFormat::Svg,
+                "pdf" => QrFormat::Pdf,
+                other => return Err(format!("unsupported format: {}", other)),
+            };
+        }
+        if let Some(size) = query.size {
+            if !(MIN_SIZE..=MAX_SIZE).contains(&size) {
+                return Err(format!("size must be between {} and {}", MIN_SIZE, MAX_SIZE));
+            }
+            options.size = size;
+        }
+        if let Some(margin) = query.margin {
+            if margin > MAX_MARGIN {
+                return Err(format!("margin must be at most {}", MAX_MARGIN));
+            }
+            options.margin = margin;
+        }
+        if let Some(ec) = &query.ec {
+            options.ec = match ec.to_uppercase().as_str() {
+                "L" => EcLevel::L,
+                "M" => EcLevel::M,
+                "Q" => EcLevel::Q,
+                "H" => EcLevel::H,
+                other => return Err(format!("unsupported ec level: {}", other)),
+            };
+        }
+        if let Some(fg) = &query.fg {
+            options.fg = parse_color(fg)?;
+        }
+        if let Some(bg) = &query.bg {
+            options.bg = parse_color(bg)?;
+        }
+        if query.logo {
+            if options.ec != EcLevel::H {
+                return Err("logo requires ec=H".to_string());
+            }
+            if !has_logo {
+                return Err("no brand logo is configured".to_string());
+            }
+            options.logo = true;
+        }
+        Ok(options)
+    }
+}
+
+// Brand logo (QrSettings::logo_path), with a SHA-256 of the file for cache keys
+pub struct BrandLogo {
+    image: DynamicImage,
+    hash: String,
+}
+
+impl BrandLogo {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|err| format!("could not read QR logo {}: {}", path, err))?;
+        let image = image::load_from_memory(&bytes).map_err(|err| format!("could not load QR logo {}: {}", path, err))?;
+        Ok(Self { image, hash: format!("{:x}", Sha256::digest(&bytes)) })
+    }
+}
+
+// Dark/light module grid including the quiet zone
+pub struct QrMatrix {
+    pub width: usize,
+    dark: Vec<bool>,
+}
+
+impl QrMatrix {
+    pub fn encode(payload: &str, ec: EcLevel, margin: u32) -> Result<Self, QrError> {
+        let code = QrCode::with_error_correction_level(payload, ec)?;
+        let inner = code.width();
+        let margin = margin as usize;
+        let width = inner + 2 * margin;
+        let mut dark = vec![false; width * width];
+        for (i, color) in code.to_colors().into_iter().enumerate() {
+            if color == Color::Dark {
+                dark[(i / inner + margin) * width + i % inner + margin] = true;
+            }
+        }
+        Ok(Self { width, dark })
+    }
+
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        self.dark[y * self.width + x]
+    }
+
+    // Draw the code as vector rectangles into a square at (x, y) with side `size`
+    pub fn draw_pdf(&self, page: &mut PdfPage, x: f64, y: f64, size: f64, fg: [u8; 3], bg: [u8; 3]) {
+        let module = size / self.width as f64;
+        page.fill_color(bg);
+        page.rect(x, y, size, size);
+        page.fill_color(fg);
+        for row in 0..self.width {
+            for col in 0..self.width {
+                if self.is_dark(col, row) {
+                    // PDF y grows upwards, matrix rows grow downwards
+                    let top = y + size - (row + 1) as f64 * module;
+                    page.rect(x + col as f64 * module, top, module, module);
+                }
+            }
+        }
+    }
+}
+
+fn logo_side(code_side: f64) -> f64 {
+    (code_side * LOGO_WIDTH_FRACTION).floor()
+}
+
+fn render_png(matrix: &QrMatrix, options: &QrOptions, logo: Option<&DynamicImage>) -> Vec<u8> {
+    let module = (options.size as usize / matrix.width).max(1) as u32;
+    let side = module * matrix.width as u32;
+    let [fr, fg, fb] = options.fg;
+    let [br, bg, bb] = options.bg;
+    let mut image = RgbaImage::from_pixel(side, side, Rgba([br, bg, bb, 255]));
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        if matrix.is_dark((x / module) as usize, (y / module) as usize) {
+            *pixel = Rgba([fr, fg, fb, 255]);
+        }
+    }
+    if options.logo {
+        if let Some(logo) = logo {
+            let target = logo_side(side as f64) as u32;
+            let logo = logo.resize(target, target, imageops::FilterType::Lanczos3).to_rgba8();
+            let x = (side - logo.width()) / 2;
+            let y = (side - logo.height()) / 2;
+            imageops::overlay(&mut image, &logo, x as i64, y as i64);
+        }
+    }
+    let mut buffer: Vec<u8> = Vec::new();
+    DynamicImage::ImageRgba8(image)
+        .write_to(&mut Cursor::new(&mut buffer), ImageFormat::Png)
+        .expect("PNG encoding to memory");
+    buffer
+}
+
+fn hex(rgb: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", rgb[0], rgb[1], rgb[2])
+}
+
+fn render_svg(matrix: &QrMatrix, options: &QrOptions, logo: Option<&DynamicImage>) -> Vec<u8> {
+    let width = matrix.width;
+    let mut path = String::new();
+    for y in 0..width {
+        for x in 0..width {
+            if matrix.is_dark(x, y) {
+                path.push_str(&format!("M{} {}h1v1h-1z", x, y));
+            }
+        }
+    }
+    let mut logo_image = String::new();
+    if options.logo {
+        if let Some(image) = logo {
+            let mut png = Vec::new();
+            if image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png).is_ok() {
+                let side = logo_side(width as f64);
+                let offset = (width as f64 - side) / 2.0;
+                logo_image = format!(
+                    "<image x=\"{o}\" y=\"{o}\" width=\"{s}\" height=\"{s}\" href=\"data:image/png;base64,{data}\"/>",
+                    o = offset,
+                    s = side,
+                    data = general_purpose::STANDARD.encode(&png)
+                );
+            }
+        }
+    }
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?><svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{size}\" height=\"{size}\" viewBox=\"0 0 {w} {w}\" shape-rendering=\"crispEdges\"><rect width=\"{w}\" height=\"{w}\" fill=\"{bg}\"/><path d=\"{path}\" fill=\"{fg}\"/>{logo}</svg>",
+        size = options.size,
+        w = width,
+        bg = hex(options.bg),
+        fg = hex(options.fg),
+        path = path,
+        logo = logo_image
+    )
+    .into_bytes()
+}
+
+fn render_pdf(matrix: &QrMatrix, options: &QrOptions, logo: Option<&DynamicImage>) -> Vec<u8> {
+    let side = options.size as f64;
+    let mut page = PdfPage::new(side, side);
+    matrix.draw_pdf(&mut page, 0.0, 0.0, side, options.fg, options.bg);
+    if options.logo {
+        if let Some(logo) = logo {
+            // Flatten transparency onto the background colour, PDF images here are plain RGB
+            let rgba = logo.to_rgba8();
+            let mut rgb = Vec::with_capacity((rgba.width() * rgba.height() * 3) as usize);
+            for pixel in rgba.pixels() {
+                let alpha = pixel[3] as u32;
+                for c in 0..3 {
+                    rgb.push(((pixel[c] as u32 * alpha + options.bg[c] as u32 * (255 - alpha)) / 255) as u8);
+                }
+            }
+            // Fit the longer side of the logo into the allowed square, keeping its aspect ratio
+            let max_side = logo_side(side);
+            let scale = max_side / rgba.width().max(rgba.height()) as f64;
+            let (w, h) = (rgba.width() as f64 * scale, rgba.height() as f64 * scale);
+            page.image(
+                PdfImage { width: rgba.width(), height: rgba.height(), rgb },
+                (side - w) / 2.0,
+                (side - h) / 2.0,
+                w,
+                h,
+            );
+        }
+    }
+    let mut doc = PdfDocument::new();
+    doc.add_page(page);
+    doc.to_bytes()
+}
+
+fn render_matrix(matrix: &QrMatrix, options: &QrOptions, logo: Option<&DynamicImage>) -> Vec<u8> {
+    match options.format {
+        QrFormat::Png => render_png(matrix, options, logo),
+        QrFormat::Svg => render_svg(matrix, options, logo),
+        QrFormat::Pdf => render_pdf(matrix, options, logo),
+    }
+}
+
+// With a public base URL (AppState::public_base_url), encode a URL to the public product page
+// (or a GS1 Digital Link when the link format is gs1 and the herb has a GTIN and lot); otherwise embed JSON
+pub fn qr_payload(herb: &Herb, base: Option<&str>, format: LinkFormat) -> String {
+    match base {
+        Some(base) => {
+            let link = if format == LinkFormat::Gs1 { gs1::digital_link(base, herb) } else { None };
+            link.unwrap_or_else(|| format!("{}/p/{}", base.trim_end_matches('/'), herb.id))
+        }
+        None => serde_json::to_string(herb).unwrap_or_default(),
+    }
+}
+
+// Preferred payload first, then shorter fallbacks: the /p/{id} URL when a public base URL is
+// configured, otherwise a JSON object carrying just the id (both understood by /scan)
+fn payload_candidates(herb: &Herb, base: Option<&str>, format: LinkFormat) -> Vec<String> {
+    let fallback = match base {
+        Some(base) => format!("{}/p/{}", base.trim_end_matches('/'), herb.id),
+        None => serde_json::json!({ "id": herb.id }).to_string(),
+    };
+    let preferred = qr_payload(herb, base, format);
+    if preferred == fallback {
+        vec![preferred]
+    } else {
+        vec![preferred, fallback]
+    }
+}
+
+// Encode a herb, falling back to a shorter payload when the preferred one exceeds QR capacity
+pub fn encode_herb(
+    herb: &Herb,
+    base: Option<&str>,
+    format: LinkFormat,
+    ec: EcLevel,
+    margin: u32,
+) -> Result<QrMatrix, QrError> {
+    let mut last_err = QrError::DataTooLong;
+    for payload in payload_candidates(herb, base, format) {
+        match QrMatrix::encode(&payload, ec, margin) {
+            Ok(matrix) => return Ok(matrix),
+            Err(QrError::DataTooLong) => last_err = QrError::DataTooLong,
+            Err(err) => return Err(err),
+        }
+    }
+    Err(last_err)
+}
+
+// Browsers may reuse a QR image briefly, then revalidate with the ETag
+pub const CACHE_CONTROL: &str = "public, max-age=300, must-revalidate";
+pub const DEFAULT_CACHE_CAPACITY: usize = 512;
+
+// Everything a rendered image depends on: herb id and revision, public base URL, render options,
+// link format and logo file. Its hash is the ETag, so conditional requests are answered before
+// anything is rendered, and a new revision or a different logo gives a new key.
+pub struct QrKey {
+    base: Option<String>,
+    options: QrOptions,
+    key: String,
+    pub etag: String,
+}
+
+impl QrKey {
+    // Whether an If-None-Match header value lists this key's ETag
+    pub fn matches(&self, if_none_match: &str) -> bool {
+        if_none_match.split(',').any(|tag| tag.trim() == self.etag || tag.trim() == "*")
+    }
+}
+
+// Renders QR codes with the configured link format and logo, keeping an LRU cache of the images
+// by QrKey; stale keys simply age out
+#[derive(Clone)]
+pub struct QrCache {
+    inner: Arc<Mutex<LruCache<String, Arc<Vec<u8>>>>>,
+    link_format: LinkFormat,
+    logo: Option<Arc<BrandLogo>>,
+}
+
+impl QrCache {
+    pub fn new(capacity: usize, settings: &QrSettings) -> Result<Self, String> {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        let logo = settings.logo_path.as_deref().map(BrandLogo::load).transpose()?;
+        Ok(Self {
+            inner: Arc::new(Mutex::new(LruCache::new(capacity))),
+            link_format: settings.link_format,
+            logo: logo.map(Arc::new),
+        })
+    }
+
+    pub fn has_logo(&self) -> bool {
+        self.logo.is_some()
+    }
+
+    pub fn key(&self, herb_id: &str, rev: &str, base: Option<&str>, options: &QrOptions) -> QrKey {
+        let logo = self.logo.as_ref().filter(|_| options.logo).map(|logo| logo.hash.as_str());
+        let key = format!("{}|{}|{:?}|{:?}|{:?}|logo={:?}", herb_id, rev, base, options, self.link_format, logo);
+        let hash = format!("{:x}", Sha256::digest(key.as_bytes()));
+        let etag = format!("\"{}\"", &hash[..32]);
+        QrKey { base: base.map(str::to_string), options: *options, key, etag }
+    }
+
+    // The image for `herb` as described by `key`. Misses are rendered on the blocking pool,
+    // since large codes with a logo take long enough to stall other requests.
+    pub async fn get_or_render(&self, herb: &Herb, key: &QrKey) -> Result<Arc<Vec<u8>>, QrError> {
+        if let Some(bytes) = self.inner.lock().unwrap_or_else(|e| e.into_inner()).get(&key.key) {
+            metrics::qr_cache_lookup(true);
+            return Ok(bytes.clone());
+        }
+        metrics::qr_cache_lookup(false);
+        let (herb, base, options) = (herb.clone(), key.base.clone(), key.options);
+        let (format, logo) = (self.link_format, self.logo.clone());
+        let rendered = tokio::task::spawn_blocking(move || {
+            let started = Instant::now();
+            let matrix = encode_herb(&herb, base.as_deref(), format, options.ec, options.margin)?;
+            let bytes = render_matrix(&matrix, &options, logo.as_ref().map(|logo| &logo.image));
+            metrics::qr_rendered(started.elapsed());
+            Ok(bytes)
+        })
+        .await
+        .expect("QR rendering panicked");
+        let bytes = Arc::new(rendered?);
+        self.inner.lock().unwrap_or_else(|e| e.into_inner()).put(key.key.clone(), bytes.clone());
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        extract::{Path, Query, State},
+        http::{header, HeaderMap, StatusCode},
+        response::IntoResponse,
+    };
+    use crate::handlers::{self, test_herb, AppState};
+
+    fn options(query: QrQuery) -> Result<QrOptions, String> {
+        QrOptions::from_query(&query, false)
+    }
+
+    fn cache(capacity: usize, link_format: LinkFormat) -> QrCache {
+        QrCache::new(capacity, &QrSettings { link_format, logo_path: None }).unwrap()
+    }
+
+    #[test]
+    fn size_and_margin_must_be_in_range() {
+        assert_eq!(options(QrQuery::default()), Ok(QrOptions::default()));
+        assert_eq!(options(QrQuery { size: Some(MIN_SIZE), ..QrQuery::default() }).map(|o| o.size), Ok(MIN_SIZE));
+        assert_eq!(options(QrQuery { size: Some(MAX_SIZE), ..QrQuery::default() }).map(|o| o.size), Ok(MAX_SIZE));
+        assert!(options(QrQuery { size: Some(MIN_SIZE - 1), ..QrQuery::default() }).is_err());
+        assert!(options(QrQuery { size: Some(MAX_SIZE + 1), ..QrQuery::default() }).is_err());
+        assert!(options(QrQuery { margin: Some(MAX_MARGIN + 1), ..QrQuery::default() }).is_err());
+        assert!(options(QrQuery { format: Some("gif".to_string()), ..QrQuery::default() }).is_err());
+        assert!(options(QrQuery { ec: Some("X".to_string()), ..QrQuery::default() }).is_err());
+    }
+
+    #[test]
+    fn colours_are_hex_triplets() {
+        assert_eq!(parse_color("0a6"), Ok([0x00, 0xaa, 0x66]));
+        assert_eq!(parse_color("#00AA66"), Ok([0x00, 0xaa, 0x66]));
+        for bad in ["", "#12", "1234", "zzzzzz", "#00aa6g", "ééé"] {
+            assert!(parse_color(bad).is_err(), "{:?}", bad);
+        }
+        let query = QrQuery { fg: Some("fff".to_string()), bg: Some("red".to_string()), ..QrQuery::default() };
+        assert!(options(query).is_err());
+    }
+
+    #[test]
+    fn logo_needs_high_error_correction_and_a_configured_logo() {
+        let query = QrQuery { logo: true, ec: Some("M".to_string()), ..QrQuery::default() };
+        assert_eq!(options(query), Err("logo requires ec=H".to_string()));
+        let query = QrQuery { logo: true, ec: Some("H".to_string()), ..QrQuery::default() };
+        assert_eq!(QrOptions::from_query(&query, true).map(|o| o.logo), Ok(true));
+        assert_eq!(options(query), Err("no brand logo is configured".to_string()));
+        assert!(QrCache::new(1, &QrSettings { logo_path: Some("/nonexistent.png".to_string()), ..QrSettings::default() }).is_err());
+    }
+
+    #[test]
+    fn payloads_fall_back_to_shorter_forms() {
+        let mut tulsi = test_herb("Tulsi", "Asha");
+        let base = Some("https://herbs.example.org/");
+        let product = format!("https://herbs.example.org/p/{}", tulsi.id);
+        assert_eq!(payload_candidates(&tulsi, base, LinkFormat::Product), [product.as_str()]);
+        // Digital Links need a GTIN and lot, and fall back to the product URL
+        assert_eq!(payload_candidates(&tulsi, base, LinkFormat::Gs1), [product.as_str()]);
+        tulsi.gtin = Some("08901234567890".to_string());
+        tulsi.lot = Some("L-1".to_string());
+        assert_eq!(payload_candidates(&tulsi, base, LinkFormat::Gs1), [
+            "https://herbs.example.org/01/08901234567890/10/L-1".to_string(),
+            product,
+        ]);
+        let candidates = payload_candidates(&tulsi, None, LinkFormat::Gs1);
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(serde_json::from_str::<Herb>(&candidates[0]).unwrap().id, tulsi.id);
+        assert_eq!(candidates[1], format!("{{\"id\":\"{}\"}}", tulsi.id));
+
+        // The full JSON does not fit at level H, the id alone does
+        let long = test_herb(&"Ashwagandha ".repeat(150), "Asha");
+        assert!(QrMatrix::encode(&qr_payload(&long, None, LinkFormat::Product), EcLevel::H, 0).is_err());
+        assert!(encode_herb(&long, None, LinkFormat::Product, EcLevel::H, 0).is_ok());
+    }
+
+    #[tokio::test]
+    async fn least_recently_used_images_are_evicted() {
+        let cache = cache(2, LinkFormat::Product);
+        let (a, b, c) = (test_herb("Tulsi", "Asha"), test_herb("Neem", "Asha"), test_herb("Brahmi", "Asha"));
+        let key = |herb: &Herb| cache.key(&herb.id, "1-a", None, &QrOptions::default());
+        let cached = |herb: &Herb| cache.inner.lock().unwrap().contains(&key(herb).key);
+        cache.get_or_render(&a, &key(&a)).await.unwrap();
+        cache.get_or_render(&b, &key(&b)).await.unwrap();
+        // Touch `a` so `b` is the least recently used
+        cache.get_or_render(&a, &key(&a)).await.unwrap();
+        cache.get_or_render(&c, &key(&c)).await.unwrap();
+        assert!(cached(&a) && cached(&c));
+        assert!(!cached(&b));
+    }
+
+    #[test]
+    fn etags_follow_everything_the_image_depends_on() {
+        let options = QrOptions::default();
+        let product = cache(1, LinkFormat::Product);
+        let etag = |rev: &str, base: Option<&str>, options: &QrOptions| product.key("herb_1", rev, base, options).etag;
+        let first = etag("1-a", None, &options);
+        assert_eq!(first, etag("1-a", None, &options));
+        assert_ne!(first, etag("2-b", None, &options));
+        assert_ne!(first, etag("1-a", Some("https://herbs.example.org"), &options));
+        assert_ne!(first, etag("1-a", None, &QrOptions { format: QrFormat::Svg, ..options }));
+        assert_ne!(first, cache(1, LinkFormat::Gs1).key("herb_1", "1-a", None, &options).etag);
+
+        // A different logo file changes codes drawn with the logo, and only those
+        let with_logo = QrOptions { ec: EcLevel::H, logo: true, ..options };
+        let logo_etag = |color: u8| {
+            let path = std::env::temp_dir().join(format!("qr-logo-{}-{}.png", std::process::id(), color));
+            RgbaImage::from_pixel(4, 4, Rgba([color, 0, 0, 255])).save(&path).unwrap();
+            let settings = QrSettings { logo_path: Some(path.to_string_lossy().into_owned()), ..QrSettings::default() };
+            let cache = QrCache::new(1, &settings).unwrap();
+            let _ = fs::remove_file(&path);
+            (cache.key("herb_1", "1-a", None, &options).etag, cache.key("herb_1", "1-a", None, &with_logo).etag)
+        };
+        let (red, dark) = (logo_etag(255), logo_etag(10));
+        assert_eq!(red.0, dark.0);
+        assert_ne!(red.1, dark.1);
+        let key = product.key("herb_1", "1-a", None, &options);
+        assert!(key.matches(&format!("\"other\", {}", first)));
+        assert!(key.matches("*"));
+        assert!(!key.matches("\"other\""));
+    }
+
+    #[tokio::test]
+    async fn matching_if_none_match_is_answered_without_rendering() {
+        let state = AppState::for_tests();
+        let tulsi = test_herb("Tulsi", "Asha");
+        state.herbs.put(&tulsi).await.unwrap();
+        let get = |headers: HeaderMap| {
+            handlers::get_qr_png(State(state.clone()), Path(tulsi.id.clone()), Query(QrQuery::default()), headers)
+        };
+
+        let response = get(HeaderMap::new()).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()[header::ETAG].clone();
+        state.qr_cache.inner.lock().unwrap().clear();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, etag.clone());
+        let response = get(headers).await.into_response();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::ETAG], etag);
+        assert!(state.qr_cache.inner.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,355 @@
+use async_trait::async_trait;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use crate::couchdb::{CouchDb, CouchDocStore, CouchHerbStore};
+use crate::docs::{DocStore, MemoryDocStore};
+use crate::handlers::Herb;
+use crate::sql_store::SqlStore;
+
+// Where herb documents are kept. Revisions follow CouchDB's _rev model: every write returns
+// nothing but changes the revision, and update/delete must name the current one or fail with
+// Conflict, which is what update_herb and the /api/v1 If-Match checks rely on.
+#[async_trait]
+pub trait HerbStore: Send + Sync {
+    // The herb and its current revision
+    async fn get(&self, id: &str) -> Result<(Herb, String), StoreError>;
+
+    // Current revision, or None if the herb does not exist
+    async fn rev(&self, id: &str) -> Result<Option<String>, StoreError>;
+
+    // Herbs with the given ids, in that order; missing ids are skipped
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Herb>, StoreError>;
+
+    // Which of the given ids exist
+    async fn existing_ids(&self, ids: &[String]) -> Result<Vec<String>, StoreError> {
+        Ok(self.get_many(ids).await?.into_iter().map(|h| h.id).collect())
+    }
+
+    // Creates a herb; Conflict if the id is taken
+    async fn put(&self, herb: &Herb) -> Result<(), StoreError>;
+
+    // Replaces a herb; Conflict unless `rev` is the current revision. Returns the new revision.
+    async fn update(&self, herb: &Herb, rev: &str) -> Result<String, StoreError>;
+
+    // Deletes a herb; Conflict unless `rev` is the current revision
+    async fn delete(&self, id: &str, rev: &str) -> Result<(), StoreError>;
+
+    // Every herb, in id order
+    async fn list(&self) -> Result<Vec<Herb>, StoreError>;
+
+    // One page of herbs in id order after `after`, plus the id to continue from
+    // (None once the last page has been read)
+    async fn list_page(&self, after: Option<&str>, limit: usize) -> Result<(Vec<Herb>, Option<String>), StoreError>;
+
+    // How many herbs there are
+    async fn count(&self) -> Result<u64, StoreError> {
+        Ok(self.list().await?.len() as u64)
+    }
+
+    async fn find(&self, query: &HerbQuery) -> Result<Vec<Herb>, StoreError>;
+
+    // Creates many herbs; one result per herb, in order. The outer error means nothing was written.
+    async fn put_many(&self, herbs: &[Herb]) -> Result<Vec<Result<(), StoreError>>, StoreError>;
+
+    // Removes every herb
+    async fn reset(&self) -> Result<(), StoreError>;
+
+    // Creates the database and indexes the store needs, if missing
+    async fn provision(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoreError {
+    NotFound,
+    // The id already exists, or the revision is not the current one
+    Conflict,
+    Backend(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::NotFound => write!(f, "not found"),
+            StoreError::Conflict => write!(f, "conflict: document update conflict"),
+            StoreError::Backend(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<reqwest::Error> for StoreError {
+    fn from(err: reqwest::Error) -> Self {
+        match err.status() {
+            Some(reqwest::StatusCode::NOT_FOUND) => StoreError::NotFound,
+            Some(reqwest::StatusCode::CONFLICT) => StoreError::Conflict,
+            _ => StoreError::Backend(err.to_string()),
+        }
+    }
+}
+
+// Exact-match filters; empty fields match everything
+#[derive(Default, Clone)]
+pub struct HerbQuery {
+    // Any of these farmers
+    pub farmers: Vec<String>,
+    pub gtin: Option<String>,
+    pub lot: Option<String>,
+}
+
+impl HerbQuery {
+    pub fn farmer(name: &str) -> Self {
+        Self { farmers: vec![name.to_string()], ..Self::default() }
+    }
+
+    pub fn matches(&self, herb: &Herb) -> bool {
+        (self.farmers.is_empty() || self.farmers.contains(&herb.farmer))
+            && self.gtin.as_ref().is_none_or(|g| herb.gtin.as_ref() == Some(g))
+            && self.lot.as_ref().is_none_or(|l| herb.lot.as_ref() == Some(l))
+    }
+}
+
+// Herbs held in process memory, for tests and local demos. Nothing survives a restart.
+#[derive(Default)]
+pub struct MemoryStore {
+    // id -> (herb, revision number)
+    herbs: Mutex<BTreeMap<String, (Herb, u64)>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // "<generation>-mem", shaped like a CouchDB _rev
+    fn rev(generation: u64) -> String {
+        format!("{}-mem", generation)
+    }
+
+    fn generation(rev: &str) -> Option<u64> {
+        rev.strip_suffix("-mem")?.parse().ok()
+    }
+
+    fn insert(herbs: &mut BTreeMap<String, (Herb, u64)>, herb: &Herb) -> Result<(), StoreError> {
+        if herbs.contains_key(&herb.id) {
+            return Err(StoreError::Conflict);
+        }
+        herbs.insert(herb.id.clone(), (herb.clone(), 1));
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl HerbStore for MemoryStore {
+    async fn get(&self, id: &str) -> Result<(Herb, String), StoreError> {
+        let herbs = self.herbs.lock().unwrap();
+        herbs
+            .get(id)
+            .map(|(herb, generation)| (herb.clone(), Self::rev(*generation)))
+            .ok_or(StoreError::NotFound)
+    }
+
+    async fn rev(&self, id: &str) -> Result<Option<String>, StoreError> {
+        Ok(self.herbs.lock().unwrap().get(id).map(|(_, generation)| Self::rev(*generation)))
+    }
+
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Herb>, StoreError> {
+        let herbs = self.herbs.lock().unwrap();
+        Ok(ids.iter().filter_map(|id| herbs.get(id)).map(|(herb, _)| herb.clone()).collect())
+    }
+
+    async fn put(&self, herb: &Herb) -> Result<(), StoreError> {
+        Self::insert(&mut self.herbs.lock().unwrap(), herb)
+    }
+
+    async fn update(&self, herb: &Herb, rev: &str) -> Result<String, StoreError> {
+        let mut herbs = self.herbs.lock().unwrap();
+        let (stored, generation) = herbs.get_mut(&herb.id).ok_or(StoreError::NotFound)?;
+        if Self::generation(rev) != Some(*generation) {
+            return Err(StoreError::Conflict);
+        }
+        *stored = herb.clone();
+        *generation += 1;
+        Ok(Self::rev(*generation))
+    }
+
+    async fn delete(&self, id: &str, rev: &str) -> Result<(), StoreError> {
+        let mut herbs = self.herbs.lock().unwrap();
+        let (_, generation) = herbs.get(id).ok_or(StoreError::NotFound)?;
+        if Self::generation(rev) != Some(*generation) {
+            return Err(StoreError::Conflict);
+        }
+        herbs.remove(id);
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<Herb>, StoreError> {
+        Ok(self.herbs.lock().unwrap().values().map(|(herb, _)| herb.clone()).collect())
+    }
+
+    async fn list_page(&self, after: Option<&str>, limit: usize) -> Result<(Vec<Herb>, Option<String>), StoreError> {
+        let herbs = self.herbs.lock().unwrap();
+        let page: Vec<Herb> = herbs
+            .values()
+            .map(|(herb, _)| herb)
+            .filter(|herb| after.is_none_or(|after| herb.id.as_str() > after))
+            .take(limit)
+            .cloned()
+            .collect();
+        let next = if page.len() < limit { None } else { page.last().map(|h| h.id.clone()) };
+        Ok((page, next))
+    }
+
+    async fn count(&self) -> Result<u64, StoreError> {
+        Ok(self.herbs.lock().unwrap().len() as u64)
+    }
+
+    async fn find(&self, query: &HerbQuery) -> Result<Vec<Herb>, StoreError> {
+        let herbs = self.herbs.lock().unwrap();
+        Ok(herbs.values().map(|(herb, _)| herb).filter(|h| query.matches(h)).cloned().collect())
+    }
+
+    async fn put_many(&self, herbs: &[Herb]) -> Result<Vec<Result<(), StoreError>>, StoreError> {
+        let mut stored = self.herbs.lock().unwrap();
+        Ok(herbs.iter().map(|herb| Self::insert(&mut stored, herb)).collect())
+    }
+
+    async fn reset(&self) -> Result<(), StoreError> {
+        self.herbs.lock().unwrap().clear();
+        Ok(())
+    }
+}
+
+// Herb and document stores of one tenant
+pub type Stores = (Arc<dyn HerbStore>, Arc<dyn DocStore>);
+
+// The HERB_STORE backend, from which the default tenant's stores and every other tenant's are
+// opened the same way
+pub enum Backend {
+    // Each tenant's stores live as long as the process
+    Memory(Mutex<HashMap<String, Stores>>),
+    // One database; rows carry the tenant
+    Sql(SqlStore),
+    // A herbs database and companion databases per tenant
+    Couchdb(CouchDb),
+}
+
+impl Backend {
+    // `tenant` is None for the default tenant; `db_name` names its CouchDB databases
+    pub fn open(&self, tenant: Option<&str>, db_name: &str) -> Stores {
+        match self {
+            Backend::Memory(opened) => opened
+                .lock()
+                .unwrap()
+                .entry(tenant.unwrap_or_default().to_string())
+                .or_insert_with(|| (Arc::new(MemoryStore::new()), Arc::new(MemoryDocStore::new())))
+                .clone(),
+            Backend::Sql(sql) => {
+                let scoped = Arc::new(sql.for_tenant(tenant.unwrap_or_default()));
+                (scoped.clone(), scoped)
+            }
+            Backend::Couchdb(couch) => {
+                (Arc::new(CouchHerbStore::new(couch.clone(), db_name)), Arc::new(CouchDocStore::new(couch.clone(), db_name)))
+            }
+        }
+    }
+
+    // Only set for HERB_STORE=couchdb
+    pub fn couch(&self) -> Option<CouchDb> {
+        match self {
+            Backend::Couchdb(couch) => Some(couch.clone()),
+            _ => None,
+        }
+    }
+}
+
+// Behaviour every HerbStore must share, run against each implementation's own tests
+#[cfg(test)]
+pub mod conformance {
+    use super::*;
+    use crate::handlers::test_herb;
+
+    pub async fn updates_need_the_current_revision(store: &dyn HerbStore) {
+        let mut tulsi = test_herb("Tulsi", "Asha");
+        store.put(&tulsi).await.unwrap();
+        assert_eq!(store.put(&tulsi).await, Err(StoreError::Conflict));
+
+        let (_, rev) = store.get(&tulsi.id).await.unwrap();
+        assert_eq!(store.rev(&tulsi.id).await, Ok(Some(rev.clone())));
+        tulsi.location = "Hassan".to_string();
+        let updated = store.update(&tulsi, &rev).await.unwrap();
+        assert_eq!(store.rev(&tulsi.id).await, Ok(Some(updated)));
+        // The revision read before the update is now stale
+        assert_eq!(store.update(&tulsi, &rev).await, Err(StoreError::Conflict));
+        assert_eq!(store.delete(&tulsi.id, &rev).await, Err(StoreError::Conflict));
+
+        let (stored, current) = store.get(&tulsi.id).await.unwrap();
+        assert_eq!(stored.location, "Hassan");
+        assert_ne!(current, rev);
+        store.delete(&tulsi.id, &current).await.unwrap();
+        assert_eq!(store.get(&tulsi.id).await.map(|_| ()), Err(StoreError::NotFound));
+        assert_eq!(store.rev(&tulsi.id).await, Ok(None));
+    }
+
+    pub async fn bulk_writes_report_each_herb(store: &dyn HerbStore) {
+        let tulsi = test_herb("Tulsi", "Asha");
+        store.put(&tulsi).await.unwrap();
+        let ashwagandha = test_herb("Ashwagandha", "Ravi");
+        let results = store.put_many(&[ashwagandha.clone(), tulsi.clone()]).await.unwrap();
+        assert_eq!(results, vec![Ok(()), Err(StoreError::Conflict)]);
+
+        let ids = vec![ashwagandha.id.clone(), "herb_missing".to_string(), tulsi.id.clone()];
+        let found: Vec<String> = store.get_many(&ids).await.unwrap().into_iter().map(|h| h.id).collect();
+        assert_eq!(found, vec![ashwagandha.id.clone(), tulsi.id.clone()]);
+        // Whatever order the backend reads them in
+        let reversed: Vec<String> = ids.iter().rev().cloned().collect();
+        let found: Vec<String> = store.get_many(&reversed).await.unwrap().into_iter().map(|h| h.id).collect();
+        assert_eq!(found, vec![tulsi.id.clone(), ashwagandha.id.clone()]);
+        assert_eq!(store.existing_ids(&ids[..2]).await.unwrap(), vec![ashwagandha.id.clone()]);
+        assert_eq!(store.find(&HerbQuery::farmer("Ravi")).await.unwrap().len(), 1);
+        let both = HerbQuery { farmers: vec!["Ravi".to_string(), "Asha".to_string()], ..HerbQuery::default() };
+        assert_eq!(store.find(&both).await.unwrap().len(), 2);
+    }
+
+    // Adds `count` herbs from the same farmer
+    pub async fn fill(store: &dyn HerbStore, count: usize) {
+        for n in 0..count {
+            store.put(&test_herb(&format!("Herb {}", n), "Asha")).await.unwrap();
+        }
+    }
+
+    pub async fn pages_continue_after_the_last_id(store: &dyn HerbStore) {
+        fill(store, 3).await;
+        let (first, next) = store.list_page(None, 2).await.unwrap();
+        assert_eq!(first.len(), 2);
+        let (rest, next) = store.list_page(next.as_deref(), 2).await.unwrap();
+        assert_eq!(rest.len(), 1);
+        assert_eq!(next, None);
+        assert_eq!(store.list().await.unwrap().len(), 3);
+        store.reset().await.unwrap();
+        assert!(store.list().await.unwrap().is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn updates_need_the_current_revision() {
+        conformance::updates_need_the_current_revision(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn bulk_writes_report_each_herb() {
+        conformance::bulk_writes_report_each_herb(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn pages_continue_after_the_last_id() {
+        conformance::pages_continue_after_the_last_id(&MemoryStore::new()).await;
+    }
+}
@@ -0,0 +1,335 @@
+use axum::{
+    extract::{Json, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use crate::gs1;
+use crate::handlers::{self, AddHerbRequest, AppState, Herb};
+use crate::store::StoreError;
+
+// Offline sync for the field app. Each change carries the server revision it was based on
+// (`base_rev`, like CouchDB's _rev): none for a herb created on the device, otherwise the
+// revision last pulled. A change based on a stale revision is not applied; it comes back as a
+// conflict together with the server's copy, so the device can merge and resend on that revision.
+const MAX_SYNC_CHANGES: usize = 500;
+// Longest client-generated id after the "herb_" prefix
+const MAX_CLIENT_ID_LEN: usize = 64;
+
+#[derive(Deserialize, ToSchema)]
+pub struct SyncRequest {
+    // Device or installation id, only used in logs
+    pub device: Option<String>,
+    pub changes: Vec<ClientChange>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ClientChange {
+    // Client-generated id, "herb_" followed by letters, digits, '-' or '_' (e.g. a UUID)
+    pub id: String,
+    // Server revision this change was made on; absent for herbs created offline
+    pub base_rev: Option<String>,
+    #[serde(default)]
+    pub deleted: bool,
+    // When the herb was registered on the device; becomes created_at
+    pub recorded_at: Option<DateTime<Utc>>,
+    // The herb's fields; required unless `deleted` is set
+    pub herb: Option<AddHerbRequest>,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncOutcome {
+    Created,
+    Updated,
+    Deleted,
+    // Already in that state on the server, e.g. a batch resent after a lost response
+    Unchanged,
+    // Not applied; `server` and `rev` hold the server's copy to merge with
+    Conflict,
+    // Not applied because the change is invalid
+    Rejected,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SyncResult {
+    pub id: String,
+    pub outcome: SyncOutcome,
+    // Current server revision; the base_rev for the next change to this herb
+    pub rev: Option<String>,
+    // Server copy of the herb, sent back with conflicts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server: Option<Herb>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SyncReport {
+    pub applied: usize,
+    pub conflicts: usize,
+    pub rejected: usize,
+    // One entry per change, in request order
+    pub results: Vec<SyncResult>,
+}
+
+impl SyncResult {
+    fn new(id: &str, outcome: SyncOutcome, rev: Option<String>) -> Self {
+        Self { id: id.to_string(), outcome, rev, server: None, reason: None }
+    }
+
+    fn rejected(id: &str, reason: impl Into<String>) -> Self {
+        Self { reason: Some(reason.into()), ..Self::new(id, SyncOutcome::Rejected, None) }
+    }
+
+    fn conflict(id: &str, server: Option<(Herb, String)>, reason: &str) -> Self {
+        let (server, rev) = server.map_or((None, None), |(herb, rev)| (Some(herb), Some(rev)));
+        Self { server, reason: Some(reason.to_string()), ..Self::new(id, SyncOutcome::Conflict, rev) }
+    }
+}
+
+fn validate_client_id(id: &str) -> Result<(), String> {
+    let suffix = id.strip_prefix("herb_").ok_or("id must start with \"herb_\"")?;
+    if suffix.is_empty() || suffix.len() > MAX_CLIENT_ID_LEN {
+        return Err(format!("id must have 1 to {} characters after \"herb_\"", MAX_CLIENT_ID_LEN));
+    }
+    if !suffix.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err("id may only contain letters, digits, '-' and '_'".to_string());
+    }
+    Ok(())
+}
+
+// Copies the field-app editable fields onto a herb; status and recall details stay server-side
+fn apply_fields(herb: &mut Herb, fields: AddHerbRequest) {
+    herb.name = fields.name;
+    herb.farmer = fields.farmer;
+    herb.location = fields.location;
+    herb.gtin = fields.gtin.and_then(|g| gs1::normalize_gtin(&g).ok());
+    herb.lot = fields.lot;
+    herb.units = fields.units;
+    herb.latitude = fields.latitude;
+    herb.longitude = fields.longitude;
+}
+
+fn same_herb(a: &Herb, b: &Herb) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+// The server copy for a conflict report; None if it is gone
+async fn server_copy(state: &AppState, id: &str) -> Result<Option<(Herb, String)>, StoreError> {
+    match state.herbs.get(id).await {
+        Ok(found) => Ok(Some(found)),
+        Err(StoreError::NotFound) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+async fn create(
+    state: &AppState,
+    id: &str,
+    recorded_at: Option<DateTime<Utc>>,
+    fields: AddHerbRequest,
+) -> Result<SyncResult, StoreError> {
+    let now = Utc::now();
+    let mut herb = handlers::new_herb(fields);
+    herb.id = id.to_string();
+    // A device clock ahead of the server must not date herbs in the future
+    herb.created_at = recorded_at.filter(|t| *t <= now).unwrap_or(now);
+    match state.herbs.put(&herb).await {
+        Ok(()) => {
+            state.live.herb_added(&herb);
+            Ok(SyncResult::new(id, SyncOutcome::Created, state.herbs.rev(id).await?))
+        }
+        Err(StoreError::Conflict) => match server_copy(state, id).await? {
+            Some((stored, rev)) => {
+                // Same fields under the same id: an earlier upload of this change went through
+                let mut resent = stored.clone();
+                apply_fields(&mut resent, editable_fields(&herb));
+                if same_herb(&stored, &resent) {
+                    Ok(SyncResult::new(id, SyncOutcome::Unchanged, Some(rev)))
+                } else {
+                    Ok(SyncResult::conflict(id, Some((stored, rev)), "a different herb already has this id"))
+                }
+            }
+            None => Ok(SyncResult::conflict(id, None, "herb was deleted on the server")),
+        },
+        Err(e) => Err(e),
+    }
+}
+
+// The editable fields of a herb, as the request that would set them
+fn editable_fields(herb: &Herb) -> AddHerbRequest {
+    AddHerbRequest {
+        name: herb.name.clone(),
+        farmer: herb.farmer.clone(),
+        location: herb.location.clone(),
+        gtin: herb.gtin.clone(),
+        lot: herb.lot.clone(),
+        units: herb.units,
+        latitude: herb.latitude,
+        longitude: herb.longitude,
+    }
+}
+
+async fn update(state: &AppState, id: &str, base_rev: &str, fields: AddHerbRequest) -> Result<SyncResult, StoreError> {
+    let Some((stored, rev)) = server_copy(state, id).await? else {
+        return Ok(SyncResult::conflict(id, None, "herb was deleted on the server"));
+    };
+    let mut herb = stored.clone();
+    apply_fields(&mut herb, fields);
+    // Checked before the revision so a batch resent after a lost response is not a conflict
+    if same_herb(&stored, &herb) {
+        return Ok(SyncResult::new(id, SyncOutcome::Unchanged, Some(rev)));
+    }
+    if rev != base_rev {
+        return Ok(SyncResult::conflict(id, Some((stored, rev)), "herb changed on the server since base_rev"));
+    }
+    match state.herbs.update(&herb, &rev).await {
+        Ok(rev) => Ok(SyncResult::new(id, SyncOutcome::Updated, Some(rev))),
+        // Someone else wrote between our read and write
+        Err(StoreError::Conflict) | Err(StoreError::NotFound) => {
+            Ok(SyncResult::conflict(id, server_copy(state, id).await?, "herb changed on the server since base_rev"))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+async fn delete(state: &AppState, id: &str, base_rev: &str) -> Result<SyncResult, StoreError> {
+    match handlers::remove_herb(state, id, base_rev).await {
+        Ok(()) => Ok(SyncResult::new(id, SyncOutcome::Deleted, None)),
+        Err(StoreError::NotFound) => Ok(SyncResult::new(id, SyncOutcome::Unchanged, None)),
+        Err(StoreError::Conflict) => {
+            Ok(SyncResult::conflict(id, server_copy(state, id).await?, "herb changed on the server since base_rev"))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+async fn apply(state: &AppState, change: ClientChange) -> Result<SyncResult, StoreError> {
+    let ClientChange { id, base_rev, deleted, recorded_at, herb } = change;
+    if let Err(msg) = validate_client_id(&id) {
+        return Ok(SyncResult::rejected(&id, msg));
+    }
+    if deleted {
+        return match base_rev {
+            Some(base_rev) => delete(state, &id, &base_rev).await,
+            None => Ok(SyncResult::rejected(&id, "base_rev is required to delete")),
+        };
+    }
+    let Some(fields) = herb else {
+        return Ok(SyncResult::rejected(&id, "herb is required unless deleted is set"));
+    };
+    if let Err(msg) = fields.validate() {
+        return Ok(SyncResult::rejected(&id, msg));
+    }
+    match base_rev {
+        None => create(state, &id, recorded_at, fields).await,
+        Some(base_rev) => update(state, &id, &base_rev, fields).await,
+    }
+}
+
+// POST /api/v1/sync
+#[utoipa::path(
+    post,
+    path = "/api/v1/sync",
+    tag = "sync",
+    request_body = SyncRequest,
+    responses(
+        (status = 200, description = "Per-change outcome; conflicts carry the server copy", body = SyncReport),
+        (status = 413, description = "Too many changes in one batch"),
+        (status = 500, description = "Storage failed; changes before the failure may have been applied"),
+    ),
+)]
+pub async fn sync_herbs(
+    State(state): State<AppState>,
+    Json(request): Json<SyncRequest>,
+) -> impl IntoResponse {
+    if request.changes.len() > MAX_SYNC_CHANGES {
+        return (StatusCode::PAYLOAD_TOO_LARGE, format!("too many changes (max {})", MAX_SYNC_CHANGES)).into_response();
+    }
+    let device = request.device.unwrap_or_else(|| "unknown device".to_string());
+    let mut results = Vec::with_capacity(request.changes.len());
+    for change in request.changes {
+        let id = change.id.clone();
+        match apply(&state, change).await {
+            Ok(result) => results.push(result),
+            Err(e) => {
+                eprintln!("sync from {} failed for id {}: {}", device, id, e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to sync herbs").into_response();
+            }
+        }
+    }
+    let count = |outcome: SyncOutcome| results.iter().filter(|r| r.outcome == outcome).count();
+    let report = SyncReport {
+        applied: count(SyncOutcome::Created) + count(SyncOutcome::Updated) + count(SyncOutcome::Deleted),
+        conflicts: count(SyncOutcome::Conflict),
+        rejected: count(SyncOutcome::Rejected),
+        results,
+    };
+    (StatusCode::OK, Json(report)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(body: serde_json::Value) -> ClientChange {
+        serde_json::from_value(body).unwrap()
+    }
+
+    fn tulsi(id: &str, location: &str, base_rev: Option<&str>) -> ClientChange {
+        change(serde_json::json!({
+            "id": id,
+            "base_rev": base_rev,
+            "recorded_at": "2026-10-01T06:30:00Z",
+            "herb": { "name": "Tulsi", "farmer": "Asha", "location": location },
+        }))
+    }
+
+    #[tokio::test]
+    async fn offline_creates_keep_the_client_id_and_resends_are_unchanged() {
+        let state = AppState::for_tests();
+        let created = apply(&state, tulsi("herb_device1-0001", "Mysuru", None)).await.unwrap();
+        assert_eq!(created.outcome, SyncOutcome::Created);
+        let (herb, rev) = state.herbs.get("herb_device1-0001").await.unwrap();
+        assert_eq!(created.rev, Some(rev));
+        assert_eq!(herb.created_at.to_rfc3339(), "2026-10-01T06:30:00+00:00");
+
+        let resent = apply(&state, tulsi("herb_device1-0001", "Mysuru", None)).await.unwrap();
+        assert_eq!(resent.outcome, SyncOutcome::Unchanged);
+        let clash = apply(&state, tulsi("herb_device1-0001", "Hassan", None)).await.unwrap();
+        assert_eq!(clash.outcome, SyncOutcome::Conflict);
+    }
+
+    #[tokio::test]
+    async fn stale_base_rev_reports_the_server_copy() {
+        let state = AppState::for_tests();
+        let first = apply(&state, tulsi("herb_a", "Mysuru", None)).await.unwrap().rev.unwrap();
+        let second = apply(&state, tulsi("herb_a", "Hassan", Some(&first))).await.unwrap();
+        assert_eq!(second.outcome, SyncOutcome::Updated);
+
+        // Another device edits from the first revision
+        let stale = apply(&state, tulsi("herb_a", "Mandya", Some(&first))).await.unwrap();
+        assert_eq!(stale.outcome, SyncOutcome::Conflict);
+        assert_eq!(stale.rev, second.rev);
+        assert_eq!(stale.server.unwrap().location, "Hassan");
+
+        let delete = change(serde_json::json!({ "id": "herb_a", "base_rev": first, "deleted": true }));
+        assert_eq!(apply(&state, delete).await.unwrap().outcome, SyncOutcome::Conflict);
+        let delete = change(serde_json::json!({ "id": "herb_a", "base_rev": second.rev, "deleted": true }));
+        assert_eq!(apply(&state, delete).await.unwrap().outcome, SyncOutcome::Deleted);
+    }
+
+    #[tokio::test]
+    async fn invalid_changes_are_rejected() {
+        let state = AppState::for_tests();
+        let bad_id = apply(&state, tulsi("device1/0001", "Mysuru", None)).await.unwrap();
+        assert_eq!(bad_id.outcome, SyncOutcome::Rejected);
+        let no_fields = apply(&state, change(serde_json::json!({ "id": "herb_b" }))).await.unwrap();
+        assert_eq!(no_fields.outcome, SyncOutcome::Rejected);
+        let blind_delete = apply(&state, change(serde_json::json!({ "id": "herb_b", "deleted": true }))).await.unwrap();
+        assert_eq!(blind_delete.outcome, SyncOutcome::Rejected);
+    }
+}
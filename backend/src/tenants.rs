@@ -0,0 +1,713 @@
+use axum::{
+    extract::{Extension, FromRequestParts, Json, Path, Request},
+    http::{header, request::Parts, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Router,
+};
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::{Arc, RwLock};
+use tower::ServiceExt;
+use utoipa::ToSchema;
+use crate::docs::{Collection, DocQuery};
+use crate::graphql::LiveFeed;
+use crate::handlers::AppState;
+use crate::store::{Backend, StoreError};
+
+// Organisations (cooperatives, brands) served by one deployment, each with its own herbs and
+// companion documents in the HERB_STORE backend (separate CouchDB databases, or rows tagged with
+// the tenant in SQL). The registry is kept by the default tenant's store. A request is for a
+// tenant when its path starts with /t/{tenant}, its X-Api-Key header holds one of the tenant's
+// keys, or its host is {tenant}.{TENANT_DOMAIN}; other requests go to the default tenant. A
+// tenant's consumer-facing pages are open, everything else needs one of its API keys.
+pub const API_KEY_HEADER: &str = "x-api-key";
+const PATH_PREFIX: &str = "/t/";
+const MAX_TENANT_ID_LEN: usize = 40;
+// Routes scanned or opened by consumers, reachable without an API key (as are the attachment
+// downloads the product page links to, see is_public)
+const PUBLIC_PREFIXES: [&str; 4] = ["/p/", "/qr/", "/01/", "/static/"];
+const PUBLIC_PATHS: [&str; 6] = ["/", "/health", "/health/live", "/health/ready", "/scan", "/scan-page"];
+
+// How a tenant's public product pages look
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, ToSchema)]
+pub struct Branding {
+    // Shown in the page header and title
+    pub display_name: Option<String>,
+    // http(s) URL of a logo image
+    pub logo_url: Option<String>,
+    // "#rgb" or "#rrggbb", used for accents
+    pub accent_color: Option<String>,
+}
+
+// No branding, for the default tenant
+pub static NO_BRANDING: Branding = Branding { display_name: None, logo_url: None, accent_color: None };
+
+impl Branding {
+    fn validate(&self) -> Result<(), String> {
+        if self.display_name.as_ref().is_some_and(|n| n.trim().is_empty() || n.len() > 100) {
+            return Err("branding.display_name must be 1-100 characters".to_string());
+        }
+        if let Some(logo) = &self.logo_url {
+            if !matches!(url::Url::parse(logo).map(|u| u.scheme().to_string()).as_deref(), Ok("http" | "https")) {
+                return Err("branding.logo_url must be an http(s) URL".to_string());
+            }
+        }
+        if let Some(color) = &self.accent_color {
+            let hex = color.strip_prefix('#').unwrap_or_default();
+            if !matches!(hex.len(), 3 | 6) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err("branding.accent_color must look like #0a6 or #00aa66".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+// Registry document for one tenant
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Tenant {
+    pub id: String,
+    pub name: String,
+    pub db_name: String,
+    // SHA-256 (hex) of each API key; keys are shown once, when issued
+    #[serde(default)]
+    pub api_key_hashes: Vec<String>,
+    // Where the tenant's public pages live (e.g. https://brand.example.org);
+    // defaults to {PUBLIC_BASE_URL}/t/{id}
+    pub public_base_url: Option<String>,
+    #[serde(default)]
+    pub branding: Branding,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateTenantRequest {
+    // Lower-case letters, digits and '-', starting with a letter
+    pub id: String,
+    pub name: String,
+    pub public_base_url: Option<String>,
+    pub branding: Option<Branding>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateTenantRequest {
+    pub name: String,
+    pub public_base_url: Option<String>,
+    #[serde(default)]
+    pub branding: Branding,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TenantInfo {
+    pub id: String,
+    pub name: String,
+    pub db_name: String,
+    pub public_base_url: Option<String>,
+    pub branding: Branding,
+    pub api_keys: usize,
+    pub created_at: DateTime<Utc>,
+}
+
+// A newly issued API key; it is not stored and cannot be shown again
+#[derive(Serialize, ToSchema)]
+pub struct IssuedKey {
+    pub tenant: TenantInfo,
+    pub api_key: String,
+}
+
+impl From<&Tenant> for TenantInfo {
+    fn from(tenant: &Tenant) -> Self {
+        Self {
+            id: tenant.id.clone(),
+            name: tenant.name.clone(),
+            db_name: tenant.db_name.clone(),
+            public_base_url: tenant.public_base_url.clone(),
+            branding: tenant.branding.clone(),
+            api_keys: tenant.api_key_hashes.len(),
+            created_at: tenant.created_at,
+        }
+    }
+}
+
+fn validate_id(id: &str) -> Result<(), String> {
+    let valid = id.len() >= 2
+        && id.len() <= MAX_TENANT_ID_LEN
+        && id.starts_with(|c: char| c.is_ascii_lowercase())
+        && id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("id must be 2-{} lower-case letters, digits or '-', starting with a letter", MAX_TENANT_ID_LEN))
+    }
+}
+
+fn validate_settings(name: &str, public_base_url: Option<&str>, branding: &Branding) -> Result<(), String> {
+    if name.trim().is_empty() || name.len() > 100 {
+        return Err("name must be 1-100 characters".to_string());
+    }
+    if let Some(base) = public_base_url {
+        if !matches!(url::Url::parse(base).map(|u| u.scheme().to_string()).as_deref(), Ok("http" | "https")) {
+            return Err("public_base_url must be an http(s) URL".to_string());
+        }
+    }
+    branding.validate()
+}
+
+fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+// 32 random bytes, hex encoded, prefixed with the tenant id for recognisability
+fn new_api_key(tenant_id: &str) -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("{}_{}", tenant_id, hex::encode(bytes))
+}
+
+// "/t/{tenant}/rest" -> (tenant, "/rest")
+fn split_tenant_path(path: &str) -> Option<(&str, String)> {
+    let rest = path.strip_prefix(PATH_PREFIX)?;
+    let (tenant, rest) = rest.split_once('/').map_or((rest, ""), |(t, r)| (t, r));
+    (!tenant.is_empty()).then(|| (tenant, format!("/{}", rest)))
+}
+
+// "{tenant}.{domain}[:port]" -> tenant
+fn subdomain<'a>(host: &'a str, domain: &str) -> Option<&'a str> {
+    let host = host.split(':').next().unwrap_or(host);
+    let tenant = host.strip_suffix(domain)?.strip_suffix('.')?;
+    (!tenant.is_empty() && !tenant.contains('.')).then_some(tenant)
+}
+
+// Where the tenant's routes are mounted for this request: "/t/{tenant}", or empty when the tenant
+// came from the host or API key. Pages prefix their root-absolute links with it.
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct BasePath(pub String);
+
+impl<S: Send + Sync> FromRequestParts<S> for BasePath {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts.extensions.get::<BasePath>().cloned().unwrap_or_default())
+    }
+}
+
+fn is_public(method: &Method, path: &str) -> bool {
+    PUBLIC_PATHS.contains(&path)
+        || PUBLIC_PREFIXES.iter().any(|prefix| path.starts_with(prefix))
+        || (method == Method::GET && is_attachment_download(path))
+}
+
+// GET /herbs/{id}/attachments/{name}[/thumbnail], linked from the product page
+fn is_attachment_download(path: &str) -> bool {
+    let segments: Vec<&str> = path.split('/').skip(1).collect();
+    matches!(
+        segments.as_slice(),
+        ["herbs", id, "attachments", name] | ["herbs", id, "attachments", name, "thumbnail"]
+            if !id.is_empty() && !name.is_empty()
+    )
+}
+
+// Resolves tenants and keeps a router per tenant that has been used
+pub struct Tenancy {
+    base: AppState,
+    backend: Arc<Backend>,
+    build: fn(AppState) -> Router,
+    default_app: Router,
+    // Host suffix for {tenant}.{domain} resolution (TENANT_DOMAIN)
+    domain: Option<String>,
+    routers: RwLock<HashMap<String, (Arc<Tenant>, Router)>>,
+    // API key hash -> tenant id
+    keys: RwLock<HashMap<String, String>>,
+}
+
+impl Tenancy {
+    pub fn new(base: AppState, backend: Arc<Backend>, build: fn(AppState) -> Router, domain: Option<String>) -> Self {
+        Self {
+            default_app: build(base.clone()),
+            base,
+            backend,
+            build,
+            domain,
+            routers: RwLock::default(),
+            keys: RwLock::default(),
+        }
+    }
+
+    // Database of the registry on CouchDB (Collection::Tenants of the default store)
+    pub fn registry_db(&self) -> String {
+        format!("{}_tenants", self.base.db_name)
+    }
+
+    fn db_name(&self, tenant_id: &str) -> String {
+        format!("{}_t_{}", self.base.db_name, tenant_id)
+    }
+
+    // The default state with the tenant's databases, public URL and branding
+    fn state_for(&self, tenant: &Tenant) -> AppState {
+        let public_base_url = tenant.public_base_url.clone().or_else(|| {
+            let base = self.base.public_base_url.as_deref()?;
+            Some(format!("{}{}{}", base.trim_end_matches('/'), PATH_PREFIX, tenant.id))
+        });
+        let (herbs, docs) = self.backend.open(Some(&tenant.id), &tenant.db_name);
+        AppState {
+            couch: self.base.couch.clone(),
+            db_name: tenant.db_name.clone(),
+            qr_cache: self.base.qr_cache.clone(),
+            herbs,
+            docs,
+            live: LiveFeed::new(),
+            public_base_url,
+            settings: self.base.settings.clone(),
+            readiness: self.base.readiness.clone(),
+            workers: self.base.workers.clone(),
+            branding: Arc::new(tenant.branding.clone()),
+        }
+    }
+
+    // Drops cached routers and keys so the next request reloads the tenant
+    fn forget(&self, tenant_id: &str) {
+        self.routers.write().unwrap().remove(tenant_id);
+        self.keys.write().unwrap().retain(|_, id| id != tenant_id);
+    }
+
+    async fn load(&self, tenant_id: &str) -> Result<Option<Tenant>, StoreError> {
+        match self.base.docs.get_doc::<Tenant>(Collection::Tenants, tenant_id).await {
+            Ok((tenant, _)) => Ok(Some(tenant)),
+            Err(StoreError::NotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn router(&self, tenant_id: &str) -> Result<Option<(Arc<Tenant>, Router)>, StoreError> {
+        if let Some(found) = self.routers.read().unwrap().get(tenant_id) {
+            return Ok(Some(found.clone()));
+        }
+        let Some(tenant) = self.load(tenant_id).await? else {
+            return Ok(None);
+        };
+        let entry = (Arc::new(tenant.clone()), (self.build)(self.state_for(&tenant)));
+        self.routers.write().unwrap().insert(tenant.id.clone(), entry.clone());
+        Ok(Some(entry))
+    }
+
+    async fn tenant_for_key(&self, key: &str) -> Result<Option<String>, StoreError> {
+        let hash = hash_key(key);
+        if let Some(id) = self.keys.read().unwrap().get(&hash) {
+            return Ok(Some(id.clone()));
+        }
+        // Deployments have a handful of tenants, so the registry is simply read through
+        let tenants = self.base.docs.find_docs::<Tenant>(Collection::Tenants, &DocQuery::default()).await?;
+        let id = tenants.into_iter().find(|t| t.api_key_hashes.contains(&hash)).map(|t| t.id);
+        if let Some(id) = &id {
+            self.keys.write().unwrap().insert(hash, id.clone());
+        }
+        Ok(id)
+    }
+}
+
+fn without_tenant_prefix(uri: &Uri, path: &str) -> Uri {
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path.to_string(),
+    };
+    path_and_query.parse().unwrap_or_else(|_| uri.clone())
+}
+
+// Fallback of the top-level router: picks the tenant and hands the request to its routes
+pub async fn dispatch(Extension(tenancy): Extension<Arc<Tenancy>>, mut request: Request) -> Response {
+    let uri = request.uri().clone();
+    let from_path = split_tenant_path(uri.path()).map(|(id, rest)| (id.to_string(), rest));
+    let from_host = request
+        .headers()
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .zip(tenancy.domain.as_deref())
+        .and_then(|(host, domain)| subdomain(host, domain))
+        .map(str::to_string);
+    let named = from_path.as_ref().map(|(id, _)| id.clone()).or(from_host);
+    let key = request.headers().get(API_KEY_HEADER).and_then(|v| v.to_str().ok()).map(str::to_string);
+
+    let key_tenant = match key {
+        Some(key) => match tenancy.tenant_for_key(&key).await {
+            Ok(Some(id)) => Some(id),
+            Ok(None) => return (StatusCode::UNAUTHORIZED, "Unknown API key").into_response(),
+            Err(err) => {
+                eprintln!("tenant lookup by API key failed: {}", err);
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to resolve tenant").into_response();
+            }
+        },
+        None => None,
+    };
+    let (tenant_id, authorized) = match (named, key_tenant) {
+        (Some(named), Some(keyed)) if named != keyed => {
+            return (StatusCode::FORBIDDEN, "API key belongs to another tenant").into_response();
+        }
+        (Some(named), keyed) => (named, keyed.is_some()),
+        (None, Some(keyed)) => (keyed, true),
+        (None, None) => return tenancy.default_app.clone().oneshot(request).await.into_response(),
+    };
+
+    let (_, router) = match tenancy.router(&tenant_id).await {
+        Ok(Some(found)) => found,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Unknown tenant").into_response(),
+        Err(err) => {
+            eprintln!("loading tenant {} failed: {}", tenant_id, err);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to resolve tenant").into_response();
+        }
+    };
+    let base = if from_path.is_some() { format!("{}{}", PATH_PREFIX, tenant_id) } else { String::new() };
+    let path = from_path.map(|(_, rest)| rest).unwrap_or_else(|| uri.path().to_string());
+    if !authorized && !is_public(request.method(), &path) {
+        return (StatusCode::UNAUTHORIZED, "API key required for this tenant").into_response();
+    }
+    *request.uri_mut() = without_tenant_prefix(&uri, &path);
+    request.extensions_mut().insert(BasePath(base));
+    router.oneshot(request).await.into_response()
+}
+
+// Handlers
+
+async fn save(tenancy: &Tenancy, tenant: &Tenant, created: bool) -> Result<(), StoreError> {
+    if created {
+        tenancy.base.docs.put_doc(Collection::Tenants, &tenant.id, tenant).await?;
+    } else {
+        tenancy.base.docs.upsert_doc(Collection::Tenants, &tenant.id, tenant).await?;
+    }
+    tenancy.forget(&tenant.id);
+    Ok(())
+}
+
+// GET /admin/tenants
+#[utoipa::path(
+    get,
+    path = "/admin/tenants",
+    tag = "admin",
+    responses(
+        (status = 200, description = "All tenants", body = [TenantInfo]),
+        (status = 401, description = "Admin token missing or wrong"),
+    ),
+)]
+pub async fn list_tenants(Extension(tenancy): Extension<Arc<Tenancy>>) -> impl IntoResponse {
+    match tenancy.base.docs.find_docs::<Tenant>(Collection::Tenants, &DocQuery::default()).await {
+        Ok(tenants) => (StatusCode::OK, Json(tenants.iter().map(TenantInfo::from).collect::<Vec<_>>())).into_response(),
+        Err(err) => {
+            eprintln!("list_tenants failed: {}", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to list tenants").into_response()
+        }
+    }
+}
+
+// POST /admin/tenants
+#[utoipa::path(
+    post,
+    path = "/admin/tenants",
+    tag = "admin",
+    request_body = CreateTenantRequest,
+    responses(
+        (status = 201, description = "Tenant and its databases created; the API key is only shown now", body = IssuedKey),
+        (status = 400, description = "Validation failed"),
+        (status = 401, description = "Admin token missing or wrong"),
+        (status = 409, description = "Tenant id taken"),
+    ),
+)]
+pub async fn create_tenant(
+    Extension(tenancy): Extension<Arc<Tenancy>>,
+    Json(payload): Json<CreateTenantRequest>,
+) -> impl IntoResponse {
+    let branding = payload.branding.unwrap_or_default();
+    let valid = validate_id(&payload.id)
+        .and_then(|_| validate_settings(&payload.name, payload.public_base_url.as_deref(), &branding));
+    if let Err(msg) = valid {
+        return (StatusCode::BAD_REQUEST, msg).into_response();
+    }
+    let api_key = new_api_key(&payload.id);
+    let tenant = Tenant {
+        db_name: tenancy.db_name(&payload.id),
+        id: payload.id,
+        name: payload.name.trim().to_string(),
+        api_key_hashes: vec![hash_key(&api_key)],
+        public_base_url: payload.public_base_url,
+        branding,
+        created_at: Utc::now(),
+    };
+
+    // Databases first, so a registered tenant always has somewhere to write; existing data (from
+    // a removed tenant with the same id) is reused
+    let tenant_state = tenancy.state_for(&tenant);
+    let provisioned = match tenant_state.herbs.provision().await {
+        Ok(_) => tenant_state.docs.provision().await,
+        Err(e) => Err(e),
+    };
+    if let Err(err) = provisioned {
+        eprintln!("create_tenant could not create databases for {}: {}", tenant.id, err);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create tenant databases").into_response();
+    }
+    match save(&tenancy, &tenant, true).await {
+        Ok(_) => (StatusCode::CREATED, Json(IssuedKey { tenant: TenantInfo::from(&tenant), api_key })).into_response(),
+        Err(StoreError::Conflict) => (StatusCode::CONFLICT, "Tenant already exists").into_response(),
+        Err(err) => {
+            eprintln!("create_tenant failed for {}: {}", tenant.id, err);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create tenant").into_response()
+        }
+    }
+}
+
+// Loads a tenant for an admin handler, or the error response to return
+async fn existing(tenancy: &Tenancy, id: &str) -> Result<Tenant, Response> {
+    match tenancy.load(id).await {
+        Ok(Some(tenant)) => Ok(tenant),
+        Ok(None) => Err((StatusCode::NOT_FOUND, "Tenant not found").into_response()),
+        Err(err) => {
+            eprintln!("loading tenant {} failed: {}", id, err);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch tenant").into_response())
+        }
+    }
+}
+
+// GET /admin/tenants/{id}
+#[utoipa::path(
+    get,
+    path = "/admin/tenants/{id}",
+    tag = "admin",
+    params(("id" = String, Path, description = "Tenant id")),
+    responses(
+        (status = 200, description = "Tenant settings", body = TenantInfo),
+        (status = 401, description = "Admin token missing or wrong"),
+        (status = 404, description = "No such tenant"),
+    ),
+)]
+pub async fn get_tenant(
+    Extension(tenancy): Extension<Arc<Tenancy>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match existing(&tenancy, &id).await {
+        Ok(tenant) => (StatusCode::OK, Json(TenantInfo::from(&tenant))).into_response(),
+        Err(response) => response,
+    }
+}
+
+// PUT /admin/tenants/{id}
+#[utoipa::path(
+    put,
+    path = "/admin/tenants/{id}",
+    tag = "admin",
+    params(("id" = String, Path, description = "Tenant id")),
+    request_body = UpdateTenantRequest,
+    responses(
+        (status = 200, description = "Name, public URL and branding replaced", body = TenantInfo),
+        (status = 400, description = "Validation failed"),
+        (status = 401, description = "Admin token missing or wrong"),
+        (status = 404, description = "No such tenant"),
+    ),
+)]
+pub async fn update_tenant(
+    Extension(tenancy): Extension<Arc<Tenancy>>,
+    Path(id): Path<String>,
+    Json(payload): Json<UpdateTenantRequest>,
+) -> impl IntoResponse {
+    if let Err(msg) = validate_settings(&payload.name, payload.public_base_url.as_deref(), &payload.branding) {
+        return (StatusCode::BAD_REQUEST, msg).into_response();
+    }
+    let mut tenant = match existing(&tenancy, &id).await {
+        Ok(tenant) => tenant,
+        Err(response) => return response,
+    };
+    tenant.name = payload.name.trim().to_string();
+    tenant.public_base_url = payload.public_base_url;
+    tenant.branding = payload.branding;
+    match save(&tenancy, &tenant, false).await {
+        Ok(_) => (StatusCode::OK, Json(TenantInfo::from(&tenant))).into_response(),
+        Err(err) => {
+            eprintln!("update_tenant failed for {}: {}", id, err);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update tenant").into_response()
+        }
+    }
+}
+
+// DELETE /admin/tenants/{id}
+#[utoipa::path(
+    delete,
+    path = "/admin/tenants/{id}",
+    tag = "admin",
+    params(("id" = String, Path, description = "Tenant id")),
+    responses(
+        (status = 204, description = "Tenant removed; its databases are kept"),
+        (status = 401, description = "Admin token missing or wrong"),
+        (status = 404, description = "No such tenant"),
+    ),
+)]
+pub async fn delete_tenant(
+    Extension(tenancy): Extension<Arc<Tenancy>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    if let Err(response) = existing(&tenancy, &id).await {
+        return response;
+    }
+    match tenancy.base.docs.delete(Collection::Tenants, &id).await {
+        Ok(_) => {
+            tenancy.forget(&id);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(err) => {
+            eprintln!("delete_tenant failed for {}: {}", id, err);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete tenant").into_response()
+        }
+    }
+}
+
+// POST /admin/tenants/{id}/keys
+#[utoipa::path(
+    post,
+    path = "/admin/tenants/{id}/keys",
+    tag = "admin",
+    params(("id" = String, Path, description = "Tenant id")),
+    responses(
+        (status = 201, description = "Additional API key; only shown now", body = IssuedKey),
+        (status = 401, description = "Admin token missing or wrong"),
+        (status = 404, description = "No such tenant"),
+    ),
+)]
+pub async fn issue_key(
+    Extension(tenancy): Extension<Arc<Tenancy>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let mut tenant = match existing(&tenancy, &id).await {
+        Ok(tenant) => tenant,
+        Err(response) => return response,
+    };
+    let api_key = new_api_key(&tenant.id);
+    tenant.api_key_hashes.push(hash_key(&api_key));
+    match save(&tenancy, &tenant, false).await {
+        Ok(_) => (StatusCode::CREATED, Json(IssuedKey { tenant: TenantInfo::from(&tenant), api_key })).into_response(),
+        Err(err) => {
+            eprintln!("issue_key failed for {}: {}", id, err);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to issue API key").into_response()
+        }
+    }
+}
+
+// DELETE /admin/tenants/{id}/keys
+#[utoipa::path(
+    delete,
+    path = "/admin/tenants/{id}/keys",
+    tag = "admin",
+    params(("id" = String, Path, description = "Tenant id")),
+    responses(
+        (status = 200, description = "Every API key revoked", body = TenantInfo),
+        (status = 401, description = "Admin token missing or wrong"),
+        (status = 404, description = "No such tenant"),
+    ),
+)]
+pub async fn revoke_keys(
+    Extension(tenancy): Extension<Arc<Tenancy>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let mut tenant = match existing(&tenancy, &id).await {
+        Ok(tenant) => tenant,
+        Err(response) => return response,
+    };
+    tenant.api_key_hashes.clear();
+    match save(&tenancy, &tenant, false).await {
+        Ok(_) => (StatusCode::OK, Json(TenantInfo::from(&tenant))).into_response(),
+        Err(err) => {
+            eprintln!("revoke_keys failed for {}: {}", id, err);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to revoke API keys").into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tenants_resolve_from_path_and_subdomain() {
+        assert_eq!(split_tenant_path("/t/acme/p/herb_1"), Some(("acme", "/p/herb_1".to_string())));
+        assert_eq!(split_tenant_path("/t/acme"), Some(("acme", "/".to_string())));
+        assert_eq!(split_tenant_path("/p/herb_1"), None);
+        assert_eq!(subdomain("acme.herbs.example:8080", "herbs.example"), Some("acme"));
+        assert_eq!(subdomain("herbs.example", "herbs.example"), None);
+        assert_eq!(subdomain("a.b.herbs.example", "herbs.example"), None);
+        assert_eq!(subdomain("evilherbs.example", "herbs.example"), None);
+    }
+
+    // Routes that report how many herbs the tenant's store holds and where it is mounted
+    fn counting_app(state: AppState) -> Router {
+        Router::new()
+            .route(
+                "/count",
+                axum::routing::get(move || async move { state.herbs.count().await.unwrap().to_string() }),
+            )
+            .route("/base", axum::routing::get(|base: BasePath| async move { base.0 }))
+    }
+
+    async fn count(tenancy: &Arc<Tenancy>, path: &str, key: Option<&str>) -> String {
+        let mut request = Request::get(path);
+        if let Some(key) = key {
+            request = request.header(API_KEY_HEADER, key);
+        }
+        let response = dispatch(Extension(tenancy.clone()), request.body(axum::body::Body::empty()).unwrap()).await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn tenants_get_their_own_store_from_the_same_backend() {
+        let backend = Arc::new(Backend::Memory(Default::default()));
+        let (herbs, docs) = backend.open(None, "herbs_test");
+        let base = AppState { herbs, docs, ..AppState::for_tests() };
+        let tenancy = Arc::new(Tenancy::new(base, backend.clone(), counting_app, None));
+
+        let request = CreateTenantRequest { id: "acme".to_string(), name: "Acme".to_string(), public_base_url: None, branding: None };
+        let response = create_tenant(Extension(tenancy.clone()), Json(request)).await.into_response();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let key = serde_json::from_slice::<serde_json::Value>(&body).unwrap()["api_key"].as_str().unwrap().to_string();
+
+        crate::store::conformance::fill(&*backend.open(Some("acme"), "herbs_test_t_acme").0, 2).await;
+        assert_eq!(count(&tenancy, "/t/acme/count", Some(&key)).await, "2");
+        assert_eq!(count(&tenancy, "/count", None).await, "0");
+        assert_eq!(count(&tenancy, "/count", Some(&key)).await, "2");
+        assert_eq!(count(&tenancy, "/t/acme/base", Some(&key)).await, "/t/acme");
+        assert_eq!(count(&tenancy, "/base", Some(&key)).await, "");
+
+        // Reloading the tenant keeps its data
+        let update = UpdateTenantRequest { name: "Acme Co-op".to_string(), public_base_url: None, branding: Branding::default() };
+        let response = update_tenant(Extension(tenancy.clone()), Path("acme".to_string()), Json(update)).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(count(&tenancy, "/t/acme/count", Some(&key)).await, "2");
+    }
+
+    #[test]
+    fn only_consumer_routes_are_public() {
+        let get = &Method::GET;
+        assert!(is_public(get, "/p/herb_1/html"));
+        assert!(is_public(get, "/01/08901234567890/10/L1"));
+        assert!(is_public(&Method::POST, "/scan"));
+        assert!(is_public(get, "/herbs/herb_1/attachments/coa.pdf"));
+        assert!(is_public(get, "/herbs/herb_1/attachments/leaf.jpg/thumbnail"));
+        assert!(!is_public(&Method::DELETE, "/herbs/herb_1/attachments/coa.pdf"));
+        assert!(!is_public(get, "/herbs/herb_1/attachments"));
+        assert!(!is_public(get, "/herbs/herb_1/lab-results"));
+        assert!(!is_public(get, "/api/v1/herbs"));
+        assert!(!is_public(get, "/listHerbs"));
+        assert!(!is_public(get, "/export/herbs.csv"));
+    }
+
+    #[test]
+    fn branding_is_validated() {
+        let mut branding = Branding { accent_color: Some("#0a6".to_string()), ..Branding::default() };
+        assert!(branding.validate().is_ok());
+        branding.accent_color = Some("red;}</style>".to_string());
+        assert!(branding.validate().is_err());
+        branding.accent_color = None;
+        branding.logo_url = Some("javascript:alert(1)".to_string());
+        assert!(branding.validate().is_err());
+        assert!(validate_id("acme-coop").is_ok());
+        assert!(validate_id("Acme").is_err());
+        assert!(validate_id("9acme").is_err());
+    }
+}
@@ -0,0 +1,22 @@
+// Posts the pasted QR text to the button's data-action (the tenant's /scan) and shows the JSON response
+const area = document.getElementById('scan');
+const out = document.getElementById('out');
+const btn = document.getElementById('btn');
+btn.addEventListener('click', async () => {
+  out.textContent = 'Loading...';
+  try {
+    const res = await fetch(btn.dataset.action, {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ data: area.value }),
+    });
+    const text = await res.text();
+    try {
+      out.textContent = JSON.stringify(JSON.parse(text), null, 2);
+    } catch (e) {
+      out.textContent = text;
+    }
+  } catch (e) {
+    out.textContent = String(e);
+  }
+});